@@ -0,0 +1,21 @@
+#!/bin/sh
+# Throughput and p99 of the DB path, with hey (https://github.com/rakyll/hey)
+#
+# Start the server with the same DB_* settings and PRIVATE_BIND for every build you compare, e.g.
+#   $ git checkout <before> && PRIVATE_BIND=127.0.0.1:8081 cargo run --release &
+#   $ bench/perf.sh > before.txt
+# /perf/query does not exist before the async pool executor; compare /perf/non_blocking there.
+# Older builds serve them on the public listener: URL=http://127.0.0.1:8080 bench/perf.sh
+set -eu
+
+# the private listener, with PRIVATE_SHARED_SECRET when the server has one
+URL=${URL:-http://127.0.0.1:8081/private}
+SECRET=${PRIVATE_SHARED_SECRET:-}
+CONCURRENCY=${CONCURRENCY:-50}
+DURATION=${DURATION:-30s}
+
+for path in /perf/query /perf/non_blocking; do
+  echo "== $path (concurrency: $CONCURRENCY, duration: $DURATION)"
+  hey -z "$DURATION" -c "$CONCURRENCY" -H "X-Internal-Secret: $SECRET" "$URL$path" \
+    | grep -E "Requests/sec|99% in|\[[0-9]{3}\]"
+done
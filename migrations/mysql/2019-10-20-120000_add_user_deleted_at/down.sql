@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table
+  user_records drop deleted_at;
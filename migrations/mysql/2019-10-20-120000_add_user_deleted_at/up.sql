@@ -0,0 +1,5 @@
+-- Your SQL goes here
+alter table
+  user_records
+add
+  deleted_at datetime;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+drop table user_email_verifications;
+
+alter table
+  user_records drop email;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+alter table
+  user_records
+add
+  email varchar(255) unique;
+
+create table user_email_verifications (
+  user_id varchar(64) primary key,
+  token_hash varchar(64) not null unique,
+  expires_at datetime not null,
+  foreign key (user_id) references user_records (id) on delete cascade on update restrict
+);
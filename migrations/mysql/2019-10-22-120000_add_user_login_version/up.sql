@@ -0,0 +1,5 @@
+-- Your SQL goes here
+alter table
+  user_login_records
+add
+  version int not null default 0;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- written on the primary and read on the replicas to measure the replication lag
+create table replica_heartbeats (
+  id int primary key,
+  beat_at datetime(6) not null
+);
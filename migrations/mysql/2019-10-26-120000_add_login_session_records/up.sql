@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- one row per login attempt of an existing user; the successful ones are the sessions
+create table login_session_records (
+  id varchar(26) primary key,
+  user_id varchar(64) not null,
+  created_at datetime(6) not null,
+  ip varchar(45),
+  user_agent text,
+  succeeded boolean not null,
+  revoked_at datetime(6),
+  index login_session_records_user_id (user_id, id),
+  foreign key (user_id) references user_records (id) on delete cascade on update restrict
+);
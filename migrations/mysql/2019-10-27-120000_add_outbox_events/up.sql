@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- written in the transaction of the change, then delivered by the dispatcher
+create table outbox_events (
+  id varchar(26) primary key,
+  occurred_at datetime(6) not null,
+  event_type varchar(32) not null,
+  payload text not null,
+  -- pending, dispatched or dead (gave up after EVENT_MAX_ATTEMPTS)
+  status varchar(16) not null,
+  attempts int not null default 0,
+  next_attempt_at datetime(6) not null,
+  last_error text,
+  index outbox_events_status (status, next_attempt_at)
+);
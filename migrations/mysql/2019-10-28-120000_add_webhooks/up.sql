@@ -0,0 +1,31 @@
+-- Your SQL goes here
+-- registered by admins; event_types is a comma separated list of DomainEvent types
+create table webhook_endpoints (
+  id varchar(26) primary key,
+  url text not null,
+  secret varchar(255) not null,
+  event_types text not null,
+  created_at datetime(6) not null
+);
+
+-- one row per endpoint and event, also the delivery log
+create table webhook_deliveries (
+  id varchar(26) primary key,
+  endpoint_id varchar(26) not null,
+  event_id varchar(26) not null,
+  event_type varchar(32) not null,
+  -- the signed body, as sent on every attempt
+  payload text not null,
+  -- pending, delivered or dead (gave up after EVENT_MAX_ATTEMPTS)
+  status varchar(16) not null,
+  attempts int not null default 0,
+  next_attempt_at datetime(6) not null,
+  last_status_code int,
+  last_error text,
+  created_at datetime(6) not null,
+  updated_at datetime(6) not null,
+  unique index webhook_deliveries_event (endpoint_id, event_id),
+  index webhook_deliveries_endpoint_id (endpoint_id, id),
+  index webhook_deliveries_status (status, next_attempt_at),
+  foreign key (endpoint_id) references webhook_endpoints (id) on delete cascade on update restrict
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+drop table replica_heartbeats;
+drop table user_email_verifications;
+drop table user_login_records;
+drop table user_records;
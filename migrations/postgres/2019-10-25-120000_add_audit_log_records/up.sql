@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- append only; no foreign keys, so that the entries outlive purged users
+create table audit_log_records (
+  id varchar(26) primary key,
+  occurred_at timestamp(6) not null,
+  actor_id varchar(64),
+  target_id varchar(64),
+  action varchar(32) not null,
+  outcome varchar(16) not null,
+  ip varchar(45),
+  user_agent text,
+  detail text
+);
+
+create index audit_log_records_actor_id on audit_log_records (actor_id, id);
+create index audit_log_records_target_id on audit_log_records (target_id, id);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- one row per login attempt of an existing user; the successful ones are the sessions
+create table login_session_records (
+  id varchar(26) primary key,
+  user_id varchar(64) not null references user_records (id) on delete cascade,
+  created_at timestamp(6) not null,
+  ip varchar(45),
+  user_agent text,
+  succeeded boolean not null,
+  revoked_at timestamp(6)
+);
+
+create index login_session_records_user_id on login_session_records (user_id, id);
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+-- the same tables as migrations/mysql, in one step
+create table user_records (
+  id varchar(36) primary key,
+  name varchar(64) not null unique,
+  display_name varchar(128) not null,
+  role varchar(32),
+  deleted_at timestamp,
+  email varchar(255) unique
+);
+
+create table user_login_records (
+  user_id varchar(64) primary key references user_records (id) on delete cascade,
+  password_hash varchar(60) not null,
+  status varchar(32),
+  version int not null default 0
+);
+
+create table user_email_verifications (
+  user_id varchar(64) primary key references user_records (id) on delete cascade,
+  token_hash varchar(64) not null unique,
+  expires_at timestamp not null
+);
+
+-- written on the primary and read on the replicas to measure the replication lag
+create table replica_heartbeats (
+  id int primary key,
+  beat_at timestamp not null
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- written in the transaction of the change, then delivered by the dispatcher
+create table outbox_events (
+  id varchar(26) primary key,
+  occurred_at timestamp not null,
+  event_type varchar(32) not null,
+  payload text not null,
+  -- pending, dispatched or dead (gave up after EVENT_MAX_ATTEMPTS)
+  status varchar(16) not null,
+  attempts int not null default 0,
+  next_attempt_at timestamp not null,
+  last_error text
+);
+
+create index outbox_events_status on outbox_events (status, next_attempt_at);
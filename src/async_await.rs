@@ -47,3 +47,19 @@ where
     Box::new(fut1)
   }
 }
+
+pub fn wrap4<F, U1, U2, U3, U4, T, Ok, Error>(
+  f: F,
+) -> impl Fn(U1, U2, U3, U4) -> Box<dyn futures01::Future<Item = Ok, Error = Error>> + Clone + 'static
+where
+  Ok: 'static,
+  Error: 'static,
+  F: Fn(U1, U2, U3, U4) -> T + Clone + 'static,
+  T: Future3<Output = Result<Ok, Error>> + 'static,
+{
+  move |u1, u2, u3, u4| {
+    // Turn a future3 Future into futures1 Future
+    let fut1 = f(u1, u2, u3, u4).boxed_local().compat();
+    Box::new(fut1)
+  }
+}
@@ -0,0 +1,262 @@
+use std::env;
+use std::time::Duration;
+
+fn env_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or_else(|_| default.to_owned())
+}
+
+fn env_flag(key: &str) -> bool {
+    match env::var(key).as_ref().map(|s| s.as_str()) {
+        Ok("1") | Ok("true") => true,
+        _ => false,
+    }
+}
+
+fn env_required(key: &str) -> Result<String, failure::Error> {
+    env::var(key).map_err(|err| format_err!("{}: {}", key, err))
+}
+
+// None when the variable is not set; an error names the variable
+fn env_parse_opt<T>(key: &str) -> Result<Option<T>, failure::Error>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(key) {
+        Ok(s) => s
+            .parse()
+            .map(Some)
+            .map_err(|err| format_err!("{}: {}", key, err)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_parse<T>(key: &str, default: T) -> Result<T, failure::Error>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    Ok(env_parse_opt(key)?.unwrap_or(default))
+}
+
+fn env_secs(key: &str) -> Result<Option<Duration>, failure::Error> {
+    Ok(env_parse_opt(key)?.map(Duration::from_secs))
+}
+
+#[derive(Clone)]
+pub enum MailTransport {
+    // directory to write .eml files into
+    File(String),
+    // host:port of an SMTP server
+    Smtp(String),
+}
+
+#[derive(Clone)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+}
+
+#[derive(Clone)]
+pub struct SignupConfig {
+    pub enabled: bool,
+    // the token is appended as `?token=...`
+    pub verification_url: String,
+    pub token_ttl_hours: i64,
+}
+
+// One pool is shared by every DBExecutor of the process
+#[derive(Clone)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    // None keeps max_size connections open
+    pub min_idle: Option<u32>,
+    // how long to wait for a connection, both when connecting and on checkout
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    // run on every new connection, e.g. "SET time_zone = '+00:00', sql_mode = 'TRADITIONAL'"
+    pub init_sql: Option<String>,
+    // how many times to retry connecting at startup, with exponential backoff
+    pub startup_retries: u32,
+    // DBExecutor threads; more than max_size only makes them wait for the pool
+    pub executors: usize,
+    // queries waiting for an executor thread, beyond which they fail with Busy
+    pub queue_size: usize,
+    // also the server side limit of the session (of SELECTs only on MySQL), see
+    // infra::statement_timeout
+    pub query_timeout: Duration,
+    // queries taking longer are logged with the "slow_query" target; None disables the log
+    pub slow_query_threshold: Option<Duration>,
+}
+
+// Reads (DBConnector::first/load) go to the replica while its lag is within max_lag
+#[derive(Clone)]
+pub struct ReplicaConfig {
+    pub database_url: String,
+    pub max_lag: Duration,
+    pub check_interval: Duration,
+}
+
+// Caches user lookups in front of the repositories, see serviceclient::cache
+// Writes through this process invalidate it; writes by other instances show up after the TTL
+#[derive(Clone)]
+pub struct CacheConfig {
+    pub ttl: Duration,
+    // per cache (users by id, logins by user name)
+    pub max_entries: usize,
+}
+
+// Where the repositories keep their data
+#[derive(Clone, PartialEq, Debug)]
+pub enum RepositoryKind {
+    Sql,
+    // in process and lost on restart; the database is only used by /health and /private/perf
+    Memory,
+}
+
+// Listener for the /private routes, which must not be reachable from outside
+#[derive(Clone)]
+pub struct PrivateConfig {
+    pub bind: String,
+    // required in the X-Internal-Secret header when set
+    pub shared_secret: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum EventSinkConfig {
+    // one JSON event per line
+    Stdout,
+    File(String),
+    // POSTed to the URL, signed with the secret
+    Webhook { url: String, secret: String },
+    // the endpoints registered through /admin/webhooks
+    Webhooks,
+}
+
+// The dispatcher of the outbox, see service::EventDispatcher
+#[derive(Clone)]
+pub struct EventsConfig {
+    // events stay in the outbox when None
+    pub sink: Option<EventSinkConfig>,
+    // also the pace of the webhook deliveries
+    pub poll_interval: Duration,
+    // failed deliveries after which an event (or a webhook delivery) is dead
+    pub max_attempts: u32,
+    // hosts the webhooks may use although they are internal, e.g. a receiver in the same network
+    pub webhook_allowed_hosts: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub bind: String,
+    // the /private routes are not served at all when this is None
+    pub private: Option<PrivateConfig>,
+    pub repository: RepositoryKind,
+    // None disables the cache
+    pub user_cache: Option<CacheConfig>,
+    pub database_url: String,
+    pub pool: PoolConfig,
+    // uses the same pool settings as the primary
+    pub replica: Option<ReplicaConfig>,
+    pub private_key_file: String,
+    // redis://[:password@]host:port[/db]; state is kept in the process when None
+    pub kv_store_url: Option<String>,
+    pub mail: MailConfig,
+    pub signup: SignupConfig,
+    pub events: EventsConfig,
+}
+
+impl Config {
+    // fails on a missing or malformed variable, naming it
+    pub fn from_env() -> Result<Config, failure::Error> {
+        Ok(Config {
+            bind: env_or("BIND", "127.0.0.1:8080"),
+            private: env::var("PRIVATE_BIND").ok().map(|bind| PrivateConfig {
+                bind: bind,
+                shared_secret: env::var("PRIVATE_SHARED_SECRET").ok(),
+            }),
+            repository: match env_or("REPOSITORY", "sql").as_str() {
+                "sql" => RepositoryKind::Sql,
+                "memory" => RepositoryKind::Memory,
+                other => bail!("REPOSITORY must be sql or memory, not {}", other),
+            },
+            user_cache: match env_secs("USER_CACHE_TTL_SECS")? {
+                Some(ttl) => Some(CacheConfig {
+                    ttl: ttl,
+                    max_entries: env_parse("USER_CACHE_SIZE", 10000)?,
+                }),
+                None => None,
+            },
+            database_url: env_required("DATABASE_URL")?,
+            pool: PoolConfig {
+                max_size: env_parse("DB_POOL_MAX_SIZE", 10)?,
+                min_idle: env_parse_opt("DB_POOL_MIN_IDLE")?,
+                connection_timeout: env_secs("DB_CONNECTION_TIMEOUT_SECS")?
+                    .unwrap_or(Duration::from_secs(5)),
+                idle_timeout: env_secs("DB_IDLE_TIMEOUT_SECS")?,
+                max_lifetime: env_secs("DB_MAX_LIFETIME_SECS")?,
+                init_sql: env::var("DB_INIT_SQL").ok(),
+                startup_retries: env_parse("DB_STARTUP_RETRIES", 5)?,
+                executors: env_parse("DB_EXECUTORS", 5)?,
+                queue_size: env_parse("DB_QUEUE_SIZE", 100)?,
+                query_timeout: env_secs("DB_QUERY_TIMEOUT_SECS")?
+                    .unwrap_or(Duration::from_secs(30)),
+                slow_query_threshold: env_parse_opt("DB_SLOW_QUERY_MS")?.map(Duration::from_millis),
+            },
+            replica: match env::var("DATABASE_REPLICA_URL") {
+                Ok(url) => Some(ReplicaConfig {
+                    database_url: url,
+                    max_lag: env_secs("DB_REPLICA_MAX_LAG_SECS")?.unwrap_or(Duration::from_secs(5)),
+                    check_interval: env_secs("DB_REPLICA_CHECK_INTERVAL_SECS")?
+                        .unwrap_or(Duration::from_secs(5)),
+                }),
+                Err(_) => None,
+            },
+            private_key_file: env_required("JWT_PRIVATE_KEY_FILE")?,
+            kv_store_url: env::var("KV_STORE_URL").ok(),
+            mail: MailConfig {
+                transport: match env::var("SMTP_ADDR") {
+                    Ok(addr) => MailTransport::Smtp(addr),
+                    Err(_) => MailTransport::File(env_or("MAIL_DIR", "mails")),
+                },
+                from: env_or("MAIL_FROM", "noreply@example.com"),
+            },
+            signup: SignupConfig {
+                enabled: env_flag("SIGNUP_ENABLED"),
+                verification_url: env_or(
+                    "SIGNUP_VERIFICATION_URL",
+                    "http://localhost:8080/auth/verify",
+                ),
+                token_ttl_hours: env_parse("SIGNUP_TOKEN_TTL_HOURS", 24)?,
+            },
+            events: EventsConfig {
+                sink: match env_or("EVENT_SINK", "webhooks")
+                    .splitn(2, ':')
+                    .collect::<Vec<_>>()
+                    .as_slice()
+                {
+                    ["none"] => None,
+                    ["webhooks"] => Some(EventSinkConfig::Webhooks),
+                    ["stdout"] => Some(EventSinkConfig::Stdout),
+                    ["file", path] => Some(EventSinkConfig::File(path.to_string())),
+                    ["webhook", url] => Some(EventSinkConfig::Webhook {
+                        url: url.to_string(),
+                        secret: env_required("EVENT_WEBHOOK_SECRET")?,
+                    }),
+                    other => bail!(
+                        "EVENT_SINK must be webhooks, stdout, file:PATH, webhook:URL or none, not {}",
+                        other.join(":")
+                    ),
+                },
+                poll_interval: Duration::from_millis(env_parse("EVENT_POLL_INTERVAL_MS", 1000)?),
+                max_attempts: env_parse("EVENT_MAX_ATTEMPTS", 10)?,
+                webhook_allowed_hosts: env_or("WEBHOOK_ALLOWED_HOSTS", "")
+                    .split(',')
+                    .map(|host| host.trim().to_owned())
+                    .filter(|host| !host.is_empty())
+                    .collect(),
+            },
+        })
+    }
+}
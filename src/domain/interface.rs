@@ -1,12 +1,24 @@
 use crate::domain::model;
 use crate::infra::DBConnectorError;
 use async_trait::async_trait;
+use std::time::Duration;
 
 #[async_trait]
 pub trait IUserRepository {
-    async fn get_by_id(&self, user_id: String) -> Result<model::User, DBConnectorError>;
-    async fn list(&self) -> Result<Vec<model::User>, DBConnectorError>;
+    async fn get_by_id(&self, user_id: String) -> Result<Option<model::User>, DBConnectorError>;
+    // same as get_by_id, but never served by a lagging replica
+    async fn get_by_id_from_primary(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::User>, DBConnectorError>;
+    async fn list(&self, query: model::UserListQuery)
+        -> Result<Vec<model::User>, DBConnectorError>;
+    async fn count(&self, filter: model::UserFilter) -> Result<i64, DBConnectorError>;
     async fn save(&self, user: model::User) -> Result<(), DBConnectorError>;
+    async fn update(&self, user: model::User) -> Result<(), DBConnectorError>;
+    // soft delete; returns false if there was no such user
+    async fn delete(&self, user_id: String) -> Result<bool, DBConnectorError>;
+    async fn purge(&self, user_id: String) -> Result<bool, DBConnectorError>;
 }
 
 #[async_trait]
@@ -14,9 +26,197 @@ pub trait IUserLoginRepository {
     async fn get_by_user_name(
         &self,
         user_name: String,
-    ) -> Result<(model::Login, model::User), DBConnectorError>;
-    async fn get_by_user_id(&self, user_id: String) -> Result<model::Login, DBConnectorError>;
+    ) -> Result<Option<(model::Login, model::User)>, DBConnectorError>;
+    async fn get_by_user_id(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::Login>, DBConnectorError>;
+    // insert, or overwrite the existing login regardless of its version
     async fn save(&self, login: model::Login) -> Result<(), DBConnectorError>;
+    // fails with UniqueViolation if the user already has a login
+    async fn create(&self, login: model::Login) -> Result<(), DBConnectorError>;
+    // fails with VersionConflict unless `login.version` is the stored one
+    async fn update(&self, login: model::Login) -> Result<(), DBConnectorError>;
+    // creates the user, its login and the verification in one transaction, with the events
+    async fn create_pending(
+        &self,
+        user: model::User,
+        login: model::Login,
+        verification: model::EmailVerification,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError>;
+    async fn get_verification(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<model::EmailVerification>, DBConnectorError>;
+    // enables the login and consumes the verification, with the events
+    async fn complete_verification(
+        &self,
+        user_id: String,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError>;
+    // replaces the token of the pending login of the user with this email
+    // returns false if there is no login waiting for its verification
+    async fn renew_verification(
+        &self,
+        email: String,
+        token_hash: String,
+        expires_at: chrono::NaiveDateTime,
+    ) -> Result<bool, DBConnectorError>;
+}
+
+// Append only
+#[async_trait]
+pub trait IAuditLog {
+    async fn append(&self, entry: model::AuditEntry) -> Result<(), DBConnectorError>;
+    async fn list(
+        &self,
+        query: model::AuditQuery,
+    ) -> Result<Vec<model::AuditEntry>, DBConnectorError>;
+}
+
+#[async_trait]
+pub trait ILoginSessionRepository {
+    // fails with ForeignKeyViolation if there is no such user
+    async fn create(&self, session: model::LoginSession) -> Result<(), DBConnectorError>;
+    // always from the primary, since it decides whether a revoked token is still accepted
+    async fn get_by_id(
+        &self,
+        session_id: String,
+    ) -> Result<Option<model::LoginSession>, DBConnectorError>;
+    async fn list(
+        &self,
+        query: model::LoginSessionQuery,
+    ) -> Result<Vec<model::LoginSession>, DBConnectorError>;
+    // returns false unless it was an active session
+    async fn revoke(&self, session_id: String) -> Result<bool, DBConnectorError>;
+}
+
+// The delivery side of the outbox; events are appended through IOutboxRepositoryTx
+#[async_trait]
+pub trait IOutboxRepository {
+    // pending events whose next attempt is due at `now`, oldest first
+    async fn list_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError>;
+    // same, but moves their next attempt to `lease_until`, so that another caller gets only
+    // the events it claimed itself, until then
+    async fn claim_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError>;
+    async fn mark_dispatched(&self, event_id: String) -> Result<(), DBConnectorError>;
+    // counts a failed attempt; the event is dead when next_attempt_at is None
+    async fn mark_failed(
+        &self,
+        event_id: String,
+        error: String,
+        next_attempt_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<(), DBConnectorError>;
+}
+
+// Where the dispatcher delivers the events, e.g. a webhook
+#[async_trait]
+pub trait IEventSink {
+    async fn deliver(&self, event: model::OutboxEvent) -> Result<(), failure::Error>;
+}
+
+#[async_trait]
+pub trait IWebhookRepository {
+    async fn create_endpoint(
+        &self,
+        endpoint: model::WebhookEndpoint,
+    ) -> Result<(), DBConnectorError>;
+    // oldest first
+    async fn list_endpoints(&self) -> Result<Vec<model::WebhookEndpoint>, DBConnectorError>;
+    async fn get_endpoint(
+        &self,
+        endpoint_id: String,
+    ) -> Result<Option<model::WebhookEndpoint>, DBConnectorError>;
+    // also deletes its deliveries; returns false if there was no such endpoint
+    async fn delete_endpoint(&self, endpoint_id: String) -> Result<bool, DBConnectorError>;
+    // skips the deliveries of an event which the endpoint already has, so an event is only
+    // queued once however often it is dispatched
+    async fn enqueue(
+        &self,
+        deliveries: Vec<model::WebhookDelivery>,
+    ) -> Result<(), DBConnectorError>;
+    // pending deliveries whose next attempt is due at `now`, oldest first, with their endpoint
+    async fn list_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(model::WebhookDelivery, model::WebhookEndpoint)>, DBConnectorError>;
+    // same, but moves their next attempt to `lease_until`, like IOutboxRepository::claim_due
+    async fn claim_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(model::WebhookDelivery, model::WebhookEndpoint)>, DBConnectorError>;
+    async fn list_deliveries(
+        &self,
+        query: model::WebhookDeliveryQuery,
+    ) -> Result<Vec<model::WebhookDelivery>, DBConnectorError>;
+    async fn get_delivery(
+        &self,
+        delivery_id: String,
+    ) -> Result<Option<model::WebhookDelivery>, DBConnectorError>;
+    // saves the status, the attempts and the outcome of the last attempt
+    async fn update_delivery(
+        &self,
+        delivery: model::WebhookDelivery,
+    ) -> Result<(), DBConnectorError>;
+}
+
+// POSTs the payload of a delivery, signed with the secret of its endpoint
+#[async_trait]
+pub trait IWebhookClient {
+    // the status code of the answer, or an error if there was none (e.g. a timeout)
+    async fn post(
+        &self,
+        url: String,
+        secret: String,
+        delivery: model::WebhookDelivery,
+    ) -> Result<u16, failure::Error>;
+}
+
+// The repositories below are bound to the connection of a running transaction (see IUnitOfWork)
+// They are called on the DB executor's thread, so they are not async
+pub trait IUserRepositoryTx {
+    fn get_by_id(&self, user_id: &str) -> Result<Option<model::User>, DBConnectorError>;
+    fn save(&self, user: model::User) -> Result<(), DBConnectorError>;
+    fn update(&self, user: model::User) -> Result<(), DBConnectorError>;
+}
+
+pub trait IUserLoginRepositoryTx {
+    fn get_by_user_id(&self, user_id: &str) -> Result<Option<model::Login>, DBConnectorError>;
+    fn save(&self, login: model::Login) -> Result<(), DBConnectorError>;
+    fn create(&self, login: model::Login) -> Result<(), DBConnectorError>;
+    fn update(&self, login: model::Login) -> Result<(), DBConnectorError>;
+}
+
+// Events are only delivered if the transaction commits
+pub trait IOutboxRepositoryTx {
+    fn append(&self, event: model::OutboxEvent) -> Result<(), DBConnectorError>;
+}
+
+pub trait ITransaction {
+    fn users(&self) -> &dyn IUserRepositoryTx;
+    fn logins(&self) -> &dyn IUserLoginRepositoryTx;
+    fn outbox(&self) -> &dyn IOutboxRepositoryTx;
+}
+
+pub type Work = Box<dyn FnOnce(&dyn ITransaction) -> Result<(), DBConnectorError> + Send>;
+
+#[async_trait]
+pub trait IUnitOfWork {
+    // runs the work in one transaction, which is rolled back if it returns an error
+    async fn run(&self, work: Work) -> Result<(), DBConnectorError>;
 }
 
 pub struct Hash(String);
@@ -40,3 +240,38 @@ pub trait IJWTHandler<Payload> {
     fn sign(&self, payload: Payload) -> Result<String, biscuit::errors::Error>;
     fn verify(&self, jwt: &str) -> Result<Payload, biscuit::errors::Error>;
 }
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait IMailer {
+    async fn send(&self, mail: Mail) -> Result<(), failure::Error>;
+}
+
+// State shared by every instance of the app, e.g. token revocations and rate limits
+#[async_trait]
+pub trait IKeyValueStore {
+    async fn get(&self, key: String) -> Result<Option<String>, failure::Error>;
+    // the value is kept until it is deleted when ttl is None
+    async fn set(
+        &self,
+        key: String,
+        value: String,
+        ttl: Option<Duration>,
+    ) -> Result<(), failure::Error>;
+    // returns false, leaving the value as is, if the key is already set
+    async fn set_if_absent(
+        &self,
+        key: String,
+        value: String,
+        ttl: Duration,
+    ) -> Result<bool, failure::Error>;
+    // returns false if there was no such key
+    async fn delete(&self, key: String) -> Result<bool, failure::Error>;
+    // counts from 1, and the counter expires ttl after it was created (a fixed window)
+    async fn incr(&self, key: String, ttl: Duration) -> Result<i64, failure::Error>;
+}
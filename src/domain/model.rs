@@ -1,5 +1,17 @@
+mod audit;
+mod event;
 mod login;
+mod session;
 mod user;
+mod user_list;
+mod verification;
+mod webhook;
 
+pub use audit::*;
+pub use event::*;
 pub use login::*;
+pub use session::*;
 pub use user::*;
+pub use user_list::*;
+pub use verification::*;
+pub use webhook::*;
@@ -0,0 +1,139 @@
+use serde::*;
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    // written by a newer version of the app
+    Unknown,
+    UserCreated,
+    UserUpdated,
+    UserDeleted,
+    UserPurged,
+    PasswordSet,
+    LoginSucceeded,
+    LoginFailed,
+    SessionRevoked,
+}
+
+impl AuditAction {
+    pub fn new_from_str(rep: &str) -> AuditAction {
+        serde_json::from_value(serde_json::Value::String(rep.to_owned()))
+            .unwrap_or(AuditAction::Unknown)
+    }
+
+    pub fn as_string(&self) -> String {
+        serde_json::from_str(&serde_json::to_string(self).unwrap()).unwrap()
+    }
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn new_from_str(rep: &str) -> Option<AuditOutcome> {
+        serde_json::from_value(serde_json::Value::String(rep.to_owned())).ok()
+    }
+
+    pub fn as_string(&self) -> String {
+        serde_json::from_str(&serde_json::to_string(self).unwrap()).unwrap()
+    }
+}
+
+// Who made the request, and from where
+#[derive(Clone, Default)]
+pub struct Actor {
+    // None before login, and on the private listener
+    pub user_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AuditEntry {
+    // a ULID, so the entries are ordered by time (to the millisecond)
+    pub id: String,
+    pub occurred_at: chrono::NaiveDateTime,
+    pub actor_id: Option<String>,
+    // the user acted on
+    pub target_id: Option<String>,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    // what changed, or why it failed
+    pub detail: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(actor: &Actor, action: AuditAction, target_id: Option<String>) -> AuditEntry {
+        AuditEntry {
+            id: ulid::Ulid::new().to_string(),
+            occurred_at: chrono::Utc::now().naive_utc(),
+            actor_id: actor.user_id.clone(),
+            target_id: target_id,
+            action: action,
+            outcome: AuditOutcome::Success,
+            ip: actor.ip.clone(),
+            user_agent: actor.user_agent.clone(),
+            detail: None,
+        }
+    }
+
+    pub fn detail(self, detail: impl Into<String>) -> AuditEntry {
+        AuditEntry {
+            detail: Some(detail.into()),
+            ..self
+        }
+    }
+
+    pub fn failed(self, reason: &impl std::fmt::Display) -> AuditEntry {
+        AuditEntry {
+            outcome: AuditOutcome::Failure,
+            ..self.detail(reason.to_string())
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct AuditFilter {
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub action: Option<AuditAction>,
+    pub outcome: Option<AuditOutcome>,
+    // occurred_at >= since
+    pub since: Option<chrono::NaiveDateTime>,
+    // occurred_at < until
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+// Newest first
+pub struct AuditQuery {
+    pub filter: AuditFilter,
+    // the id of the last entry of the previous page
+    pub before_id: Option<String>,
+    pub limit: i64,
+}
+
+#[derive(Serialize)]
+pub struct AuditList {
+    pub items: Vec<AuditEntry>,
+    pub next_cursor: Option<String>,
+}
+
+#[test]
+fn audit_action_new_as_str_inverse() {
+    assert_eq!(
+        AuditAction::new_from_str(&AuditAction::LoginFailed.as_string()),
+        AuditAction::LoginFailed
+    );
+    assert_eq!("password_set", AuditAction::PasswordSet.as_string());
+    assert_eq!(
+        AuditAction::Unknown,
+        AuditAction::new_from_str("user_exploded")
+    );
+    assert_eq!(None, AuditOutcome::new_from_str("maybe"));
+}
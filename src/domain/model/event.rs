@@ -0,0 +1,91 @@
+use super::user::role_serde;
+use super::Role;
+use serde::*;
+
+// Published to the other services through the outbox, see EventDispatcher
+// Consumers must tolerate types and fields they do not know
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum DomainEvent {
+    UserCreated {
+        user_id: String,
+        name: String,
+        #[serde(deserialize_with = "role_serde::deserialize")]
+        role: Role,
+    },
+    RoleChanged {
+        user_id: String,
+        #[serde(deserialize_with = "role_serde::deserialize")]
+        from: Role,
+        #[serde(deserialize_with = "role_serde::deserialize")]
+        to: Role,
+    },
+    // the user can log in from now on, e.g. a password was set for the first time
+    LoginEnabled {
+        user_id: String,
+    },
+    // the password of an enabled login was replaced
+    PasswordChanged {
+        user_id: String,
+    },
+}
+
+// every value of DomainEvent::event_type
+pub const EVENT_TYPES: [&str; 4] = [
+    "user_created",
+    "role_changed",
+    "login_enabled",
+    "password_changed",
+];
+
+impl DomainEvent {
+    pub fn event_type(&self) -> &'static str {
+        use DomainEvent::*;
+
+        match self {
+            UserCreated { .. } => "user_created",
+            RoleChanged { .. } => "role_changed",
+            LoginEnabled { .. } => "login_enabled",
+            PasswordChanged { .. } => "password_changed",
+        }
+    }
+}
+
+// What the sinks receive: {"id": ..., "occurred_at": ..., "type": ..., "data": {...}}
+#[derive(Serialize, Clone, Debug)]
+pub struct OutboxEvent {
+    // a ULID; the same for every delivery of the event, so consumers can drop duplicates
+    pub id: String,
+    pub occurred_at: chrono::NaiveDateTime,
+    #[serde(flatten)]
+    pub event: DomainEvent,
+    // failed deliveries so far
+    #[serde(skip)]
+    pub attempts: i32,
+}
+
+impl OutboxEvent {
+    pub fn new(event: DomainEvent) -> OutboxEvent {
+        OutboxEvent {
+            id: ulid::Ulid::new().to_string(),
+            occurred_at: chrono::Utc::now().naive_utc(),
+            event: event,
+            attempts: 0,
+        }
+    }
+}
+
+#[test]
+fn outbox_event_should_be_serialized_with_its_type() {
+    let event = OutboxEvent::new(DomainEvent::RoleChanged {
+        user_id: "u1".to_owned(),
+        from: Role::User,
+        to: Role::Admin,
+    });
+    let json = serde_json::to_value(&event).unwrap();
+
+    assert_eq!("role_changed", json["type"]);
+    assert_eq!(event.event.event_type(), json["type"]);
+    assert_eq!("admin", json["data"]["to"]);
+    assert!(json.get("attempts").is_none());
+}
@@ -6,6 +6,17 @@ pub enum LoginUserStatus {
     Enabled,
     Disabled,
     PasswordChangeRequired,
+    // signed up, but the email address is not verified yet
+    PendingVerification,
+}
+
+impl LoginUserStatus {
+    pub fn can_login(&self) -> bool {
+        match self {
+            LoginUserStatus::Enabled | LoginUserStatus::PasswordChangeRequired => true,
+            LoginUserStatus::Disabled | LoginUserStatus::PendingVerification => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -13,6 +24,8 @@ pub struct Login {
     pub user_id: String,
     pub password_hash: String,
     pub status: LoginUserStatus,
+    // incremented on each write, for optimistic concurrency
+    pub version: i32,
 }
 
 #[test]
@@ -21,6 +34,7 @@ fn it_should_serialize() {
         user_id: "aaa".to_owned(),
         password_hash: "bbb".to_owned(),
         status: LoginUserStatus::PasswordChangeRequired,
+        version: 3,
     };
     assert_eq!(
         serde_json::to_value(&login).unwrap(),
@@ -29,10 +43,18 @@ fn it_should_serialize() {
                 {
                     "user_id": "aaa",
                     "password_hash": "bbb",
-                    "status": "password_change_required"
+                    "status": "password_change_required",
+                    "version": 3
                 }
             "#
         )
         .unwrap()
     );
 }
+
+#[test]
+fn pending_user_should_not_login() {
+    assert!(!LoginUserStatus::PendingVerification.can_login());
+    assert!(!LoginUserStatus::Disabled.can_login());
+    assert!(LoginUserStatus::Enabled.can_login());
+}
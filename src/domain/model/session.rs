@@ -0,0 +1,58 @@
+use super::User;
+use serde::*;
+
+// A login attempt of an existing user; the successful ones are sessions, which last until revoked
+#[derive(Serialize, Clone, Debug)]
+pub struct LoginSession {
+    // a ULID; also the `sid` claim of the token issued by the login
+    pub id: String,
+    pub user_id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    // failed attempts have no token, and cannot be revoked
+    pub succeeded: bool,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl LoginSession {
+    pub fn new(user_id: String, actor: &super::Actor, succeeded: bool) -> LoginSession {
+        LoginSession {
+            id: ulid::Ulid::new().to_string(),
+            user_id: user_id,
+            created_at: chrono::Utc::now().naive_utc(),
+            ip: actor.ip.clone(),
+            user_agent: actor.user_agent.clone(),
+            succeeded: succeeded,
+            revoked_at: None,
+        }
+    }
+
+    // whether its token is accepted
+    pub fn is_active(&self) -> bool {
+        self.succeeded && self.revoked_at.is_none()
+    }
+}
+
+// Newest first
+pub struct LoginSessionQuery {
+    pub user_id: String,
+    // the id of the last session of the previous page
+    pub before_id: Option<String>,
+    pub limit: i64,
+}
+
+#[derive(Serialize)]
+pub struct LoginSessionList {
+    pub items: Vec<LoginSession>,
+    pub next_cursor: Option<String>,
+}
+
+// The private claims of the tokens issued by LoginService
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TokenClaims {
+    // the LoginSession the token belongs to
+    pub sid: String,
+    #[serde(flatten)]
+    pub user: User,
+}
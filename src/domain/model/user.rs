@@ -15,13 +15,14 @@ pub enum Role {
 
 impl Role {
     pub fn new_from_str(rep: &str) -> Role {
-        match serde_json::to_string(rep)
+        Role::parse(rep).unwrap_or(Role::Unknown)
+    }
+
+    // None for anything but the name of a role, where new_from_str falls back to Unknown
+    pub fn parse(rep: &str) -> Option<Role> {
+        serde_json::to_string(rep)
             .and_then(|r| serde_json::from_str(&r))
             .ok()
-        {
-            Some(t) => t,
-            None => Role::Unknown,
-        }
     }
 
     pub fn as_string(&self) -> String {
@@ -29,7 +30,7 @@ impl Role {
     }
 }
 
-mod role_serde {
+pub(super) mod role_serde {
     use super::Role;
     use serde::*;
 
@@ -48,6 +49,14 @@ fn role_new_as_str_inverse() {
     assert_eq!(Role::new_from_str("admin").as_string(), "admin");
 }
 
+#[test]
+fn parse_should_reject_unknown_names() {
+    assert_eq!(Some(Role::PowerUser), Role::parse("power_user"));
+    assert_eq!(Some(Role::Unknown), Role::parse("unknown"));
+    assert_eq!(None, Role::parse("adminn"));
+    assert_eq!(Role::Unknown, Role::new_from_str("adminn"));
+}
+
 #[test]
 fn admin_should_stronger_than_all_other_role() {
     use Role::*;
@@ -71,6 +80,9 @@ pub struct User {
     pub display_name: String,
     #[serde(deserialize_with = "role_serde::deserialize")]
     pub role: Role,
+    // only set for self-service signups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
 }
 
 #[test]
@@ -80,6 +92,7 @@ fn it_should_serialize() {
         name: "name".to_owned(),
         display_name: "日本語".to_owned(),
         role: Role::PowerUser,
+        email: None,
     };
     assert_eq!(
         serde_json::to_value(&user).unwrap(),
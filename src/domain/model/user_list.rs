@@ -0,0 +1,108 @@
+use super::{Role, User};
+use serde::*;
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    IdAsc,
+    IdDesc,
+    NameAsc,
+    NameDesc,
+}
+
+impl Default for UserSort {
+    fn default() -> UserSort {
+        UserSort::IdAsc
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct UserFilter {
+    pub role: Option<Role>,
+    // matches either name or display_name
+    pub name_prefix: Option<String>,
+}
+
+// Position right after the last user of the previous page
+// `name` is only needed for the name_* sorts but is always carried, so a cursor
+// stays valid if the client keeps it while switching sort options
+#[derive(PartialEq, Debug, Clone)]
+pub struct UserCursor {
+    pub id: String,
+    pub name: String,
+}
+
+impl UserCursor {
+    pub fn from_user(user: &User) -> UserCursor {
+        UserCursor {
+            id: user.id.clone(),
+            name: user.name.clone(),
+        }
+    }
+
+    // Opaque to the client: hex of "{id}\n{name}"
+    pub fn encode(&self) -> String {
+        format!("{}\n{}", self.id, self.name)
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn decode(rep: &str) -> Option<UserCursor> {
+        if rep.len() % 2 != 0 || !rep.is_ascii() {
+            return None;
+        }
+
+        let bytes = (0..rep.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&rep[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let raw = String::from_utf8(bytes).ok()?;
+        let mut parts = raw.splitn(2, '\n');
+
+        Some(UserCursor {
+            id: parts.next()?.to_owned(),
+            name: parts.next()?.to_owned(),
+        })
+    }
+}
+
+pub struct UserListQuery {
+    pub filter: UserFilter,
+    pub sort: UserSort,
+    pub cursor: Option<UserCursor>,
+    pub limit: i64,
+}
+
+#[derive(Serialize)]
+pub struct UserList {
+    pub items: Vec<User>,
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+#[test]
+fn cursor_encode_decode_inverse() {
+    let cursor = UserCursor {
+        id: "01DQ4H3X6X2M5Y5ZK8W4V3GQ7P".to_owned(),
+        name: "日本語\nname".to_owned(),
+    };
+    assert_eq!(UserCursor::decode(&cursor.encode()), Some(cursor));
+}
+
+#[test]
+fn cursor_decode_should_reject_garbage() {
+    assert_eq!(UserCursor::decode("xyz"), None);
+    assert_eq!(UserCursor::decode("zz"), None);
+    // valid hex, but no separator
+    assert_eq!(UserCursor::decode("6161"), None);
+}
+
+#[test]
+fn sort_should_parse_snake_case() {
+    assert_eq!(
+        serde_json::from_str::<UserSort>(r#""name_desc""#).unwrap(),
+        UserSort::NameDesc
+    );
+}
@@ -0,0 +1,12 @@
+// The raw token is only sent by mail; only its hash is stored
+pub struct EmailVerification {
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+impl EmailVerification {
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.expires_at <= now
+    }
+}
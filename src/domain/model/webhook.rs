@@ -0,0 +1,98 @@
+use super::OutboxEvent;
+use serde::*;
+
+// An endpoint registered by an admin, which receives the events of the given types
+#[derive(Serialize, Clone, Debug)]
+pub struct WebhookEndpoint {
+    // a ULID
+    pub id: String,
+    pub url: String,
+    // signs the deliveries; never returned by the API
+    #[serde(skip)]
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl WebhookEndpoint {
+    pub fn subscribes_to(&self, event_type: &str) -> bool {
+        self.event_types.iter().any(|t| t == event_type)
+    }
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    // waiting for its first or next attempt
+    Pending,
+    Delivered,
+    // failed EVENT_MAX_ATTEMPTS times; only retried by an admin
+    Dead,
+}
+
+impl WebhookDeliveryStatus {
+    pub fn new_from_str(rep: &str) -> Option<WebhookDeliveryStatus> {
+        serde_json::from_value(serde_json::Value::String(rep.to_owned())).ok()
+    }
+
+    pub fn as_string(&self) -> String {
+        serde_json::from_str(&serde_json::to_string(self).unwrap()).unwrap()
+    }
+}
+
+// An event on its way to an endpoint, and the outcome of its last attempt
+#[derive(Serialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    // a ULID; sent as X-Delivery-Id
+    pub id: String,
+    pub endpoint_id: String,
+    pub event_id: String,
+    pub event_type: String,
+    // the body, the same on every attempt
+    #[serde(skip)]
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    // None when the endpoint did not answer
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl WebhookDelivery {
+    pub fn new(endpoint_id: String, event: &OutboxEvent) -> WebhookDelivery {
+        let now = chrono::Utc::now().naive_utc();
+
+        WebhookDelivery {
+            id: ulid::Ulid::new().to_string(),
+            endpoint_id: endpoint_id,
+            event_id: event.id.clone(),
+            event_type: event.event.event_type().to_owned(),
+            payload: serde_json::to_string(event).unwrap(),
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_status_code: None,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// Newest first
+pub struct WebhookDeliveryQuery {
+    pub endpoint_id: String,
+    pub status: Option<WebhookDeliveryStatus>,
+    // the id of the last delivery of the previous page
+    pub before_id: Option<String>,
+    pub limit: i64,
+}
+
+#[derive(Serialize)]
+pub struct WebhookDeliveryList {
+    pub items: Vec<WebhookDelivery>,
+    pub next_cursor: Option<String>,
+}
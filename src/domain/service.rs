@@ -1,5 +1,16 @@
+mod audit_service;
+mod event_dispatcher;
 mod login_service;
+mod pagination;
+mod session_service;
+mod signup_service;
 mod user_service;
+mod webhook_service;
 
+pub use audit_service::*;
+pub use event_dispatcher::*;
 pub use login_service::*;
+pub use session_service::*;
+pub use signup_service::*;
 pub use user_service::*;
+pub use webhook_service::*;
@@ -0,0 +1,152 @@
+use crate::domain::interface::IAuditLog;
+use crate::domain::model;
+use crate::domain::service::pagination::{paginate, split_page};
+use crate::error::ServiceError;
+use crate::infra::{instrument, Span};
+use futures::Stream;
+use serde::*;
+use std::sync::Arc;
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+// entries read per query while exporting
+const EXPORT_BATCH: i64 = 500;
+
+// Best effort: a failed write is logged, but does not fail the audited operation
+pub async fn record(audit_log: &(dyn IAuditLog + Sync + Send), entry: model::AuditEntry) {
+    let action = entry.action;
+    if let Err(err) = audit_log.append(entry).await {
+        error!("Failed to write the audit log ({:?}): {}", action, err);
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct AuditListInput {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub action: Option<String>,
+    pub outcome: Option<String>,
+    // e.g. 2019-10-25T12:00:00 (UTC)
+    pub since: Option<chrono::NaiveDateTime>,
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+fn invalid_request(message: String) -> ServiceError {
+    ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(failure::err_msg(
+        message,
+    ))))
+}
+
+impl AuditListInput {
+    fn filter(&self) -> Result<model::AuditFilter, ServiceError> {
+        let action = match &self.action {
+            Some(action) => match model::AuditAction::new_from_str(action) {
+                model::AuditAction::Unknown => {
+                    return Err(invalid_request(format!("unknown action: {}", action)))
+                }
+                action => Some(action),
+            },
+            None => None,
+        };
+        let outcome = match &self.outcome {
+            Some(outcome) => Some(
+                model::AuditOutcome::new_from_str(outcome)
+                    .ok_or_else(|| invalid_request(format!("unknown outcome: {}", outcome)))?,
+            ),
+            None => None,
+        };
+
+        Ok(model::AuditFilter {
+            actor_id: self.actor_id.clone(),
+            target_id: self.target_id.clone(),
+            action: action,
+            outcome: outcome,
+            since: self.since,
+            until: self.until,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct AuditService {
+    audit_log: Arc<dyn IAuditLog + Sync + Send>,
+}
+
+impl AuditService {
+    pub fn new(audit_log: Arc<dyn IAuditLog + Sync + Send>) -> AuditService {
+        AuditService {
+            audit_log: audit_log,
+        }
+    }
+
+    pub async fn list(&self, input: AuditListInput) -> Result<model::AuditList, ServiceError> {
+        instrument(Span::new("AuditService::list"), async move {
+            let limit = paginate(input.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+
+            let items = self
+                .audit_log
+                .list(model::AuditQuery {
+                    filter: input.filter()?,
+                    before_id: input.cursor,
+                    limit: limit + 1,
+                })
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            let (items, next_cursor) = split_page(items, limit, |e| e.id.clone());
+
+            Ok(model::AuditList {
+                items: items,
+                next_cursor: next_cursor,
+            })
+        })
+        .await
+    }
+
+    // Every matching entry, newest first, read in batches as the stream is consumed
+    // (cursor and limit are ignored)
+    pub fn export(
+        &self,
+        input: AuditListInput,
+    ) -> Result<impl Stream<Item = Result<Vec<model::AuditEntry>, ServiceError>>, ServiceError>
+    {
+        let filter = input.filter()?;
+        let audit_log = self.audit_log.clone();
+
+        // None once the last batch was read
+        let start = Some(None);
+        Ok(futures::stream::unfold(
+            start,
+            move |before_id: Option<Option<String>>| {
+                let audit_log = audit_log.clone();
+                let filter = filter.clone();
+                async move {
+                    let before_id = before_id?;
+                    let batch = audit_log
+                        .list(model::AuditQuery {
+                            filter: filter,
+                            before_id: before_id,
+                            limit: EXPORT_BATCH,
+                        })
+                        .await;
+
+                    match batch {
+                        Ok(batch) if batch.is_empty() => None,
+                        Ok(batch) => {
+                            let next = if (batch.len() as i64) < EXPORT_BATCH {
+                                None
+                            } else {
+                                batch.last().map(|e| Some(e.id.clone()))
+                            };
+                            Some((Ok(batch), next))
+                        }
+                        // ends the stream after the error
+                        Err(err) => Some((Err(ServiceError::DBError(err)), None)),
+                    }
+                }
+            },
+        ))
+    }
+}
@@ -0,0 +1,169 @@
+use crate::domain::interface::{IEventSink, IOutboxRepository};
+use crate::infra::{instrument, DBConnectorError, Span};
+use std::sync::Arc;
+
+// events handed to the sink per dispatch_due
+const BATCH_SIZE: i64 = 100;
+const BASE_BACKOFF_SECS: i64 = 1;
+const MAX_BACKOFF_SECS: i64 = 600;
+// how long a claimed batch is kept from other dispatchers; longer than a batch which times out
+// on every delivery, and the wait before a batch is retried when its dispatcher died
+pub(super) const CLAIM_LEASE_SECS: i64 = 1800;
+
+// the wait after the given number of failed attempts: 1s, 2s, 4s, ... up to 10 minutes
+pub(super) fn backoff(attempts: i32) -> chrono::Duration {
+    let secs = BASE_BACKOFF_SECS
+        .checked_shl(attempts.max(0) as u32)
+        .filter(|s| *s > 0 && *s < MAX_BACKOFF_SECS)
+        .unwrap_or(MAX_BACKOFF_SECS);
+
+    chrono::Duration::seconds(secs)
+}
+
+// Delivers the events of the outbox to the sink, at least once
+// A failed event is retried with exponential backoff, and is dead after max_attempts failures;
+// it does not hold back the events behind it, so the order is only kept while nothing fails
+#[derive(Clone)]
+pub struct EventDispatcher {
+    outbox_repository: Arc<dyn IOutboxRepository + Sync + Send>,
+    sink: Arc<dyn IEventSink + Sync + Send>,
+    max_attempts: u32,
+}
+
+impl EventDispatcher {
+    pub fn new(
+        outbox_repository: Arc<dyn IOutboxRepository + Sync + Send>,
+        sink: Arc<dyn IEventSink + Sync + Send>,
+        max_attempts: u32,
+    ) -> EventDispatcher {
+        EventDispatcher {
+            outbox_repository: outbox_repository,
+            sink: sink,
+            max_attempts: max_attempts,
+        }
+    }
+
+    // Returns how many events were delivered
+    pub async fn dispatch_due(
+        &self,
+        now: chrono::NaiveDateTime,
+    ) -> Result<usize, DBConnectorError> {
+        instrument(Span::new("EventDispatcher::dispatch_due"), async move {
+            let lease_until = now + chrono::Duration::seconds(CLAIM_LEASE_SECS);
+            let events = self
+                .outbox_repository
+                .claim_due(now, lease_until, BATCH_SIZE)
+                .await?;
+
+            let mut delivered = 0;
+            for event in events {
+                let (event_id, attempts) = (event.id.clone(), event.attempts);
+
+                match self.sink.deliver(event).await {
+                    Ok(()) => {
+                        self.outbox_repository.mark_dispatched(event_id).await?;
+                        delivered += 1;
+                    }
+                    Err(err) => {
+                        let next_attempt_at = if attempts as u32 + 1 >= self.max_attempts {
+                            error!("Giving up on the event {}: {}", event_id, err);
+                            None
+                        } else {
+                            warn!("Failed to deliver the event {}: {}", event_id, err);
+                            Some(now + backoff(attempts))
+                        };
+
+                        self.outbox_repository
+                            .mark_failed(event_id, err.to_string(), next_attempt_at)
+                            .await?;
+                    }
+                }
+            }
+
+            Ok(delivered)
+        })
+        .await
+    }
+}
+
+#[test]
+fn backoff_should_double_up_to_the_max() {
+    assert_eq!(chrono::Duration::seconds(1), backoff(0));
+    assert_eq!(chrono::Duration::seconds(8), backoff(3));
+    assert_eq!(chrono::Duration::seconds(600), backoff(10));
+    assert_eq!(chrono::Duration::seconds(600), backoff(100));
+}
+
+#[test]
+fn failed_events_should_be_retried_until_dead() {
+    use crate::domain::interface::{ITransaction, IUnitOfWork};
+    use crate::domain::model;
+    use crate::serviceclient::memory::*;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    // fails while `failing` is set, and keeps the ids of the delivered events
+    struct FakeSink {
+        failing: Mutex<bool>,
+        delivered: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl IEventSink for FakeSink {
+        async fn deliver(&self, event: model::OutboxEvent) -> Result<(), failure::Error> {
+            if *self.failing.lock().unwrap() {
+                bail!("unavailable");
+            }
+            self.delivered.lock().unwrap().push(event.id);
+            Ok(())
+        }
+    }
+
+    let store = MemoryStore::new();
+    let sink = Arc::new(FakeSink {
+        failing: Mutex::new(true),
+        delivered: Mutex::new(Vec::new()),
+    });
+    let dispatcher = EventDispatcher::new(
+        Arc::new(MemoryOutboxRepository::new(store.clone())),
+        sink.clone(),
+        3,
+    );
+    let (first, second) = (test_event("u1"), test_event("u2"));
+    let (first_id, second_id) = (first.id.clone(), second.id.clone());
+
+    futures::executor::block_on(async {
+        MemoryUnitOfWork::new(store.clone())
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                tx.outbox().append(first)
+            }))
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().naive_utc();
+
+        // the first failure is retried after a second
+        assert_eq!(0, dispatcher.dispatch_due(now).await.unwrap());
+        assert_eq!(0, dispatcher.dispatch_due(now).await.unwrap());
+        let now = now + chrono::Duration::seconds(1);
+        assert_eq!(0, dispatcher.dispatch_due(now).await.unwrap());
+
+        // the third failure is the last one
+        let now = now + chrono::Duration::seconds(2);
+        assert_eq!(0, dispatcher.dispatch_due(now).await.unwrap());
+
+        *sink.failing.lock().unwrap() = false;
+        MemoryUnitOfWork::new(store.clone())
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                tx.outbox().append(second)
+            }))
+            .await
+            .unwrap();
+        let now = now + chrono::Duration::hours(1);
+        assert_eq!(1, dispatcher.dispatch_due(now).await.unwrap());
+        assert_eq!(0, dispatcher.dispatch_due(now).await.unwrap());
+    });
+
+    let delivered = sink.delivered.lock().unwrap();
+    assert_eq!(vec![second_id], *delivered);
+    assert!(!delivered.contains(&first_id));
+}
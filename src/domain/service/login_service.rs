@@ -1,14 +1,23 @@
-use crate::domain::interface::{Hash, IHashManager, IJWTHandler, IUserLoginRepository};
+use crate::domain::interface::{
+    Hash, IAuditLog, IHashManager, IJWTHandler, IKeyValueStore, ILoginSessionRepository,
+    ITransaction, IUnitOfWork, IUserLoginRepository,
+};
 use crate::domain::model;
+use crate::domain::service::{audit_service, session_service};
 use crate::error::ServiceError;
+use crate::infra::{instrument, Span};
 use serde::*;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct LoginService {
     login_repository: Arc<dyn IUserLoginRepository + Sync + Send>,
+    unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
     hash_manager: Arc<dyn IHashManager + Sync + Send>,
-    jwt_handler: Arc<dyn IJWTHandler<model::User> + Sync + Send>,
+    jwt_handler: Arc<dyn IJWTHandler<model::TokenClaims> + Sync + Send>,
+    session_repository: Arc<dyn ILoginSessionRepository + Sync + Send>,
+    audit_log: Arc<dyn IAuditLog + Sync + Send>,
+    kv_store: Arc<dyn IKeyValueStore + Sync + Send>,
 }
 
 #[derive(Deserialize)]
@@ -19,74 +28,193 @@ pub struct AuthenticateInput {
 
 #[derive(Deserialize)]
 pub struct EnableUserWithPasswordInput {
-    user_id: String,
     password: String,
 }
 
 impl LoginService {
     pub fn new(
         login_repository: Arc<dyn IUserLoginRepository + Sync + Send>,
+        unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
         hash_manager: Arc<dyn IHashManager + Sync + Send>,
-        jwt_handler: Arc<dyn IJWTHandler<model::User> + Sync + Send>,
+        jwt_handler: Arc<dyn IJWTHandler<model::TokenClaims> + Sync + Send>,
+        session_repository: Arc<dyn ILoginSessionRepository + Sync + Send>,
+        audit_log: Arc<dyn IAuditLog + Sync + Send>,
+        kv_store: Arc<dyn IKeyValueStore + Sync + Send>,
     ) -> LoginService {
         LoginService {
             login_repository: login_repository,
+            unit_of_work: unit_of_work,
             hash_manager: hash_manager,
             jwt_handler: jwt_handler,
+            session_repository: session_repository,
+            audit_log: audit_log,
+            kv_store: kv_store,
         }
     }
 
+    // The error comes with the id of the user, when there is one by that name
     async fn authenticate_user(
         &self,
         input: AuthenticateInput,
-    ) -> Result<model::User, ServiceError> {
+    ) -> Result<model::User, (Option<String>, ServiceError)> {
+        // do not tell whether the user exists or not
+        let invalid = || {
+            ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(failure::err_msg(
+                "invalid user name or password",
+            ))))
+        };
+
         let (login, user) = self
             .login_repository
             .get_by_user_name(input.user_name)
             .await
-            .map_err(ServiceError::DBError)?;
+            .map_err(|err| (None, ServiceError::DBError(err)))?
+            .ok_or_else(|| (None, invalid()))?;
 
         if !self
             .hash_manager
             .verify(Hash::from_string(login.password_hash), input.password)
         {
-            return Err(ServiceError::InvalidRequest(Box::new(
-                ServiceError::GeneralError(failure::err_msg("invalid password")),
-            )));
+            return Err((Some(user.id), invalid()));
+        }
+
+        // a bad request like the other failed logins, only told to who knows the password
+        if !login.status.can_login() {
+            return Err((
+                Some(user.id),
+                ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(
+                    failure::err_msg("login is not enabled"),
+                ))),
+            ));
         }
 
         Ok(user)
     }
 
-    pub async fn authenticate(&self, input: AuthenticateInput) -> Result<String, ServiceError> {
-        let user = self.authenticate_user(input).await?;
+    pub async fn authenticate(
+        &self,
+        actor: model::Actor,
+        input: AuthenticateInput,
+    ) -> Result<String, ServiceError> {
+        instrument(Span::new("LoginService::authenticate"), async move {
+            let user = match self.authenticate_user(input).await {
+                Ok(user) => user,
+                Err((user_id, err)) => {
+                    // best effort, like the audit log
+                    if let Some(user_id) = &user_id {
+                        let session = model::LoginSession::new(user_id.clone(), &actor, false);
+                        if let Err(err) = self.session_repository.create(session).await {
+                            error!("Failed to record the login attempt: {}", err);
+                        }
+                    }
+
+                    let entry =
+                        model::AuditEntry::new(&actor, model::AuditAction::LoginFailed, user_id)
+                            .failed(&err);
+                    audit_service::record(self.audit_log.as_ref(), entry).await;
+                    return Err(err);
+                }
+            };
+
+            // the token is only accepted while its session is, so this one must be written
+            let session = model::LoginSession::new(user.id.clone(), &actor, true);
+            let sid = session.id.clone();
+            self.session_repository
+                .create(session)
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            // the user is the actor from now on
+            let actor = model::Actor {
+                user_id: Some(user.id.clone()),
+                ..actor
+            };
+            let entry = model::AuditEntry::new(
+                &actor,
+                model::AuditAction::LoginSucceeded,
+                Some(user.id.clone()),
+            )
+            .detail(format!("session {}", sid));
+            audit_service::record(self.audit_log.as_ref(), entry).await;
 
-        self.jwt_handler.sign(user).map_err(|err| {
-            ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(err.into())))
+            self.jwt_handler
+                .sign(model::TokenClaims {
+                    sid: sid,
+                    user: user,
+                })
+                .map_err(|err| {
+                    ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(err.into())))
+                })
         })
+        .await
     }
 
     pub async fn enable_user_with_password(
         &self,
+        actor: model::Actor,
+        user_id: String,
         input: EnableUserWithPasswordInput,
     ) -> Result<(), ServiceError> {
-        let login = model::Login {
-            user_id: input.user_id,
-            password_hash: self.hash_manager.hash(input.password).to_string(),
-            status: model::LoginUserStatus::Enabled,
-        };
+        instrument(
+            Span::new("LoginService::enable_user_with_password"),
+            async move {
+                let entry = model::AuditEntry::new(
+                    &actor,
+                    model::AuditAction::PasswordSet,
+                    Some(user_id.clone()),
+                );
+                let login = model::Login {
+                    user_id: user_id.clone(),
+                    password_hash: self.hash_manager.hash(input.password).to_string(),
+                    status: model::LoginUserStatus::Enabled,
+                    version: 0,
+                };
 
-        self.login_repository
-            .save(login)
-            .await
-            .map_err(ServiceError::DBError)?;
+                let result = self
+                    .unit_of_work
+                    .run(Box::new(move |tx: &dyn ITransaction| {
+                        // a new password for a login which could already log in is a change
+                        let event = match tx.logins().get_by_user_id(&user_id)? {
+                            Some(previous) if previous.status.can_login() => {
+                                model::DomainEvent::PasswordChanged { user_id: user_id }
+                            }
+                            _ => model::DomainEvent::LoginEnabled { user_id: user_id },
+                        };
+                        tx.logins().save(login)?;
+                        tx.outbox().append(model::OutboxEvent::new(event))
+                    }))
+                    .await;
+                let entry = match &result {
+                    Ok(_) => entry,
+                    Err(err) => entry.failed(err),
+                };
+                audit_service::record(self.audit_log.as_ref(), entry).await;
 
-        Ok(())
+                result.map_err(ServiceError::DBError)
+            },
+        )
+        .await
     }
 
     pub async fn authorize(&self, token: String) -> Result<model::User, ServiceError> {
-        self.jwt_handler.verify(&token).map_err(|err| {
-            ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(err.into())))
+        instrument(Span::new("LoginService::authorize"), async move {
+            let claims = self.jwt_handler.verify(&token).map_err(|err| {
+                ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(err.into())))
+            })?;
+
+            let user_id = session_service::active_session_user(
+                self.session_repository.as_ref(),
+                self.kv_store.as_ref(),
+                claims.sid,
+            )
+            .await?;
+            match user_id {
+                Some(user_id) if user_id == claims.user.id => Ok(claims.user),
+                _ => Err(ServiceError::Unauthorized(failure::err_msg(
+                    "the session was revoked",
+                ))),
+            }
         })
+        .await
     }
 }
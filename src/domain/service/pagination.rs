@@ -0,0 +1,40 @@
+use crate::error::ServiceError;
+
+// The limit of a list request, `default` when it has none
+pub(super) fn paginate(limit: Option<i64>, default: i64, max: i64) -> Result<i64, ServiceError> {
+    let limit = limit.unwrap_or(default);
+    if limit < 1 || limit > max {
+        return Err(ServiceError::InvalidRequest(Box::new(
+            ServiceError::GeneralError(format_err!("limit must be between 1 and {}", max)),
+        )));
+    }
+
+    Ok(limit)
+}
+
+// The page and the cursor of the next one, from the items listed with `limit + 1`: the extra
+// item only tells that there is a next page
+pub(super) fn split_page<T>(
+    mut items: Vec<T>,
+    limit: i64,
+    cursor_of: impl Fn(&T) -> String,
+) -> (Vec<T>, Option<String>) {
+    if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        let next_cursor = items.last().map(cursor_of);
+        (items, next_cursor)
+    } else {
+        (items, None)
+    }
+}
+
+#[test]
+fn split_page_should_drop_the_extra_item() {
+    let (items, next_cursor) = split_page(vec![1, 2, 3], 2, |n| n.to_string());
+    assert_eq!(vec![1, 2], items);
+    assert_eq!(Some("2".to_owned()), next_cursor);
+
+    let (items, next_cursor) = split_page(vec![1, 2], 2, |n| n.to_string());
+    assert_eq!(vec![1, 2], items);
+    assert_eq!(None, next_cursor);
+}
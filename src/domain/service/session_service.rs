@@ -0,0 +1,220 @@
+use crate::domain::interface::{IAuditLog, IKeyValueStore, ILoginSessionRepository};
+use crate::domain::model;
+use crate::domain::service::audit_service;
+use crate::domain::service::pagination::{paginate, split_page};
+use crate::error::ServiceError;
+use crate::infra::{instrument, Span};
+use serde::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+
+// The states of the sessions are cached in the key-value store, so that authorizing a request
+// does not read the database every time; a revocation is seen at once by the instances sharing
+// the store, and only after ACTIVE_SESSION_TTL by the others (e.g. without KV_STORE_URL)
+const ACTIVE_SESSION_TTL: Duration = Duration::from_secs(60);
+// outlives the cached active states, so that one read before the revocation cannot replace it
+const REVOKED_SESSION_TTL: Duration = Duration::from_secs(3600);
+// the state of an inactive session; otherwise the id of its user
+const REVOKED: &str = "revoked";
+
+fn session_key(session_id: &str) -> String {
+    format!("session:{}", session_id)
+}
+
+// The id of the user of an active session, None when it failed, was revoked or does not exist
+pub async fn active_session_user(
+    session_repository: &(dyn ILoginSessionRepository + Sync + Send),
+    kv_store: &(dyn IKeyValueStore + Sync + Send),
+    session_id: String,
+) -> Result<Option<String>, ServiceError> {
+    let key = session_key(&session_id);
+    // the database decides while the store is unavailable
+    match kv_store.get(key.clone()).await {
+        Ok(Some(state)) => return Ok(Some(state).filter(|state| state != REVOKED)),
+        Ok(None) => (),
+        Err(err) => warn!("Failed to read the session state: {}", err),
+    }
+
+    let user_id = session_repository
+        .get_by_id(session_id)
+        .await
+        .map_err(ServiceError::DBError)?
+        .filter(|session| session.is_active())
+        .map(|session| session.user_id);
+
+    // an active state must not replace a revocation written since the session was read
+    let cached = match &user_id {
+        Some(user_id) => kv_store
+            .set_if_absent(key, user_id.clone(), ACTIVE_SESSION_TTL)
+            .await
+            .map(|_| ()),
+        None => {
+            kv_store
+                .set(key, REVOKED.to_owned(), Some(REVOKED_SESSION_TTL))
+                .await
+        }
+    };
+    if let Err(err) = cached {
+        warn!("Failed to cache the session state: {}", err);
+    }
+
+    Ok(user_id)
+}
+
+#[derive(Deserialize, Default)]
+pub struct SessionListInput {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn session_not_found() -> ServiceError {
+    ServiceError::NotFound(failure::err_msg("session not found"))
+}
+
+// The login history of a user, and revocation of the tokens issued by it
+#[derive(Clone)]
+pub struct SessionService {
+    session_repository: Arc<dyn ILoginSessionRepository + Sync + Send>,
+    audit_log: Arc<dyn IAuditLog + Sync + Send>,
+    kv_store: Arc<dyn IKeyValueStore + Sync + Send>,
+}
+
+impl SessionService {
+    pub fn new(
+        session_repository: Arc<dyn ILoginSessionRepository + Sync + Send>,
+        audit_log: Arc<dyn IAuditLog + Sync + Send>,
+        kv_store: Arc<dyn IKeyValueStore + Sync + Send>,
+    ) -> SessionService {
+        SessionService {
+            session_repository: session_repository,
+            audit_log: audit_log,
+            kv_store: kv_store,
+        }
+    }
+
+    pub async fn list(
+        &self,
+        user_id: String,
+        input: SessionListInput,
+    ) -> Result<model::LoginSessionList, ServiceError> {
+        instrument(Span::new("SessionService::list"), async move {
+            let limit = paginate(input.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+
+            let items = self
+                .session_repository
+                .list(model::LoginSessionQuery {
+                    user_id: user_id,
+                    before_id: input.cursor,
+                    limit: limit + 1,
+                })
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            let (items, next_cursor) = split_page(items, limit, |s| s.id.clone());
+
+            Ok(model::LoginSessionList {
+                items: items,
+                next_cursor: next_cursor,
+            })
+        })
+        .await
+    }
+
+    // Revoking a revoked session again is not an error, and replaces its cached state again
+    pub async fn revoke(
+        &self,
+        actor: model::Actor,
+        user_id: String,
+        session_id: String,
+    ) -> Result<(), ServiceError> {
+        instrument(Span::new("SessionService::revoke"), async move {
+            let session = self
+                .session_repository
+                .get_by_id(session_id.clone())
+                .await
+                .map_err(ServiceError::DBError)?
+                .filter(|s| s.user_id == user_id && s.succeeded)
+                .ok_or_else(session_not_found)?;
+
+            let revoked = self
+                .session_repository
+                .revoke(session.id.clone())
+                .await
+                .map_err(ServiceError::DBError)?;
+            if revoked {
+                let entry = model::AuditEntry::new(
+                    &actor,
+                    model::AuditAction::SessionRevoked,
+                    Some(user_id),
+                )
+                .detail(format!("session {}", session_id));
+                audit_service::record(self.audit_log.as_ref(), entry).await;
+            }
+
+            // revoked in the database already; without the cached state it is read from there
+            let key = session_key(&session.id);
+            if let Err(err) = self
+                .kv_store
+                .set(key.clone(), REVOKED.to_owned(), Some(REVOKED_SESSION_TTL))
+                .await
+            {
+                warn!("Failed to cache the revoked session state: {}", err);
+                if let Err(err) = self.kv_store.delete(key).await {
+                    warn!("Failed to drop the cached session state: {}", err);
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[test]
+fn revocations_should_replace_the_cached_state() {
+    use crate::infra::MemoryKeyValueStore;
+    use crate::serviceclient::memory::*;
+
+    let store = MemoryStore::new();
+    let sessions = Arc::new(MemoryLoginSessionRepository::new(store.clone()));
+    let kv_store = Arc::new(MemoryKeyValueStore::new());
+    let service = SessionService::new(
+        sessions.clone(),
+        Arc::new(MemoryAuditLog::new(store.clone())),
+        kv_store.clone(),
+    );
+    let user = test_user("sessions");
+    let actor = test_actor(&user);
+    let session = model::LoginSession::new(user.id.clone(), &actor, true);
+    let session_id = session.id.clone();
+
+    futures::executor::block_on(async {
+        use crate::domain::interface::IUserRepository;
+
+        MemoryUserRepository::new(store.clone())
+            .save(user.clone())
+            .await
+            .unwrap();
+        sessions.create(session).await.unwrap();
+        let active = active_session_user(sessions.as_ref(), kv_store.as_ref(), session_id.clone());
+        assert_eq!(Some(user.id.clone()), active.await.unwrap());
+        assert_eq!(
+            Some(user.id.clone()),
+            kv_store.get(session_key(&session_id)).await.unwrap()
+        );
+
+        service
+            .revoke(actor, user.id.clone(), session_id.clone())
+            .await
+            .unwrap();
+        let active = active_session_user(sessions.as_ref(), kv_store.as_ref(), session_id.clone());
+        assert_eq!(None, active.await.unwrap());
+
+        kv_store.delete(session_key(&session_id)).await.unwrap();
+        let active = active_session_user(sessions.as_ref(), kv_store.as_ref(), session_id);
+        assert_eq!(None, active.await.unwrap());
+    });
+}
@@ -0,0 +1,260 @@
+use crate::domain::interface::{IHashManager, IKeyValueStore, IMailer, IUserLoginRepository, Mail};
+use crate::domain::model;
+use crate::domain::service::user_service;
+use crate::error::{FieldError, ServiceError};
+use crate::infra::{instrument, DBConnectorError, Span};
+use ring::rand::SecureRandom;
+use serde::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct SignupService {
+    login_repository: Arc<dyn IUserLoginRepository + Sync + Send>,
+    hash_manager: Arc<dyn IHashManager + Sync + Send>,
+    mailer: Arc<dyn IMailer + Sync + Send>,
+    kv_store: Arc<dyn IKeyValueStore + Sync + Send>,
+    config: crate::config::SignupConfig,
+}
+
+#[derive(Deserialize)]
+pub struct SignupInput {
+    name: String,
+    display_name: String,
+    email: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+pub struct SignupOutput {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailInput {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationInput {
+    email: String,
+}
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+// mails sent by resend per address and window
+const MAX_RESENDS: i64 = 3;
+const RESEND_WINDOW: Duration = Duration::from_secs(3600);
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_token(token: &str) -> String {
+    to_hex(ring::digest::digest(&ring::digest::SHA256, token.as_bytes()).as_ref())
+}
+
+// Returns (token, hash of the token)
+fn new_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    ring::rand::SystemRandom::new().fill(&mut bytes).unwrap();
+    let token = to_hex(&bytes);
+    let hash = hash_token(&token);
+
+    (token, hash)
+}
+
+// Not RFC 5322, just enough to keep the address safe to put in a mail header
+fn is_valid_email(email: &str) -> bool {
+    let parts = email.split('@').collect::<Vec<&str>>();
+    email.len() <= 255
+        && parts.len() == 2
+        && !parts[0].is_empty()
+        && parts[1].contains('.')
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn invalid_request(message: &'static str) -> ServiceError {
+    ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(failure::err_msg(
+        message,
+    ))))
+}
+
+impl SignupService {
+    pub fn new(
+        login_repository: Arc<dyn IUserLoginRepository + Sync + Send>,
+        hash_manager: Arc<dyn IHashManager + Sync + Send>,
+        mailer: Arc<dyn IMailer + Sync + Send>,
+        kv_store: Arc<dyn IKeyValueStore + Sync + Send>,
+        config: crate::config::SignupConfig,
+    ) -> SignupService {
+        SignupService {
+            login_repository: login_repository,
+            hash_manager: hash_manager,
+            mailer: mailer,
+            kv_store: kv_store,
+            config: config,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub async fn signup(&self, input: SignupInput) -> Result<SignupOutput, ServiceError> {
+        instrument(Span::new("SignupService::signup"), async move {
+            let mut errors = user_service::name_errors(&input.name, &input.display_name);
+            if !is_valid_email(&input.email) {
+                errors.push(FieldError::new("email", "invalid email"));
+            }
+            if input.password.chars().count() < MIN_PASSWORD_LENGTH {
+                errors.push(FieldError::new(
+                    "password",
+                    format!("must be at least {} characters", MIN_PASSWORD_LENGTH),
+                ));
+            }
+            if !errors.is_empty() {
+                return Err(ServiceError::InvalidFields(errors));
+            }
+
+            let user_id = ulid::Ulid::new().to_string();
+            let (token, token_hash) = new_token();
+            let created = model::OutboxEvent::new(model::DomainEvent::UserCreated {
+                user_id: user_id.clone(),
+                name: input.name.clone(),
+                role: model::Role::User,
+            });
+
+            self.login_repository
+                .create_pending(
+                    model::User {
+                        id: user_id.clone(),
+                        name: input.name,
+                        display_name: input.display_name,
+                        role: model::Role::User,
+                        email: Some(input.email.clone()),
+                    },
+                    model::Login {
+                        user_id: user_id.clone(),
+                        password_hash: self.hash_manager.hash(input.password).to_string(),
+                        status: model::LoginUserStatus::PendingVerification,
+                        version: 0,
+                    },
+                    model::EmailVerification {
+                        user_id: user_id.clone(),
+                        token_hash: token_hash,
+                        expires_at: chrono::Utc::now().naive_utc()
+                            + chrono::Duration::hours(self.config.token_ttl_hours),
+                    },
+                    vec![created],
+                )
+                .await
+                .map_err(|err| match err {
+                    // either name or email
+                    DBConnectorError::UniqueViolation(_) => ServiceError::Conflict(
+                        "user_already_exists",
+                        failure::err_msg("the user name or email is already registered"),
+                    ),
+                    err => ServiceError::DBError(err),
+                })?;
+
+            // the account stays pending if this fails, until a resend succeeds
+            self.send_verification(input.email, token).await?;
+
+            Ok(SignupOutput { user_id: user_id })
+        })
+        .await
+    }
+
+    // Issues a new token to a pending account, e.g. when the first mail was lost or expired
+    // Succeeds whether or not there is such an account, so that it does not reveal the emails,
+    // and whether or not the address is over MAX_RESENDS
+    pub async fn resend(&self, input: ResendVerificationInput) -> Result<(), ServiceError> {
+        instrument(Span::new("SignupService::resend"), async move {
+            // not throttled while the store is unavailable
+            let key = format!("signup_resend:{}", input.email.to_lowercase());
+            match self.kv_store.incr(key, RESEND_WINDOW).await {
+                Ok(count) if count > MAX_RESENDS => return Ok(()),
+                Ok(_) => (),
+                Err(err) => warn!("Failed to count the verification mails: {}", err),
+            }
+
+            let (token, token_hash) = new_token();
+            let renewed = self
+                .login_repository
+                .renew_verification(
+                    input.email.clone(),
+                    token_hash,
+                    chrono::Utc::now().naive_utc()
+                        + chrono::Duration::hours(self.config.token_ttl_hours),
+                )
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            if renewed {
+                self.send_verification(input.email, token).await?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn send_verification(&self, email: String, token: String) -> Result<(), ServiceError> {
+        self.mailer
+            .send(Mail {
+                to: email,
+                subject: "Verify your email address".to_owned(),
+                body: format!(
+                    "Open the link below to verify your email address:\n\n{}?token={}\n",
+                    self.config.verification_url, token
+                ),
+            })
+            .await
+            .map_err(ServiceError::GeneralError)
+    }
+
+    pub async fn verify(&self, input: VerifyEmailInput) -> Result<(), ServiceError> {
+        instrument(Span::new("SignupService::verify"), async move {
+            let verification = self
+                .login_repository
+                .get_verification(hash_token(&input.token))
+                .await
+                .map_err(ServiceError::DBError)?
+                .ok_or_else(|| invalid_request("invalid token"))?;
+
+            if verification.is_expired(chrono::Utc::now().naive_utc()) {
+                return Err(invalid_request("token is expired"));
+            }
+
+            let enabled = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+                user_id: verification.user_id.clone(),
+            });
+            // the token was used meanwhile, or the user deleted
+            self.login_repository
+                .complete_verification(verification.user_id, vec![enabled])
+                .await
+                .map_err(|err| match err {
+                    DBConnectorError::NotFound => invalid_request("invalid token"),
+                    err => ServiceError::DBError(err),
+                })
+        })
+        .await
+    }
+}
+
+#[test]
+fn it_should_validate_email() {
+    assert!(is_valid_email("user@example.com"));
+    assert!(!is_valid_email("user.example.com"));
+    assert!(!is_valid_email("@example.com"));
+    assert!(!is_valid_email(
+        "user@example.com\r\nBcc: other@example.com"
+    ));
+}
+
+#[test]
+fn token_hash_should_be_stable() {
+    let (token, hash) = new_token();
+    assert_eq!(64, token.len());
+    assert_eq!(hash, hash_token(&token));
+}
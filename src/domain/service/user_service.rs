@@ -1,43 +1,540 @@
-use crate::domain::interface::IUserRepository;
+use crate::domain::interface::{
+    IAuditLog, IHashManager, ITransaction, IUnitOfWork, IUserRepository,
+};
 use crate::domain::model;
-use crate::error::ServiceError;
+use crate::domain::service::audit_service;
+use crate::domain::service::pagination::{paginate, split_page};
+use crate::error::{FieldError, ServiceError};
+use crate::infra::DBConnectorError;
+use crate::infra::{instrument, Span};
 use serde::*;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct UserService {
     user_repository: Arc<dyn IUserRepository + Sync + Send>,
+    unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+    hash_manager: Arc<dyn IHashManager + Sync + Send>,
+    audit_log: Arc<dyn IAuditLog + Sync + Send>,
 }
 
 #[derive(Deserialize)]
 pub struct UserCreateInput {
     pub name: String,
     pub display_name: String,
+    // the login is created with the user when given
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UserUpdateInput {
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub role: Option<String>,
+}
+
+impl UserUpdateInput {
+    // for the audit log, e.g. "name, role=admin" (the values of names are personal data)
+    fn changes(&self) -> String {
+        let mut changes = Vec::new();
+        if self.name.is_some() {
+            changes.push("name".to_owned());
+        }
+        if self.display_name.is_some() {
+            changes.push("display_name".to_owned());
+        }
+        if let Some(role) = &self.role {
+            changes.push(format!("role={}", role));
+        }
+        changes.join(", ")
+    }
+}
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+
+#[derive(Deserialize, Default)]
+pub struct UserListInput {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub role: Option<String>,
+    pub name_prefix: Option<String>,
+    #[serde(default)]
+    pub sort: model::UserSort,
+    // counting is a full scan of the filtered rows, so only do it when asked
+    #[serde(default)]
+    pub with_total: bool,
+}
+
+// the sizes of the columns
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_DISPLAY_NAME_LENGTH: usize = 128;
+
+// Also checks the users who sign up
+pub(super) fn name_errors(name: &str, display_name: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        errors.push(FieldError::new(
+            "name",
+            format!("must be 1 to {} characters", MAX_NAME_LENGTH),
+        ));
+    } else if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        errors.push(FieldError::new("name", "must not contain spaces"));
+    }
+    if display_name.trim().is_empty() || display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        errors.push(FieldError::new(
+            "display_name",
+            format!("must be 1 to {} characters", MAX_DISPLAY_NAME_LENGTH),
+        ));
+    } else if display_name.chars().any(|c| c.is_control()) {
+        errors.push(FieldError::new(
+            "display_name",
+            "must not contain control characters",
+        ));
+    }
+    errors
+}
+
+// `name` is the only unique column which can be set through UserService
+fn name_conflict(err: DBConnectorError) -> ServiceError {
+    match err {
+        DBConnectorError::UniqueViolation(_) => ServiceError::Conflict(
+            "user_name_taken",
+            failure::err_msg("the user name is already taken"),
+        ),
+        err => ServiceError::DBError(err),
+    }
+}
+
+// A typo must not be saved as Role::Unknown, which would demote the user
+fn parse_role(role: &str) -> Result<model::Role, ServiceError> {
+    model::Role::parse(role).ok_or_else(|| {
+        ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(format_err!(
+            "role must be one of unknown, user, power_user or admin, got {:?}",
+            role
+        ))))
+    })
+}
+
+fn user_not_found() -> ServiceError {
+    ServiceError::NotFound(failure::err_msg("user not found"))
 }
 
 impl UserService {
-    pub fn new(user_repository: Arc<dyn IUserRepository + Sync + Send>) -> UserService {
+    pub fn new(
+        user_repository: Arc<dyn IUserRepository + Sync + Send>,
+        unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+        hash_manager: Arc<dyn IHashManager + Sync + Send>,
+        audit_log: Arc<dyn IAuditLog + Sync + Send>,
+    ) -> UserService {
         UserService {
             user_repository: user_repository,
+            unit_of_work: unit_of_work,
+            hash_manager: hash_manager,
+            audit_log: audit_log,
         }
     }
 
-    pub async fn create(&self, input: UserCreateInput) -> Result<(), ServiceError> {
-        self.user_repository
-            .save(model::User {
-                id: ulid::Ulid::new().to_string(),
-                name: input.name,
-                display_name: input.display_name,
-                role: model::Role::Unknown,
-            })
+    // Failures are recorded too, with the error as the detail
+    async fn audit<T>(
+        &self,
+        entry: model::AuditEntry,
+        result: Result<T, ServiceError>,
+    ) -> Result<T, ServiceError> {
+        let entry = match &result {
+            Ok(_) => entry,
+            Err(err) => entry.failed(err),
+        };
+        audit_service::record(self.audit_log.as_ref(), entry).await;
+
+        result
+    }
+
+    pub async fn create(
+        &self,
+        actor: model::Actor,
+        input: UserCreateInput,
+    ) -> Result<model::User, ServiceError> {
+        instrument(Span::new("UserService::create"), async move {
+            let user_id = ulid::Ulid::new().to_string();
+            let entry = model::AuditEntry::new(
+                &actor,
+                model::AuditAction::UserCreated,
+                Some(user_id.clone()),
+            );
+            let result = self.create_user(user_id, input).await;
+
+            self.audit(entry, result).await
+        })
+        .await
+    }
+
+    async fn create_user(
+        &self,
+        user_id: String,
+        input: UserCreateInput,
+    ) -> Result<model::User, ServiceError> {
+        let errors = name_errors(&input.name, &input.display_name);
+        if !errors.is_empty() {
+            return Err(ServiceError::InvalidFields(errors));
+        }
+
+        let user = model::User {
+            id: user_id,
+            name: input.name,
+            display_name: input.display_name,
+            role: model::Role::Unknown,
+            email: None,
+        };
+
+        let login = input.password.map(|password| model::Login {
+            user_id: user.id.clone(),
+            password_hash: self.hash_manager.hash(password).to_string(),
+            status: model::LoginUserStatus::Enabled,
+            version: 0,
+        });
+        let saved = user.clone();
+
+        // the events are only published if the user is saved
+        self.unit_of_work
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                let user_id = saved.id.clone();
+                tx.outbox()
+                    .append(model::OutboxEvent::new(model::DomainEvent::UserCreated {
+                        user_id: user_id.clone(),
+                        name: saved.name.clone(),
+                        role: saved.role.clone(),
+                    }))?;
+                tx.users().save(saved)?;
+
+                if let Some(login) = login {
+                    tx.logins().create(login)?;
+                    tx.outbox().append(model::OutboxEvent::new(
+                        model::DomainEvent::LoginEnabled { user_id: user_id },
+                    ))?;
+                }
+
+                Ok(())
+            }))
             .await
-            .map_err(ServiceError::DBError)
+            .map_err(name_conflict)?;
+
+        Ok(user)
     }
 
-    pub async fn list(&self) -> Result<Vec<model::User>, ServiceError> {
-        self.user_repository
-            .list()
+    pub async fn list(&self, input: UserListInput) -> Result<model::UserList, ServiceError> {
+        instrument(Span::new("UserService::list"), async move {
+            let limit = paginate(input.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+
+            let cursor = match input.cursor {
+                Some(c) => Some(model::UserCursor::decode(&c).ok_or_else(|| {
+                    ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(
+                        failure::err_msg("invalid cursor"),
+                    )))
+                })?),
+                None => None,
+            };
+            let role = match input.role {
+                Some(r) => Some(parse_role(&r)?),
+                None => None,
+            };
+            let filter = model::UserFilter {
+                role: role,
+                name_prefix: input.name_prefix,
+            };
+
+            let total = if input.with_total {
+                Some(
+                    self.user_repository
+                        .count(filter.clone())
+                        .await
+                        .map_err(ServiceError::DBError)?,
+                )
+            } else {
+                None
+            };
+
+            let items = self
+                .user_repository
+                .list(model::UserListQuery {
+                    filter: filter,
+                    sort: input.sort,
+                    cursor: cursor,
+                    limit: limit + 1,
+                })
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            let (items, next_cursor) =
+                split_page(items, limit, |u| model::UserCursor::from_user(u).encode());
+
+            Ok(model::UserList {
+                items: items,
+                next_cursor: next_cursor,
+                total: total,
+            })
+        })
+        .await
+    }
+
+    pub async fn get(&self, user_id: String) -> Result<model::User, ServiceError> {
+        instrument(Span::new("UserService::get"), async move {
+            self.user_repository
+                .get_by_id(user_id)
+                .await
+                .map_err(ServiceError::DBError)?
+                .ok_or_else(user_not_found)
+        })
+        .await
+    }
+
+    pub async fn update(
+        &self,
+        actor: model::Actor,
+        user_id: String,
+        input: UserUpdateInput,
+    ) -> Result<model::User, ServiceError> {
+        instrument(Span::new("UserService::update"), async move {
+            let mut entry = model::AuditEntry::new(
+                &actor,
+                model::AuditAction::UserUpdated,
+                Some(user_id.clone()),
+            );
+            let changes = input.changes();
+            if !changes.is_empty() {
+                entry = entry.detail(changes);
+            }
+            let result = self.update_user(user_id, input).await;
+
+            self.audit(entry, result).await
+        })
+        .await
+    }
+
+    async fn update_user(
+        &self,
+        user_id: String,
+        input: UserUpdateInput,
+    ) -> Result<model::User, ServiceError> {
+        // read from the primary, so that a stale replica cannot undo a recent update
+        let mut user = self
+            .user_repository
+            .get_by_id_from_primary(user_id)
+            .await
+            .map_err(ServiceError::DBError)?
+            .ok_or_else(user_not_found)?;
+        let previous_role = user.role.clone();
+
+        if let Some(name) = input.name {
+            user.name = name;
+        }
+        if let Some(display_name) = input.display_name {
+            user.display_name = display_name;
+        }
+        if let Some(role) = input.role {
+            user.role = parse_role(&role)?;
+        }
+
+        let updated = user.clone();
+        self.unit_of_work
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                if updated.role != previous_role {
+                    tx.outbox().append(model::OutboxEvent::new(
+                        model::DomainEvent::RoleChanged {
+                            user_id: updated.id.clone(),
+                            from: previous_role,
+                            to: updated.role.clone(),
+                        },
+                    ))?;
+                }
+                tx.users().update(updated)
+            }))
             .await
-            .map_err(ServiceError::DBError)
+            .map_err(name_conflict)?;
+
+        Ok(user)
+    }
+
+    // purge also removes the user's login (and is irreversible)
+    pub async fn delete(
+        &self,
+        actor: model::Actor,
+        user_id: String,
+        purge: bool,
+    ) -> Result<(), ServiceError> {
+        instrument(Span::new("UserService::delete"), async move {
+            let action = if purge {
+                model::AuditAction::UserPurged
+            } else {
+                model::AuditAction::UserDeleted
+            };
+            let entry = model::AuditEntry::new(&actor, action, Some(user_id.clone()));
+
+            let result = if purge {
+                self.user_repository.purge(user_id).await
+            } else {
+                self.user_repository.delete(user_id).await
+            };
+            let result = match result {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(user_not_found()),
+                Err(err) => Err(ServiceError::DBError(err)),
+            };
+
+            self.audit(entry, result).await
+        })
+        .await
     }
 }
+
+#[test]
+fn names_should_fit_their_columns() {
+    assert!(name_errors("alice", "Alice A.").is_empty());
+    assert_eq!(1, name_errors("", "Alice").len());
+    assert_eq!(1, name_errors("alice smith", "Alice").len());
+    assert_eq!(1, name_errors(&"a".repeat(65), "Alice").len());
+    assert_eq!(1, name_errors("alice", " ").len());
+    assert_eq!(2, name_errors("alice\n", "Alice\u{0}").len());
+}
+
+#[test]
+fn update_should_reject_taken_names_and_unknown_roles() {
+    use crate::serviceclient::memory::*;
+
+    let store = MemoryStore::new();
+    let service = UserService::new(
+        Arc::new(MemoryUserRepository::new(store.clone())),
+        Arc::new(MemoryUnitOfWork::new(store.clone())),
+        Arc::new(crate::infra::HashManager::new()),
+        Arc::new(MemoryAuditLog::new(store.clone())),
+    );
+    let input = |name: &str| UserCreateInput {
+        name: name.to_owned(),
+        display_name: name.to_owned(),
+        password: None,
+    };
+
+    futures::executor::block_on(async {
+        let alice = service
+            .create(model::Actor::default(), input("alice"))
+            .await
+            .unwrap();
+        service
+            .create(model::Actor::default(), input("bob"))
+            .await
+            .unwrap();
+
+        let renamed = UserUpdateInput {
+            name: Some("bob".to_owned()),
+            display_name: None,
+            role: None,
+        };
+        match service
+            .update(model::Actor::default(), alice.id.clone(), renamed)
+            .await
+        {
+            Err(ServiceError::Conflict("user_name_taken", _)) => (),
+            _ => panic!("expected a conflict"),
+        }
+
+        // the failed update is in the audit log
+        let updates = MemoryAuditLog::new(store)
+            .list(model::AuditQuery {
+                filter: model::AuditFilter {
+                    target_id: Some(alice.id.clone()),
+                    action: Some(model::AuditAction::UserUpdated),
+                    ..Default::default()
+                },
+                before_id: None,
+                limit: 10,
+            })
+            .await
+            .unwrap();
+        assert_eq!(1, updates.len());
+        assert_eq!(model::AuditOutcome::Failure, updates[0].outcome);
+        assert!(updates[0]
+            .detail
+            .as_ref()
+            .unwrap()
+            .contains("already taken"));
+
+        // a typo is not saved as Role::Unknown
+        let typo = UserUpdateInput {
+            name: None,
+            display_name: None,
+            role: Some("adminn".to_owned()),
+        };
+        match service
+            .update(model::Actor::default(), alice.id.clone(), typo)
+            .await
+        {
+            Err(ServiceError::InvalidRequest(_)) => (),
+            _ => panic!("expected the role to be rejected"),
+        }
+        assert_eq!(
+            model::Role::Unknown,
+            service.get(alice.id).await.unwrap().role
+        );
+    });
+}
+
+#[test]
+fn changes_should_be_published_as_events() {
+    use crate::domain::interface::IOutboxRepository;
+    use crate::serviceclient::memory::*;
+
+    let store = MemoryStore::new();
+    let service = UserService::new(
+        Arc::new(MemoryUserRepository::new(store.clone())),
+        Arc::new(MemoryUnitOfWork::new(store.clone())),
+        Arc::new(crate::infra::HashManager::new()),
+        Arc::new(MemoryAuditLog::new(store.clone())),
+    );
+
+    futures::executor::block_on(async {
+        let alice = service
+            .create(
+                model::Actor::default(),
+                UserCreateInput {
+                    name: "alice".to_owned(),
+                    display_name: "Alice".to_owned(),
+                    password: Some("password".to_owned()),
+                },
+            )
+            .await
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2)); // ids are only ordered to the millisecond
+
+        let update = |role: Option<&str>| UserUpdateInput {
+            name: None,
+            display_name: Some("Alice A.".to_owned()),
+            role: role.map(|r| r.to_owned()),
+        };
+        service
+            .update(model::Actor::default(), alice.id.clone(), update(None))
+            .await
+            .unwrap();
+        service
+            .update(
+                model::Actor::default(),
+                alice.id.clone(),
+                update(Some("admin")),
+            )
+            .await
+            .unwrap();
+
+        let events = MemoryOutboxRepository::new(store)
+            .list_due(chrono::Utc::now().naive_utc(), 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.event.event_type())
+            .collect::<Vec<_>>();
+        assert_eq!(3, events.len());
+        // the events of one change share the millisecond
+        assert!(events[..2].contains(&"user_created"));
+        assert!(events[..2].contains(&"login_enabled"));
+        assert_eq!("role_changed", events[2]);
+    });
+}
@@ -0,0 +1,515 @@
+use super::event_dispatcher::{backoff, CLAIM_LEASE_SECS};
+use super::pagination::{paginate, split_page};
+use crate::domain::interface::{IEventSink, IWebhookClient, IWebhookRepository};
+use crate::domain::model;
+use crate::error::ServiceError;
+use crate::infra::{self, instrument, DBConnectorError, Span};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::*;
+use std::sync::Arc;
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+// deliveries attempted per deliver_due
+const BATCH_SIZE: i64 = 100;
+// endpoints deliver_due sends to at the same time
+const CONCURRENT_ENDPOINTS: usize = 10;
+
+#[derive(Deserialize)]
+pub struct WebhookEndpointInput {
+    // http or https
+    pub url: String,
+    pub secret: String,
+    // see model::EVENT_TYPES
+    pub event_types: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct WebhookDeliveryListInput {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    // pending, delivered or dead
+    pub status: Option<String>,
+}
+
+fn invalid(message: &'static str) -> ServiceError {
+    ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(failure::err_msg(
+        message,
+    ))))
+}
+
+fn endpoint_not_found() -> ServiceError {
+    ServiceError::NotFound(failure::err_msg("webhook not found"))
+}
+
+fn delivery_not_found() -> ServiceError {
+    ServiceError::NotFound(failure::err_msg("delivery not found"))
+}
+
+// The webhooks registered by admins: the fan-out of the events into one delivery per endpoint
+// (as the sink of EventDispatcher), and the queue which sends the deliveries
+#[derive(Clone)]
+pub struct WebhookService {
+    webhook_repository: Arc<dyn IWebhookRepository + Sync + Send>,
+    webhook_client: Arc<dyn IWebhookClient + Sync + Send>,
+    max_attempts: u32,
+    // see infra::check_webhook_url
+    allowed_hosts: Vec<String>,
+}
+
+impl WebhookService {
+    pub fn new(
+        webhook_repository: Arc<dyn IWebhookRepository + Sync + Send>,
+        webhook_client: Arc<dyn IWebhookClient + Sync + Send>,
+        max_attempts: u32,
+        allowed_hosts: Vec<String>,
+    ) -> WebhookService {
+        WebhookService {
+            webhook_repository: webhook_repository,
+            webhook_client: webhook_client,
+            max_attempts: max_attempts,
+            allowed_hosts: allowed_hosts,
+        }
+    }
+
+    pub async fn create_endpoint(
+        &self,
+        input: WebhookEndpointInput,
+    ) -> Result<model::WebhookEndpoint, ServiceError> {
+        instrument(Span::new("WebhookService::create_endpoint"), async move {
+            infra::check_webhook_url(&input.url, &self.allowed_hosts).map_err(|err| {
+                ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(err)))
+            })?;
+            if input.secret.is_empty() {
+                return Err(invalid("secret must not be empty"));
+            }
+            if input.event_types.is_empty() {
+                return Err(invalid("event_types must not be empty"));
+            }
+            if let Some(unknown) = input
+                .event_types
+                .iter()
+                .find(|t| !model::EVENT_TYPES.contains(&t.as_str()))
+            {
+                return Err(ServiceError::InvalidRequest(Box::new(
+                    ServiceError::GeneralError(format_err!("unknown event type {}", unknown)),
+                )));
+            }
+
+            let mut event_types = input.event_types;
+            event_types.sort();
+            event_types.dedup();
+            let endpoint = model::WebhookEndpoint {
+                id: ulid::Ulid::new().to_string(),
+                url: input.url,
+                secret: input.secret,
+                event_types: event_types,
+                created_at: chrono::Utc::now().naive_utc(),
+            };
+
+            self.webhook_repository
+                .create_endpoint(endpoint.clone())
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            Ok(endpoint)
+        })
+        .await
+    }
+
+    pub async fn list_endpoints(&self) -> Result<Vec<model::WebhookEndpoint>, ServiceError> {
+        instrument(Span::new("WebhookService::list_endpoints"), async move {
+            self.webhook_repository
+                .list_endpoints()
+                .await
+                .map_err(ServiceError::DBError)
+        })
+        .await
+    }
+
+    // Pending deliveries of the endpoint are dropped with it
+    pub async fn delete_endpoint(&self, endpoint_id: String) -> Result<(), ServiceError> {
+        instrument(Span::new("WebhookService::delete_endpoint"), async move {
+            let deleted = self
+                .webhook_repository
+                .delete_endpoint(endpoint_id)
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            if deleted {
+                Ok(())
+            } else {
+                Err(endpoint_not_found())
+            }
+        })
+        .await
+    }
+
+    pub async fn list_deliveries(
+        &self,
+        endpoint_id: String,
+        input: WebhookDeliveryListInput,
+    ) -> Result<model::WebhookDeliveryList, ServiceError> {
+        instrument(Span::new("WebhookService::list_deliveries"), async move {
+            let limit = paginate(input.limit, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT)?;
+            let status = match input.status {
+                Some(s) => Some(
+                    model::WebhookDeliveryStatus::new_from_str(&s)
+                        .ok_or_else(|| invalid("status must be pending, delivered or dead"))?,
+                ),
+                None => None,
+            };
+
+            self.webhook_repository
+                .get_endpoint(endpoint_id.clone())
+                .await
+                .map_err(ServiceError::DBError)?
+                .ok_or_else(endpoint_not_found)?;
+
+            let items = self
+                .webhook_repository
+                .list_deliveries(model::WebhookDeliveryQuery {
+                    endpoint_id: endpoint_id,
+                    status: status,
+                    before_id: input.cursor,
+                    limit: limit + 1,
+                })
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            let (items, next_cursor) = split_page(items, limit, |d| d.id.clone());
+
+            Ok(model::WebhookDeliveryList {
+                items: items,
+                next_cursor: next_cursor,
+            })
+        })
+        .await
+    }
+
+    // Queues the delivery again with a fresh set of attempts, whatever its status
+    pub async fn retry_delivery(
+        &self,
+        endpoint_id: String,
+        delivery_id: String,
+    ) -> Result<model::WebhookDelivery, ServiceError> {
+        instrument(Span::new("WebhookService::retry_delivery"), async move {
+            let mut delivery = self
+                .webhook_repository
+                .get_delivery(delivery_id)
+                .await
+                .map_err(ServiceError::DBError)?
+                .filter(|d| d.endpoint_id == endpoint_id)
+                .ok_or_else(delivery_not_found)?;
+
+            let now = chrono::Utc::now().naive_utc();
+            delivery.status = model::WebhookDeliveryStatus::Pending;
+            delivery.attempts = 0;
+            delivery.next_attempt_at = now;
+            delivery.updated_at = now;
+
+            self.webhook_repository
+                .update_delivery(delivery.clone())
+                .await
+                .map_err(ServiceError::DBError)?;
+
+            Ok(delivery)
+        })
+        .await
+    }
+
+    // Sends the deliveries due at `now`, and returns how many succeeded
+    // A failed delivery is retried with exponential backoff, and is dead after max_attempts
+    // Endpoints are sent to concurrently, so that a slow one only holds back its own deliveries
+    pub async fn deliver_due(&self, now: chrono::NaiveDateTime) -> Result<usize, DBConnectorError> {
+        instrument(Span::new("WebhookService::deliver_due"), async move {
+            let lease_until = now + chrono::Duration::seconds(CLAIM_LEASE_SECS);
+            let due = self
+                .webhook_repository
+                .claim_due(now, lease_until, BATCH_SIZE)
+                .await?;
+
+            let mut by_endpoint: Vec<(model::WebhookEndpoint, Vec<model::WebhookDelivery>)> =
+                Vec::new();
+            for (delivery, endpoint) in due {
+                match by_endpoint.iter_mut().find(|(e, _)| e.id == endpoint.id) {
+                    Some((_, deliveries)) => deliveries.push(delivery),
+                    None => by_endpoint.push((endpoint, vec![delivery])),
+                }
+            }
+
+            let results = futures::stream::iter(by_endpoint)
+                .map(|(endpoint, deliveries)| self.deliver_to(now, endpoint, deliveries))
+                .buffer_unordered(CONCURRENT_ENDPOINTS)
+                .collect::<Vec<_>>()
+                .await;
+
+            let mut delivered = 0;
+            for result in results {
+                delivered += result?;
+            }
+
+            Ok(delivered)
+        })
+        .await
+    }
+
+    // The deliveries of one endpoint, in order
+    // Stops at the first one the endpoint does not answer (e.g. a timeout): the others were not
+    // attempted, and are released to be due again at the next call
+    async fn deliver_to(
+        &self,
+        now: chrono::NaiveDateTime,
+        endpoint: model::WebhookEndpoint,
+        deliveries: Vec<model::WebhookDelivery>,
+    ) -> Result<usize, DBConnectorError> {
+        let mut delivered = 0;
+        let mut deliveries = deliveries.into_iter();
+        while let Some(mut delivery) = deliveries.next() {
+            let result = self
+                .webhook_client
+                .post(
+                    endpoint.url.clone(),
+                    endpoint.secret.clone(),
+                    delivery.clone(),
+                )
+                .await;
+
+            delivery.attempts += 1;
+            delivery.updated_at = chrono::Utc::now().naive_utc();
+            let unreachable = result.is_err();
+            let error = match result {
+                Ok(code) => {
+                    delivery.last_status_code = Some(i32::from(code));
+                    if code >= 200 && code < 300 {
+                        None
+                    } else {
+                        Some(format!("answered {}", code))
+                    }
+                }
+                Err(err) => {
+                    delivery.last_status_code = None;
+                    Some(err.to_string())
+                }
+            };
+
+            match error {
+                None => {
+                    delivery.status = model::WebhookDeliveryStatus::Delivered;
+                    delivery.last_error = None;
+                    delivered += 1;
+                }
+                Some(error) if delivery.attempts as u32 >= self.max_attempts => {
+                    error!(
+                        "Giving up on the webhook delivery {}: {}",
+                        delivery.id, error
+                    );
+                    delivery.status = model::WebhookDeliveryStatus::Dead;
+                    delivery.last_error = Some(error);
+                }
+                Some(error) => {
+                    warn!("Failed to deliver the webhook {}: {}", delivery.id, error);
+                    delivery.next_attempt_at = now + backoff(delivery.attempts - 1);
+                    delivery.last_error = Some(error);
+                }
+            }
+
+            self.webhook_repository.update_delivery(delivery).await?;
+            if unreachable {
+                // as claimed, with the next attempt they had
+                for unattempted in deliveries.by_ref() {
+                    self.webhook_repository.update_delivery(unattempted).await?;
+                }
+                break;
+            }
+        }
+
+        Ok(delivered)
+    }
+}
+
+#[async_trait]
+impl IEventSink for WebhookService {
+    // one delivery per subscribed endpoint, sent later by deliver_due
+    async fn deliver(&self, event: model::OutboxEvent) -> Result<(), failure::Error> {
+        let deliveries = self
+            .webhook_repository
+            .list_endpoints()
+            .await?
+            .into_iter()
+            .filter(|e| e.subscribes_to(event.event.event_type()))
+            .map(|e| model::WebhookDelivery::new(e.id, &event))
+            .collect::<Vec<_>>();
+
+        if !deliveries.is_empty() {
+            self.webhook_repository.enqueue(deliveries).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn deliveries_should_be_retried_until_dead() {
+    use crate::serviceclient::memory::*;
+    use futures::{FutureExt, TryFutureExt};
+
+    // the first delivery fails twice then succeeds, the second one fails for good
+    let (url, requests) = infra::start_http_stand_in(vec![500, 503, 200, 500, 500, 500]);
+    let store = MemoryStore::new();
+    let service = WebhookService::new(
+        Arc::new(MemoryWebhookRepository::new(store)),
+        Arc::new(infra::WebhookClient::new(vec!["127.0.0.1".to_owned()])),
+        3,
+        vec!["127.0.0.1".to_owned()],
+    );
+    let event = model::OutboxEvent::new(model::DomainEvent::RoleChanged {
+        user_id: "u1".to_owned(),
+        from: model::Role::User,
+        to: model::Role::Admin,
+    });
+
+    let fut = async move {
+        let subscribed = service
+            .create_endpoint(WebhookEndpointInput {
+                url: format!("{}/hook", url),
+                secret: "secret".to_owned(),
+                event_types: vec!["role_changed".to_owned()],
+            })
+            .await
+            .unwrap();
+        service
+            .create_endpoint(WebhookEndpointInput {
+                url: url.clone(),
+                secret: "secret".to_owned(),
+                event_types: vec!["user_created".to_owned()],
+            })
+            .await
+            .unwrap();
+
+        // dispatching the event twice queues it once
+        service.deliver(event.clone()).await.unwrap();
+        service.deliver(event.clone()).await.unwrap();
+
+        let mut now = chrono::Utc::now().naive_utc();
+        assert_eq!(0, service.deliver_due(now).await.unwrap());
+        assert_eq!(0, service.deliver_due(now).await.unwrap());
+        now = now + chrono::Duration::seconds(1);
+        assert_eq!(0, service.deliver_due(now).await.unwrap());
+        now = now + chrono::Duration::seconds(2);
+        assert_eq!(1, service.deliver_due(now).await.unwrap());
+
+        let log = service
+            .list_deliveries(subscribed.id.clone(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(1, log.items.len());
+        let delivery = log.items[0].clone();
+        assert_eq!(model::WebhookDeliveryStatus::Delivered, delivery.status);
+        assert_eq!(3, delivery.attempts);
+        assert_eq!(Some(200), delivery.last_status_code);
+        assert_eq!(event.id, delivery.event_id);
+
+        // a retried delivery is sent again, and is dead after the third failure
+        service
+            .retry_delivery(subscribed.id.clone(), delivery.id.clone())
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            now = now + chrono::Duration::hours(1);
+            assert_eq!(0, service.deliver_due(now).await.unwrap());
+        }
+        let dead = service
+            .list_deliveries(
+                subscribed.id.clone(),
+                WebhookDeliveryListInput {
+                    status: Some("dead".to_owned()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, dead.items.len());
+        assert_eq!(Some(500), dead.items[0].last_status_code);
+        assert_eq!(0, service.deliver_due(now).await.unwrap());
+    };
+    actix_web::test::block_on(Box::pin(fut.map(Ok::<_, ()>)).compat()).unwrap();
+
+    for _ in 0..6 {
+        let request = requests.recv().unwrap();
+        assert_eq!("/hook", request.path);
+        assert_eq!(
+            crate::infra::webhook_signature(b"secret", &request.body),
+            request.headers["x-signature-256"]
+        );
+        assert_eq!("role_changed", request.headers["x-event-type"]);
+    }
+}
+
+#[test]
+fn unreachable_endpoints_should_not_hold_back_the_others() {
+    use crate::serviceclient::memory::*;
+    use std::sync::Mutex;
+
+    struct StubClient(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl IWebhookClient for StubClient {
+        async fn post(
+            &self,
+            url: String,
+            _secret: String,
+            _delivery: model::WebhookDelivery,
+        ) -> Result<u16, failure::Error> {
+            self.0.lock().unwrap().push(url.clone());
+            if url.contains("blackhole") {
+                bail!("timed out");
+            }
+
+            Ok(200)
+        }
+    }
+
+    let client = Arc::new(StubClient(Mutex::new(Vec::new())));
+    let service = WebhookService::new(
+        Arc::new(MemoryWebhookRepository::new(MemoryStore::new())),
+        client.clone(),
+        3,
+        vec![],
+    );
+
+    futures::executor::block_on(async {
+        for url in &[
+            "https://blackhole.example.com/",
+            "https://hooks.example.com/",
+        ] {
+            service
+                .create_endpoint(WebhookEndpointInput {
+                    url: url.to_string(),
+                    secret: "secret".to_owned(),
+                    event_types: vec!["login_enabled".to_owned()],
+                })
+                .await
+                .unwrap();
+        }
+        for user_id in &["u1", "u2"] {
+            service.deliver(test_event(user_id)).await.unwrap();
+        }
+
+        let now = chrono::Utc::now().naive_utc();
+        assert_eq!(2, service.deliver_due(now).await.unwrap());
+    });
+
+    // the second delivery to the unreachable endpoint waits for the next round
+    let mut posted = client.0.lock().unwrap().clone();
+    posted.sort();
+    assert_eq!(
+        vec![
+            "https://blackhole.example.com/",
+            "https://hooks.example.com/",
+            "https://hooks.example.com/",
+        ],
+        posted
+    );
+}
@@ -1,4 +1,22 @@
 use crate::infra::DBConnectorError;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::*;
+
+#[derive(Serialize, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> FieldError {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
 
 #[derive(Fail, Debug)]
 pub enum ServiceError {
@@ -14,22 +32,221 @@ pub enum ServiceError {
     #[fail(display = "Unauthorized: {}", _0)]
     Unauthorized(failure::Error),
 
+    #[fail(display = "Not found: {}", _0)]
+    NotFound(failure::Error),
+
     #[fail(display = "Invalid request: {}", _0)]
     InvalidRequest(Box<ServiceError>),
 
+    #[fail(display = "Invalid fields: {:?}", _0)]
+    InvalidFields(Vec<FieldError>),
+
     #[fail(display = "Internal Server Error: {}", _0)]
     InternalServerError(Box<ServiceError>),
+
+    // the first field is a machine-readable code for clients
+    #[fail(display = "Conflict: {}", _1)]
+    Conflict(&'static str, failure::Error),
+}
+
+// sent with 503, when the DB is overloaded
+const RETRY_AFTER_SECS: u32 = 5;
+
+// RFC 7807 (application/problem+json) with our own extension members
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    type_: &'a str,
+    title: &'a str,
+    status: u16,
+    // stable, machine-readable
+    code: &'a str,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError],
 }
 
 impl ServiceError {
-    pub fn to_http_error(self) -> actix_web::Error {
+    pub fn status(&self) -> StatusCode {
         use ServiceError::*;
 
         match self {
-            InvalidRequest(err) => actix_web::error::ErrorBadRequest(err),
-            InternalServerError(err) => actix_web::error::ErrorInternalServerError(err),
-            Unauthorized(err) => actix_web::error::ErrorUnauthorized(err),
-            err => actix_web::error::ErrorInternalServerError(err),
+            InvalidRequest(_) | InvalidFields(_) | ParseError(_) => StatusCode::BAD_REQUEST,
+            Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            NotFound(_) | DBError(DBConnectorError::NotFound) => StatusCode::NOT_FOUND,
+            Conflict(_, _) | DBError(DBConnectorError::VersionConflict) => StatusCode::CONFLICT,
+            DBError(DBConnectorError::Pool(_))
+            | DBError(DBConnectorError::Busy)
+            | DBError(DBConnectorError::Timeout) => StatusCode::SERVICE_UNAVAILABLE,
+            DBError(_) | GeneralError(_) | InternalServerError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         }
     }
+
+    pub fn code(&self) -> &'static str {
+        use ServiceError::*;
+
+        match self {
+            InvalidRequest(_) => "invalid_request",
+            InvalidFields(_) => "invalid_fields",
+            ParseError(_) => "invalid_body",
+            Unauthorized(_) => "unauthorized",
+            NotFound(_) | DBError(DBConnectorError::NotFound) => "not_found",
+            Conflict(code, _) => *code,
+            DBError(DBConnectorError::VersionConflict) => "version_conflict",
+            DBError(DBConnectorError::Pool(_))
+            | DBError(DBConnectorError::Busy)
+            | DBError(DBConnectorError::Timeout) => "service_unavailable",
+            DBError(_) | GeneralError(_) | InternalServerError(_) => "internal_error",
+        }
+    }
+
+    // What the client is allowed to see
+    // InvalidRequest carries messages chosen by the services; what the libraries say of a
+    // malformed request is only logged
+    fn detail(&self) -> String {
+        use ServiceError::*;
+
+        match self {
+            InvalidRequest(err) => err.to_string(),
+            Unauthorized(err) | NotFound(err) | Conflict(_, err) => err.to_string(),
+            DBError(DBConnectorError::NotFound) => "not found".to_owned(),
+            DBError(DBConnectorError::VersionConflict) => {
+                "the resource was modified concurrently".to_owned()
+            }
+            DBError(DBConnectorError::Pool(_))
+            | DBError(DBConnectorError::Busy)
+            | DBError(DBConnectorError::Timeout) => {
+                "the service is busy, try again later".to_owned()
+            }
+            InvalidFields(_) => "some fields are invalid".to_owned(),
+            ParseError(_) => "the body is not valid JSON for this request".to_owned(),
+            DBError(_) | GeneralError(_) | InternalServerError(_) => {
+                "an internal error occurred".to_owned()
+            }
+        }
+    }
+}
+
+impl actix_web::ResponseError for ServiceError {
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status();
+        // the request span is current while the handler is polled (see web::Tracing)
+        let request_id = crate::infra::current_span().map(|s| s.request_id());
+
+        if status.is_server_error() {
+            error!(
+                "[{}] {}",
+                request_id.as_ref().map(|s| s.as_str()).unwrap_or("-"),
+                failure::Fail::iter_chain(self)
+                    .map(|f| f.to_string())
+                    .collect::<Vec<_>>()
+                    .join(": caused by: ")
+            );
+        }
+
+        if let ServiceError::ParseError(err) = self {
+            warn!(
+                "[{}] Malformed body: {}",
+                request_id.as_ref().map(|s| s.as_str()).unwrap_or("-"),
+                err
+            );
+        }
+
+        let errors = match self {
+            ServiceError::InvalidFields(errors) => errors.as_slice(),
+            _ => &[],
+        };
+
+        let mut response = HttpResponse::build(status);
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            response.header(
+                actix_web::http::header::RETRY_AFTER,
+                RETRY_AFTER_SECS.to_string(),
+            );
+        }
+
+        response.content_type("application/problem+json").body(
+            serde_json::to_string(&Problem {
+                type_: "about:blank",
+                title: status.canonical_reason().unwrap_or(""),
+                status: status.as_u16(),
+                code: self.code(),
+                detail: self.detail(),
+                request_id: request_id,
+                errors: errors,
+            })
+            .unwrap(),
+        )
+    }
+
+    // the default one replaces the body with the Display text, which may leak internals
+    fn render_response(&self) -> HttpResponse {
+        self.error_response()
+    }
+}
+
+#[test]
+fn internal_errors_should_hide_the_cause() {
+    let err = ServiceError::GeneralError(failure::err_msg("secret table name"));
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, err.status());
+    assert_eq!("internal_error", err.code());
+    assert!(!err.detail().contains("secret"));
+}
+
+#[test]
+fn parse_errors_should_hide_the_serde_text() {
+    let err = ServiceError::ParseError(
+        serde_json::from_str::<crate::domain::model::Role>("\"secret_role\"")
+            .err()
+            .unwrap(),
+    );
+    assert_eq!(StatusCode::BAD_REQUEST, err.status());
+    assert!(!err.detail().contains("secret_role"));
+}
+
+#[test]
+fn missing_record_should_be_not_found() {
+    let err = ServiceError::DBError(DBConnectorError::NotFound);
+    assert_eq!(StatusCode::NOT_FOUND, err.status());
+    assert_eq!("not_found", err.code());
+}
+
+#[test]
+fn conflict_should_carry_its_code() {
+    let err = ServiceError::Conflict("user_name_taken", failure::err_msg("taken"));
+    assert_eq!(StatusCode::CONFLICT, err.status());
+    assert_eq!("user_name_taken", err.code());
+}
+
+#[test]
+fn pool_errors_should_be_unavailable() {
+    use actix_web::ResponseError;
+
+    let err = ServiceError::DBError(DBConnectorError::Pool(
+        diesel::r2d2::Pool::builder()
+            .connection_timeout(std::time::Duration::from_millis(1))
+            .build_unchecked(
+                diesel::r2d2::ConnectionManager::<crate::infra::DBConnection>::new(
+                    crate::infra::UNREACHABLE_DATABASE_URL,
+                ),
+            )
+            .get()
+            .err()
+            .unwrap(),
+    ));
+    let response = err.error_response();
+
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+    assert_eq!(
+        "5",
+        response
+            .headers()
+            .get(actix_web::http::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap()
+    );
 }
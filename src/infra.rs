@@ -1,9 +1,23 @@
+mod backend;
+mod cache;
 mod connection_pool;
 mod db_executor;
+mod event_sink;
 mod hash_manager;
 mod jwt_handler;
+mod kv_store;
+mod mailer;
+mod replica;
+mod tracer;
 
+pub use backend::*;
+pub use cache::*;
 pub use connection_pool::*;
 pub use db_executor::*;
+pub use event_sink::*;
 pub use hash_manager::*;
 pub use jwt_handler::*;
+pub use kv_store::*;
+pub use mailer::*;
+pub use replica::*;
+pub use tracer::*;
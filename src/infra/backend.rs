@@ -0,0 +1,95 @@
+// The database is chosen at build time, with exactly one of the `mysql` (default), `postgres`
+// and `sqlite` features; the rest of the crate only refers to the aliases below
+use std::time::Duration;
+
+#[cfg(any(
+    all(feature = "mysql", feature = "postgres"),
+    all(feature = "mysql", feature = "sqlite"),
+    all(feature = "postgres", feature = "sqlite"),
+))]
+compile_error!("enable only one of the `mysql`, `postgres` and `sqlite` features");
+
+#[cfg(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")))]
+compile_error!("enable one of the `mysql`, `postgres` and `sqlite` features");
+
+#[cfg(feature = "mysql")]
+pub type DBConnection = diesel::MysqlConnection;
+#[cfg(feature = "mysql")]
+pub type DBBackend = diesel::mysql::Mysql;
+
+#[cfg(feature = "postgres")]
+pub type DBConnection = diesel::PgConnection;
+#[cfg(feature = "postgres")]
+pub type DBBackend = diesel::pg::Pg;
+
+#[cfg(feature = "sqlite")]
+pub type DBConnection = diesel::SqliteConnection;
+#[cfg(feature = "sqlite")]
+pub type DBBackend = diesel::sqlite::Sqlite;
+
+// Bounds the statements of a session
+// MySQL only applies max_execution_time to read-only SELECTs
+#[cfg(feature = "mysql")]
+pub fn statement_timeout(timeout: Duration) -> String {
+    format!("SET SESSION max_execution_time = {}", timeout.as_millis())
+}
+
+#[cfg(feature = "postgres")]
+pub fn statement_timeout(timeout: Duration) -> String {
+    format!("SET statement_timeout = {}", timeout.as_millis())
+}
+
+// SQLite cannot abort a running statement; this only bounds the wait for a locked database
+#[cfg(feature = "sqlite")]
+pub fn statement_timeout(timeout: Duration) -> String {
+    format!("PRAGMA busy_timeout = {}", timeout.as_millis())
+}
+
+// The id of a connection's session on the server, for cancel_statement
+#[cfg(feature = "mysql")]
+pub const BACKEND_ID: Option<&str> = Some("SELECT CAST(CONNECTION_ID() AS SIGNED) AS id");
+#[cfg(feature = "postgres")]
+pub const BACKEND_ID: Option<&str> = Some("SELECT CAST(pg_backend_pid() AS BIGINT) AS id");
+// there is no session to cancel from another connection: a running statement runs to its end
+#[cfg(feature = "sqlite")]
+pub const BACKEND_ID: Option<&str> = None;
+
+// Aborts the statement the session is running, if any, and leaves the session open
+#[cfg(feature = "mysql")]
+pub fn cancel_statement(backend_id: i64) -> String {
+    format!("KILL QUERY {}", backend_id)
+}
+
+#[cfg(feature = "postgres")]
+pub fn cancel_statement(backend_id: i64) -> String {
+    format!("SELECT pg_cancel_backend({})", backend_id)
+}
+
+// never run, since BACKEND_ID is None
+#[cfg(feature = "sqlite")]
+pub fn cancel_statement(_backend_id: i64) -> String {
+    unreachable!("SQLite statements cannot be cancelled")
+}
+
+// Run on every new connection, before DB_INIT_SQL
+#[cfg(not(feature = "sqlite"))]
+pub fn connection_init(query_timeout: Duration) -> String {
+    statement_timeout(query_timeout)
+}
+
+// foreign keys are off by default in SQLite (e.g. purging a user would leave its login behind)
+#[cfg(feature = "sqlite")]
+pub fn connection_init(query_timeout: Duration) -> String {
+    format!(
+        "PRAGMA foreign_keys = ON; {}",
+        statement_timeout(query_timeout)
+    )
+}
+
+// Nothing listens there, for the tests of the connection errors
+#[cfg(all(test, feature = "mysql"))]
+pub const UNREACHABLE_DATABASE_URL: &str = "mysql://localhost:1/db";
+#[cfg(all(test, feature = "postgres"))]
+pub const UNREACHABLE_DATABASE_URL: &str = "postgres://localhost:1/db";
+#[cfg(all(test, feature = "sqlite"))]
+pub const UNREACHABLE_DATABASE_URL: &str = "/nonexistent/db.sqlite";
@@ -0,0 +1,225 @@
+use serde::*;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CacheStats {
+    pub size: usize,
+    pub hits: u64,
+    // expired entries count as misses
+    pub misses: u64,
+    // entries dropped to stay within max_entries
+    pub evictions: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    // position in State::order
+    used: u64,
+}
+
+struct State<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    // least recently used first
+    order: BTreeMap<u64, K>,
+    clock: u64,
+    // bumped by every invalidation, see LruCache::insert
+    generation: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> State<K, V> {
+    fn touch(&mut self, key: &K) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.used);
+            entry.used = clock;
+            self.order.insert(clock, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used);
+        }
+    }
+}
+
+// Bounded by max_entries, evicting the least recently used entry, and by a TTL per entry
+pub struct LruCache<K, V> {
+    max_entries: usize,
+    ttl: Duration,
+    state: Mutex<State<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(max_entries: usize, ttl: Duration) -> LruCache<K, V> {
+        LruCache {
+            max_entries: max_entries,
+            ttl: ttl,
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                clock: 0,
+                generation: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &K, now: Instant) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        let value = match state.entries.get(key) {
+            Some(entry) if entry.expires_at > now => Some(entry.value.clone()),
+            _ => None,
+        };
+
+        if value.is_some() {
+            state.touch(key);
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            // drops the expired entry, if any
+            state.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    // Take it before loading a value, and pass it to insert
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    // The value is dropped if anything was invalidated since `generation`, since it may have
+    // been loaded before a write which the invalidation was for
+    pub fn insert(&self, key: K, value: V, generation: u64) {
+        self.insert_at(key, value, generation, Instant::now())
+    }
+
+    fn insert_at(&self, key: K, value: V, generation: u64, now: Instant) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+
+        state.remove(&key);
+        while state.entries.len() >= self.max_entries {
+            let oldest = match state.order.keys().next() {
+                Some(used) => *used,
+                None => break,
+            };
+            if let Some(oldest) = state.order.remove(&oldest) {
+                state.entries.remove(&oldest);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        state.order.insert(clock, key.clone());
+        state.entries.insert(
+            key,
+            Entry {
+                value: value,
+                expires_at: now + self.ttl,
+                used: clock,
+            },
+        );
+    }
+
+    pub fn remove(&self, key: &K) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.remove(key);
+    }
+
+    pub fn remove_where(&self, f: impl Fn(&K, &V) -> bool) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let keys = state
+            .entries
+            .iter()
+            .filter(|(key, entry)| f(*key, &entry.value))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            size: self.state.lock().unwrap().entries.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[test]
+fn cache_should_evict_the_least_recently_used() {
+    let cache = LruCache::new(2, Duration::from_secs(60));
+    cache.insert("a", 1, cache.generation());
+    cache.insert("b", 2, cache.generation());
+    assert_eq!(Some(1), cache.get(&"a"));
+
+    cache.insert("c", 3, cache.generation());
+    assert_eq!(Some(1), cache.get(&"a"));
+    assert_eq!(None, cache.get(&"b"));
+    assert_eq!(Some(3), cache.get(&"c"));
+
+    assert_eq!(
+        CacheStats {
+            size: 2,
+            hits: 3,
+            misses: 1,
+            evictions: 1,
+        },
+        cache.stats()
+    );
+}
+
+#[test]
+fn cache_should_expire_entries() {
+    let cache = LruCache::new(10, Duration::from_secs(60));
+    let now = Instant::now();
+    cache.insert_at("a", 1, cache.generation(), now);
+
+    assert_eq!(Some(1), cache.get_at(&"a", now + Duration::from_secs(59)));
+    assert_eq!(None, cache.get_at(&"a", now + Duration::from_secs(60)));
+    assert_eq!(0, cache.stats().size);
+}
+
+#[test]
+fn cache_should_drop_values_loaded_before_an_invalidation() {
+    let cache = LruCache::new(10, Duration::from_secs(60));
+    let generation = cache.generation();
+    cache.remove(&"a");
+
+    cache.insert("a", 1, generation);
+    assert_eq!(None, cache.get(&"a"));
+
+    cache.insert("a", 1, cache.generation());
+    cache.insert("b", 2, cache.generation());
+    cache.remove_where(|_, v| *v == 1);
+    assert_eq!(None, cache.get(&"a"));
+    assert_eq!(Some(2), cache.get(&"b"));
+}
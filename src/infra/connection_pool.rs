@@ -1,21 +1,181 @@
-use diesel::{mysql::MysqlConnection, r2d2};
+use crate::config::PoolConfig;
+use crate::infra::{cancel_statement, connection_init, DBConnection, BACKEND_ID};
+use diesel::r2d2::{self, ManageConnection};
+use diesel::sql_types::BigInt;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// A connection of DBConnPool, which is closed instead of going back to the pool once discarded
+pub struct PoolConnection {
+    conn: DBConnection,
+    discarded: bool,
+    // see BACKEND_ID
+    backend_id: Option<i64>,
+}
+
+impl PoolConnection {
+    // e.g. when its session settings could not be restored
+    pub fn discard(&mut self) {
+        self.discarded = true;
+    }
+
+    pub fn backend_id(&self) -> Option<i64> {
+        self.backend_id
+    }
+}
+
+#[derive(QueryableByName)]
+struct BackendId {
+    #[sql_type = "BigInt"]
+    id: i64,
+}
+
+impl std::ops::Deref for PoolConnection {
+    type Target = DBConnection;
+
+    fn deref(&self) -> &DBConnection {
+        &self.conn
+    }
+}
+
+impl std::ops::DerefMut for PoolConnection {
+    fn deref_mut(&mut self) -> &mut DBConnection {
+        &mut self.conn
+    }
+}
+
+// diesel's ConnectionManager, which also drops the discarded connections
+pub struct DBConnManager(r2d2::ConnectionManager<DBConnection>);
+
+impl ManageConnection for DBConnManager {
+    type Connection = PoolConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<PoolConnection, r2d2::Error> {
+        Ok(PoolConnection {
+            conn: self.0.connect()?,
+            discarded: false,
+            backend_id: None,
+        })
+    }
+
+    fn is_valid(&self, conn: &mut PoolConnection) -> Result<(), r2d2::Error> {
+        self.0.is_valid(&mut conn.conn)
+    }
+
+    fn has_broken(&self, conn: &mut PoolConnection) -> bool {
+        conn.discarded || self.0.has_broken(&mut conn.conn)
+    }
+}
+
+// Sets up each new connection: the default query timeout, then the configured init SQL
+#[derive(Debug)]
+struct ConnectionInit {
+    query_timeout: Duration,
+    init_sql: Option<String>,
+}
+
+impl r2d2::CustomizeConnection<PoolConnection, r2d2::Error> for ConnectionInit {
+    fn on_acquire(&self, conn: &mut PoolConnection) -> Result<(), r2d2::Error> {
+        use diesel::connection::SimpleConnection;
+
+        conn.batch_execute(&connection_init(self.query_timeout))
+            .map_err(r2d2::Error::QueryError)?;
+        if let Some(sql) = &self.init_sql {
+            conn.batch_execute(sql).map_err(r2d2::Error::QueryError)?;
+        }
+        if let Some(query) = BACKEND_ID {
+            use diesel::RunQueryDsl;
+
+            let backend = diesel::sql_query(query)
+                .get_result::<BackendId>(&conn.conn)
+                .map_err(r2d2::Error::QueryError)?;
+            conn.backend_id = Some(backend.id);
+        }
+
+        Ok(())
+    }
+}
 
 #[derive(Clone)]
-pub struct MySQLConnPool(r2d2::Pool<r2d2::ConnectionManager<MysqlConnection>>);
-
-impl MySQLConnPool {
-    pub fn new(database_url: String) -> MySQLConnPool {
-        let manager = r2d2::ConnectionManager::<MysqlConnection>::new(database_url);
-        let pool = r2d2::Pool::builder()
-            .max_size(15)
-            .build(manager)
-            .expect("Failed to create pool");
-        MySQLConnPool(pool)
-    }
-
-    pub fn get_connection(
-        &self,
-    ) -> r2d2::PooledConnection<diesel::r2d2::ConnectionManager<diesel::MysqlConnection>> {
-        self.0.get().unwrap()
+pub struct DBConnPool {
+    pool: r2d2::Pool<DBConnManager>,
+    database_url: Arc<String>,
+}
+
+impl DBConnPool {
+    fn builder(config: &PoolConfig) -> r2d2::Builder<DBConnManager> {
+        r2d2::Pool::builder()
+            .max_size(config.max_size)
+            .min_idle(config.min_idle)
+            .connection_timeout(config.connection_timeout)
+            .idle_timeout(config.idle_timeout)
+            .max_lifetime(config.max_lifetime)
+            .connection_customizer(Box::new(ConnectionInit {
+                query_timeout: config.query_timeout,
+                init_sql: config.init_sql.clone(),
+            }))
+    }
+
+    // Fails if the initial connections cannot be opened within the connection timeout
+    pub fn new(database_url: String, config: &PoolConfig) -> Result<DBConnPool, r2d2::PoolError> {
+        let manager = DBConnManager(r2d2::ConnectionManager::new(database_url.clone()));
+        let pool = DBConnPool::builder(config).build(manager)?;
+        Ok(DBConnPool {
+            pool: pool,
+            database_url: Arc::new(database_url),
+        })
+    }
+
+    // Connects in the background, so that a database which is down does not block the startup
+    pub fn new_lazy(database_url: String, config: &PoolConfig) -> DBConnPool {
+        let manager = DBConnManager(r2d2::ConnectionManager::new(database_url.clone()));
+        DBConnPool {
+            pool: DBConnPool::builder(config).build_unchecked(manager),
+            database_url: Arc::new(database_url),
+        }
+    }
+
+    // Retries with exponential backoff, for a database which is still starting up
+    pub fn connect(
+        database_url: String,
+        config: &PoolConfig,
+    ) -> Result<DBConnPool, r2d2::PoolError> {
+        let mut interval = Duration::from_secs(1);
+        let mut attempt = 0;
+
+        loop {
+            match DBConnPool::new(database_url.clone(), config) {
+                Ok(pool) => return Ok(pool),
+                Err(err) if attempt < config.startup_retries => {
+                    attempt += 1;
+                    warn!(
+                        "Failed to connect to the database ({}), retrying in {:?} ({}/{})",
+                        err, interval, attempt, config.startup_retries
+                    );
+                    std::thread::sleep(interval);
+                    interval = std::cmp::min(interval * 2, MAX_RETRY_INTERVAL);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Waits up to the connection timeout when every connection is in use
+    pub fn get_connection(&self) -> Result<r2d2::PooledConnection<DBConnManager>, r2d2::PoolError> {
+        self.pool.get()
+    }
+
+    // Aborts the statement a connection of the pool is running (see PoolConnection::backend_id)
+    // From a new connection, since the pool may be exhausted by such statements
+    pub fn cancel(&self, backend_id: i64) -> Result<(), failure::Error> {
+        use diesel::{Connection, RunQueryDsl};
+
+        let conn = DBConnection::establish(&self.database_url)?;
+        diesel::sql_query(cancel_statement(backend_id)).execute(&conn)?;
+
+        Ok(())
     }
 }
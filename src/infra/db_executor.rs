@@ -1,58 +1,457 @@
-use crate::infra::MySQLConnPool;
-use actix::prelude::*;
+use crate::config::PoolConfig;
+use crate::infra::{
+    current_span, instrument, statement_timeout, DBBackend, DBConnPool, DBConnection,
+    ReplicaHealth, ReplicaStatus, Span,
+};
+use diesel::query_builder::{QueryBuilder, QueryFragment};
+use diesel::query_dsl::methods::ExecuteDsl;
+use diesel::RunQueryDsl;
 use futures::compat::*;
+use futures01::sync::oneshot;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
+type Job = Box<dyn FnOnce(&Pools) + Send>;
+
+thread_local! {
+    // The statements run through `traced` by the job of this executor thread, with their row counts
+    static TRACED: RefCell<Option<Vec<(String, Option<usize>)>>> = RefCell::new(None);
+}
+
+// Runs f, returning the statements it ran through `traced`
+fn collect_traced<R>(f: impl FnOnce() -> R) -> (R, Vec<(String, Option<usize>)>) {
+    TRACED.with(|t| *t.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let traced = TRACED.with(|t| t.borrow_mut().take()).unwrap_or_default();
+
+    (result, traced)
+}
+
+// The connection a job is running its statements on, while it does: (its pool, its backend id)
+type Running = Arc<Mutex<Option<(DBConnPool, i64)>>>;
+
+thread_local! {
+    // where DBConnector::connection publishes the connection of the job of this executor thread
+    static RUNNING: RefCell<Option<Running>> = RefCell::new(None);
+}
+
+// Publishes the connection for cancel_running until dropped, which must happen before the
+// connection goes back to the pool: a cancellation waits for nothing else to run on it
+struct RunningConnection(Option<Running>);
+
+impl RunningConnection {
+    fn start(pool: &DBConnPool, backend_id: Option<i64>) -> RunningConnection {
+        let running = RUNNING.with(|r| r.borrow().clone());
+        if let (Some(running), Some(backend_id)) = (&running, backend_id) {
+            *running.lock().unwrap() = Some((pool.clone(), backend_id));
+        }
+
+        RunningConnection(running)
+    }
+}
+
+impl Drop for RunningConnection {
+    fn drop(&mut self) {
+        if let Some(running) = &self.0 {
+            *running.lock().unwrap() = None;
+        }
+    }
+}
+
+// Aborts the statement of a job which is still running after its timeout, off the event loop
+// The lock keeps the job from returning the connection (and another job from reusing it) until
+// the cancellation is done
+fn cancel_running(running: Running) {
+    std::thread::spawn(move || {
+        let running = running.lock().unwrap();
+        if let Some((pool, backend_id)) = running.as_ref() {
+            match pool.cancel(*backend_id) {
+                Ok(()) => warn!(
+                    "Cancelled the timed out statement of session {}",
+                    backend_id
+                ),
+                Err(err) => warn!(
+                    "Failed to cancel the timed out statement of session {}: {}",
+                    backend_id, err
+                ),
+            }
+        }
+    });
+}
+
+// The statements joined, and the sum of the row counts which are known
+fn summarize(traced: Vec<(String, Option<usize>)>) -> (String, Option<usize>) {
+    let counts = traced
+        .iter()
+        .filter_map(|(_, rows)| *rows)
+        .collect::<Vec<_>>();
+    let rows = if counts.is_empty() {
+        None
+    } else {
+        Some(counts.iter().sum())
+    };
+    let sql = traced
+        .into_iter()
+        .map(|(sql, _)| sql)
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    (sql, rows)
+}
+
+// The primary, and the replica with the health reported by its monitor (see start_replica_monitor)
 #[derive(Clone)]
-pub struct DBExecutor(MySQLConnPool);
+struct Pools {
+    primary: DBConnPool,
+    replica: Option<(DBConnPool, Arc<ReplicaHealth>)>,
+}
+
+impl Pools {
+    // reads fail over to the primary while the replica is unhealthy
+    fn route(&self, read_only: bool) -> (&DBConnPool, &'static str) {
+        match &self.replica {
+            Some((replica, health)) if read_only && health.is_healthy() => (replica, "replica"),
+            _ => (&self.primary, "primary"),
+        }
+    }
+}
+
+// A fixed set of threads running the blocking diesel calls, fed through a bounded queue
+// Callers never wait for a free slot in the queue: they get DBConnectorError::Busy instead
+#[derive(Clone)]
+pub struct DBExecutor {
+    queue: mpsc::SyncSender<Job>,
+    replica_health: Option<Arc<ReplicaHealth>>,
+    query_timeout: Duration,
+    slow_query_threshold: Option<Duration>,
+    slow_query_log: SlowQueryLog,
+}
+
+// Where the lines of the slow query log go; warnings with the "slow_query" target by default
+type SlowQueryLog = Arc<dyn Fn(&str) + Send + Sync>;
+
+fn log_slow_query(line: &str) {
+    warn!(target: "slow_query", "{}", line);
+}
 
 impl DBExecutor {
-    pub fn new(database_url: String) -> DBExecutor {
-        DBExecutor(MySQLConnPool::new(database_url))
+    pub fn start(
+        pool: DBConnPool,
+        replica: Option<(DBConnPool, Arc<ReplicaHealth>)>,
+        config: &PoolConfig,
+    ) -> DBExecutor {
+        let (queue, jobs) = mpsc::sync_channel::<Job>(config.queue_size);
+        let jobs = Arc::new(Mutex::new(jobs));
+        let replica_health = replica.as_ref().map(|(_, health)| health.clone());
+        let pools = Pools {
+            primary: pool,
+            replica: replica,
+        };
+
+        for i in 0..config.executors {
+            let jobs = jobs.clone();
+            let pools = pools.clone();
+            std::thread::Builder::new()
+                .name(format!("db-executor-{}", i))
+                .spawn(move || loop {
+                    // the lock is only held while waiting for the next job
+                    let job = match jobs.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        // every DBExecutor has been dropped
+                        Err(_) => return,
+                    };
+
+                    // a panicking job drops its result sender, which the caller sees as Canceled
+                    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(&pools)));
+                })
+                .expect("Failed to spawn a DB executor thread");
+        }
+
+        DBExecutor {
+            queue: queue,
+            replica_health: replica_health,
+            query_timeout: config.query_timeout,
+            slow_query_threshold: config.slow_query_threshold,
+            slow_query_log: Arc::new(log_slow_query),
+        }
     }
 
-    pub fn get_connection(
+    // Enqueues f right away; the returned future resolves with its result
+    // If the future is dropped or times out before f starts, f is skipped
+    // `rows` tells the row count of a result, for the slow query log
+    // `read_only` jobs run on the replica, unless it is unhealthy or the call is pinned to the primary
+    fn submit<R: 'static + Send, F: 'static + Send>(
         &self,
-    ) -> r2d2::PooledConnection<diesel::r2d2::ConnectionManager<diesel::MysqlConnection>> {
-        self.0.get_connection()
+        name: &'static str,
+        call: &Call,
+        read_only: bool,
+        statement: Option<String>,
+        rows: fn(&R) -> Option<usize>,
+        f: F,
+    ) -> impl Future<Output = Result<R, DBConnectorError>>
+    where
+        F: FnOnce(&DBConnPool) -> Result<R, DBConnectorError>,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let running: Running = Arc::new(Mutex::new(None));
+        let job_running = running.clone();
+        let parent = current_span();
+        let caller = call.caller;
+        let read_only = read_only && !call.primary;
+        let slow_query_threshold = self.slow_query_threshold;
+        let slow_query_log = self.slow_query_log.clone();
+        let job: Job = Box::new(move |pools: &Pools| {
+            if sender.is_canceled() {
+                return;
+            }
+
+            let (pool, role) = pools.route(read_only);
+
+            // opens the executor side span, as a child of the span which submitted the job
+            let mut span = Span::with_parent(name, parent);
+            if let Some(statement) = &statement {
+                span.set_attribute("db.statement", statement);
+            }
+            if let Some(caller) = caller {
+                span.set_attribute("db.caller", caller);
+            }
+            span.set_attribute("db.pool", role);
+
+            let started = Instant::now();
+            RUNNING.with(|r| *r.borrow_mut() = Some(job_running));
+            let (result, traced) = span.in_scope(|| collect_traced(|| f(pool)));
+            RUNNING.with(|r| *r.borrow_mut() = None);
+            let elapsed = started.elapsed();
+            if slow_query_threshold.map_or(false, |threshold| elapsed >= threshold) {
+                // the closures of run, run_read_only and transaction report their statements
+                // through `traced`
+                let (sql, rows) = match statement {
+                    Some(statement) => (statement, result.as_ref().ok().and_then(rows)),
+                    None if !traced.is_empty() => summarize(traced),
+                    None => (name.to_owned(), None),
+                };
+                slow_query_log(&format!(
+                    "{}ms caller={} pool={} rows={} {}",
+                    elapsed.as_millis(),
+                    caller.unwrap_or("-"),
+                    role,
+                    rows.map(|n| n.to_string())
+                        .unwrap_or_else(|| "-".to_owned()),
+                    sql
+                ));
+            }
+
+            let _ = sender.send(result);
+        });
+
+        let queued = self.queue.try_send(job).map_err(|err| match err {
+            mpsc::TrySendError::Full(_) => DBConnectorError::Busy,
+            mpsc::TrySendError::Disconnected(_) => DBConnectorError::Canceled,
+        });
+        let timeout = call.timeout.unwrap_or(self.query_timeout);
+
+        async move {
+            queued?;
+
+            match tokio_timer::Timeout::new(receiver, timeout).compat().await {
+                Ok(result) => result,
+                Err(err) if err.is_elapsed() => {
+                    // else the statement would keep its connection and executor thread busy
+                    cancel_running(running);
+                    Err(DBConnectorError::Timeout)
+                }
+                Err(_) => Err(DBConnectorError::Canceled),
+            }
+        }
     }
 }
 
-impl Actor for DBExecutor {
-    type Context = SyncContext<Self>;
+// Per call site settings, see DBConnector::caller, DBConnector::timeout and DBConnector::primary
+#[derive(Clone, Default)]
+struct Call {
+    caller: Option<&'static str>,
+    timeout: Option<Duration>,
+    primary: bool,
 }
 
 #[derive(Clone)]
-pub struct DBConnector(Addr<DBExecutor>);
+pub struct DBConnector(DBExecutor, Call);
 
 #[derive(Fail, Debug)]
 pub enum DBConnectorError {
     #[fail(display = "DB Error: {}", _0)]
     DBError(#[fail(cause)] diesel::result::Error),
 
-    #[fail(display = "Actor Error: {}", _0)]
-    MailboxError(#[fail(cause)] actix::MailboxError),
+    // e.g. duplicate entry on a unique index
+    #[fail(display = "Unique violation: {}", _0)]
+    UniqueViolation(String),
+
+    #[fail(display = "Record not found")]
+    NotFound,
+
+    // the record was updated by someone else since it was read
+    #[fail(display = "Version conflict")]
+    VersionConflict,
+
+    // no connection could be checked out within the connection timeout
+    #[fail(display = "Pool Error: {}", _0)]
+    Pool(#[fail(cause)] diesel::r2d2::PoolError),
+
+    // the executor queue is full
+    #[fail(display = "DB executor is busy")]
+    Busy,
+
+    // no result within the query timeout (the statement is then cancelled, see BACKEND_ID)
+    #[fail(display = "Query timed out")]
+    Timeout,
+
+    // the executor went away without a result
+    #[fail(display = "Query was canceled")]
+    Canceled,
+}
+
+impl From<diesel::result::Error> for DBConnectorError {
+    fn from(err: diesel::result::Error) -> DBConnectorError {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        match err {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                DBConnectorError::UniqueViolation(info.message().to_owned())
+            }
+            Error::NotFound => DBConnectorError::NotFound,
+            err => DBConnectorError::DBError(err),
+        }
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for DBConnectorError {
+    fn from(err: diesel::r2d2::PoolError) -> DBConnectorError {
+        DBConnectorError::Pool(err)
+    }
+}
+
+// Same as diesel's OptionalExtension, for the results of DBConnector
+pub trait OptionalResult<T> {
+    fn optional(self) -> Result<Option<T>, DBConnectorError>;
+}
+
+impl<T> OptionalResult<T> for Result<T, DBConnectorError> {
+    fn optional(self) -> Result<Option<T>, DBConnectorError> {
+        match self {
+            Ok(value) => Ok(Some(value)),
+            Err(DBConnectorError::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
 }
 
 impl DBConnector {
-    pub fn new(conn: Addr<DBExecutor>) -> DBConnector {
-        DBConnector(conn)
+    pub fn new(executor: DBExecutor) -> DBConnector {
+        DBConnector(executor, Call::default())
     }
 
-    pub async fn execute<
-        Q: diesel::query_builder::QueryFragment<diesel::mysql::Mysql> + Sync + Send + 'static,
-    >(
-        &self,
-        query: Q,
-    ) -> Result<usize, DBConnectorError> {
-        let rows = self
-            .0
-            .send(Execute::new(query))
-            .compat()
-            .await
-            .map_err(DBConnectorError::MailboxError)?
-            .map_err(DBConnectorError::DBError)?;
+    // Names the call site (e.g. "UserRepository::list") in the slow query log and the spans
+    pub fn caller(&self, caller: &'static str) -> DBConnector {
+        DBConnector(
+            self.0.clone(),
+            Call {
+                caller: Some(caller),
+                ..self.1.clone()
+            },
+        )
+    }
+
+    // Overrides the query timeout (DB_QUERY_TIMEOUT_SECS) for this call site
+    pub fn timeout(&self, timeout: Duration) -> DBConnector {
+        DBConnector(
+            self.0.clone(),
+            Call {
+                timeout: Some(timeout),
+                ..self.1.clone()
+            },
+        )
+    }
 
-        Ok(rows)
+    // Reads from the primary even when a replica is configured (read your own writes)
+    // e.g. a get_by_id following a save, which the replica may not have applied yet
+    pub fn primary(&self) -> DBConnector {
+        DBConnector(
+            self.0.clone(),
+            Call {
+                primary: true,
+                ..self.1.clone()
+            },
+        )
+    }
+
+    // None when no replica is configured
+    pub fn replica_status(&self) -> Option<ReplicaStatus> {
+        self.0.replica_health.as_ref().map(|health| health.status())
+    }
+
+    // Checks out a connection, with the server side limit of this call site
+    // The server may not bound every statement (see statement_timeout); everything else is
+    // bounded by the client side timeout in DBExecutor::submit
+    fn connection<R>(
+        pool: &DBConnPool,
+        timeout: Option<Duration>,
+        default: Duration,
+        f: impl FnOnce(&DBConnection) -> Result<R, DBConnectorError>,
+    ) -> Result<R, DBConnectorError> {
+        let mut conn = pool.get_connection()?;
+        // dropped before conn
+        let _running = RunningConnection::start(pool, conn.backend_id());
+        let timeout = match timeout {
+            // the pool default is set when connecting, see DBConnPool::new
+            None => return f(&**conn),
+            Some(timeout) => timeout,
+        };
+
+        diesel::sql_query(statement_timeout(timeout)).execute(&**conn)?;
+        let result = f(&**conn);
+        // back to the pool default, before the connection is returned; a connection which keeps
+        // the override is closed instead, whatever the result of f
+        if let Err(err) = diesel::sql_query(statement_timeout(default)).execute(&**conn) {
+            warn!(
+                "Failed to restore the query timeout, discarding the connection: {}",
+                err
+            );
+            conn.discard();
+        }
+
+        result
+    }
+
+    // the query runs as a prepared statement with its binds
+    pub async fn execute<Q: 'static + Send>(&self, query: Q) -> Result<usize, DBConnectorError>
+    where
+        Q: ExecuteDsl<DBConnection>,
+        Q: QueryFragment<DBBackend>,
+    {
+        let sql = statement(&query);
+        let mut span = Span::new("DBConnector::execute");
+        span.set_attribute("db.statement", &sql);
+
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(span, async move {
+            self.0
+                .submit(
+                    "DBExecutor::Execute",
+                    &self.1,
+                    false,
+                    Some(sql),
+                    |n| Some(*n),
+                    move |pool| {
+                        DBConnector::connection(pool, timeout, default, |conn| {
+                            Ok(ExecuteDsl::execute(query, conn)?)
+                        })
+                    },
+                )
+                .await
+        })
+        .await
     }
 
     pub async fn first<T: 'static + Send, Q: 'static + Send>(
@@ -61,18 +460,32 @@ impl DBConnector {
     ) -> Result<T, DBConnectorError>
     where
         Q: diesel::query_dsl::limit_dsl::LimitDsl,
-        Q: diesel::RunQueryDsl<diesel::MysqlConnection>,
-        diesel::helper_types::Limit<Q>: diesel::query_dsl::LoadQuery<diesel::MysqlConnection, T>,
+        Q: diesel::RunQueryDsl<DBConnection>,
+        Q: QueryFragment<DBBackend>,
+        diesel::helper_types::Limit<Q>: diesel::query_dsl::LoadQuery<DBConnection, T>,
     {
-        let result = self
-            .0
-            .send(First::new(query))
-            .compat()
-            .await
-            .map_err(DBConnectorError::MailboxError)?
-            .map_err(DBConnectorError::DBError)?;
-
-        Ok(result)
+        let sql = statement(&query);
+        let mut span = Span::new("DBConnector::first");
+        span.set_attribute("db.statement", &sql);
+
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(span, async move {
+            self.0
+                .submit(
+                    "DBExecutor::First",
+                    &self.1,
+                    true,
+                    Some(sql),
+                    |_| Some(1),
+                    move |pool| {
+                        DBConnector::connection(pool, timeout, default, |conn| {
+                            Ok(query.first(conn)?)
+                        })
+                    },
+                )
+                .await
+        })
+        .await
     }
 
     pub async fn load<T: 'static + Send, Q: 'static + Send>(
@@ -80,141 +493,400 @@ impl DBConnector {
         query: Q,
     ) -> Result<Vec<T>, DBConnectorError>
     where
-        Q: diesel::RunQueryDsl<diesel::MysqlConnection>,
-        Q: diesel::query_dsl::LoadQuery<diesel::MysqlConnection, T>,
+        Q: diesel::RunQueryDsl<DBConnection>,
+        Q: diesel::query_dsl::LoadQuery<DBConnection, T>,
+        Q: QueryFragment<DBBackend>,
     {
-        let result = self
-            .0
-            .send(Load::new(query))
-            .compat()
-            .await
-            .map_err(DBConnectorError::MailboxError)?
-            .map_err(DBConnectorError::DBError)?;
-
-        Ok(result)
+        let sql = statement(&query);
+        let mut span = Span::new("DBConnector::load");
+        span.set_attribute("db.statement", &sql);
+
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(span, async move {
+            self.0
+                .submit(
+                    "DBExecutor::Load",
+                    &self.1,
+                    true,
+                    Some(sql),
+                    |v| Some(v.len()),
+                    move |pool| {
+                        DBConnector::connection(
+                            pool,
+                            timeout,
+                            default,
+                            |conn| Ok(query.load(conn)?),
+                        )
+                    },
+                )
+                .await
+        })
+        .await
     }
 
-    pub async fn sql_query(&self, query: impl Into<String>) -> Result<usize, DBConnectorError> {
-        let result = self
-            .0
-            .send(SqlQuery::new(query))
-            .compat()
-            .await
-            .map_err(DBConnectorError::MailboxError)?
-            .map_err(DBConnectorError::DBError)?;
-
-        Ok(result)
+    // For `diesel::sql_query(...).bind::<ST, _>(...)`, whose rows are read by name
+    pub async fn sql_load<T: 'static + Send, Q: 'static + Send>(
+        &self,
+        query: Q,
+    ) -> Result<Vec<T>, DBConnectorError>
+    where
+        T: diesel::deserialize::QueryableByName<DBBackend>,
+        Q: diesel::query_dsl::LoadQuery<DBConnection, T>,
+        Q: QueryFragment<DBBackend>,
+    {
+        let sql = statement(&query);
+        let mut span = Span::new("DBConnector::sql_load");
+        span.set_attribute("db.statement", &sql);
+
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(span, async move {
+            self.0
+                .submit(
+                    "DBExecutor::Load",
+                    &self.1,
+                    true,
+                    Some(sql),
+                    |v| Some(v.len()),
+                    move |pool| {
+                        DBConnector::connection(
+                            pool,
+                            timeout,
+                            default,
+                            |conn| Ok(query.load(conn)?),
+                        )
+                    },
+                )
+                .await
+        })
+        .await
     }
-}
 
-// -------------------
-// Message/Handler
-// -------------------
-
-// This is for running "raw" sql query
-pub struct SqlQuery(String);
-
-impl SqlQuery {
-    pub fn new(query: impl Into<String>) -> SqlQuery {
-        SqlQuery(query.into())
+    // Run arbitrary diesel operations on the executor's connection
+    // Use this when the query cannot be built beforehand (e.g. boxed queries are not Send)
+    // Run the statements through `traced`, for their spans and the slow query log
+    pub async fn run<R: 'static + Send, F: 'static + Send>(
+        &self,
+        f: F,
+    ) -> Result<R, DBConnectorError>
+    where
+        F: FnOnce(&DBConnection) -> Result<R, diesel::result::Error>,
+    {
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(Span::new("DBConnector::run"), async move {
+            self.0
+                .submit(
+                    "DBExecutor::Run",
+                    &self.1,
+                    false,
+                    None,
+                    |_| None,
+                    move |pool| {
+                        DBConnector::connection(pool, timeout, default, |conn| Ok(f(conn)?))
+                    },
+                )
+                .await
+        })
+        .await
     }
-}
 
-impl Message for SqlQuery {
-    type Result = Result<usize, diesel::result::Error>;
-}
-
-impl Handler<SqlQuery> for DBExecutor {
-    type Result = Result<usize, diesel::result::Error>;
+    // Same as run, for closures which only read (routed like load)
+    pub async fn run_read_only<R: 'static + Send, F: 'static + Send>(
+        &self,
+        f: F,
+    ) -> Result<R, DBConnectorError>
+    where
+        F: FnOnce(&DBConnection) -> Result<R, diesel::result::Error>,
+    {
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(Span::new("DBConnector::run_read_only"), async move {
+            self.0
+                .submit(
+                    "DBExecutor::Run",
+                    &self.1,
+                    true,
+                    None,
+                    |_| None,
+                    move |pool| {
+                        DBConnector::connection(pool, timeout, default, |conn| Ok(f(conn)?))
+                    },
+                )
+                .await
+        })
+        .await
+    }
 
-    fn handle(&mut self, message: SqlQuery, _: &mut Self::Context) -> Self::Result {
-        use diesel::prelude::*;
-        let conn = self.get_connection();
+    // Run the closure inside a transaction on a single connection
+    // Everything done in the closure is rolled back when it returns an error
+    pub async fn transaction<R: 'static + Send, F: 'static + Send>(
+        &self,
+        f: F,
+    ) -> Result<R, DBConnectorError>
+    where
+        F: FnOnce(&DBConnection) -> Result<R, DBConnectorError>,
+    {
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(Span::new("DBConnector::transaction"), async move {
+            self.0
+                .submit(
+                    "DBExecutor::Run",
+                    &self.1,
+                    false,
+                    None,
+                    |_| None,
+                    move |pool| {
+                        DBConnector::connection(pool, timeout, default, |conn| {
+                            use diesel::connection::Connection;
+                            conn.transaction(|| f(conn))
+                        })
+                    },
+                )
+                .await
+        })
+        .await
+    }
 
-        diesel::sql_query(message.0).execute(&conn)
+    // Raw SQL without any bind parameter; use `execute(diesel::sql_query(...).bind(...))` for values
+    pub async fn sql_query(&self, query: impl Into<String>) -> Result<usize, DBConnectorError> {
+        let query = query.into();
+        let mut span = Span::new("DBConnector::sql_query");
+        span.set_attribute("db.statement", &query);
+
+        let (timeout, default) = (self.1.timeout, self.0.query_timeout);
+        instrument(span, async move {
+            self.0
+                .submit(
+                    "DBExecutor::SqlQuery",
+                    &self.1,
+                    false,
+                    Some(query.clone()),
+                    |n| Some(*n),
+                    move |pool| {
+                        DBConnector::connection(pool, timeout, default, |conn| {
+                            Ok(diesel::sql_query(query).execute(conn)?)
+                        })
+                    },
+                )
+                .await
+        })
+        .await
     }
 }
 
-// Do execution and return usize (affected rows)
-pub struct Execute<Q>(Q);
-
-impl<Q> Execute<Q> {
-    pub fn new(query: Q) -> Execute<Q> {
-        Execute(query)
+// SQL text without bind values (binds may contain password hashes)
+pub fn statement<Q: QueryFragment<DBBackend>>(query: &Q) -> String {
+    let mut builder = <DBBackend as diesel::backend::Backend>::QueryBuilder::default();
+    match query.to_sql(&mut builder) {
+        Ok(()) => builder.finish(),
+        Err(_) => String::new(),
     }
 }
 
-impl<Q> Message for Execute<Q> {
-    type Result = Result<usize, diesel::result::Error>;
+// Runs one statement of a run, run_read_only or transaction closure, in a span named `name` with
+// its SQL text; the slow query log of the closure shows the statement and the count from `rows`
+pub fn traced<Q: QueryFragment<DBBackend>, R>(
+    name: &'static str,
+    query: Q,
+    rows: fn(&R) -> Option<usize>,
+    f: impl FnOnce(Q) -> diesel::QueryResult<R>,
+) -> diesel::QueryResult<R> {
+    let sql = statement(&query);
+    let mut span = Span::new(name);
+    span.set_attribute("db.statement", &sql);
+
+    let result = span.in_scope(|| f(query));
+    let count = result.as_ref().ok().and_then(rows);
+    TRACED.with(|t| {
+        if let Some(traced) = t.borrow_mut().as_mut() {
+            traced.push((sql, count));
+        }
+    });
+
+    result
 }
 
-impl<Q: diesel::query_builder::QueryFragment<diesel::mysql::Mysql>> Handler<Execute<Q>>
-    for DBExecutor
-{
-    type Result = Result<usize, diesel::result::Error>;
+#[cfg(test)]
+use crate::infra::UNREACHABLE_DATABASE_URL;
+
+#[cfg(test)]
+fn test_executor(queue_size: usize, query_timeout: Duration) -> DBExecutor {
+    let config = PoolConfig {
+        max_size: 1,
+        min_idle: Some(0),
+        connection_timeout: Duration::from_secs(1),
+        idle_timeout: None,
+        max_lifetime: None,
+        init_sql: None,
+        startup_retries: 0,
+        executors: 1,
+        queue_size: queue_size,
+        query_timeout: query_timeout,
+        slow_query_threshold: None,
+    };
+    let pool = DBConnPool::new_lazy(UNREACHABLE_DATABASE_URL.to_owned(), &config);
+    DBExecutor::start(pool, None, &config)
+}
 
-    fn handle(&mut self, message: Execute<Q>, _: &mut Self::Context) -> Self::Result {
-        use diesel::prelude::*;
-        use diesel::query_builder::QueryBuilder;
-        let conn = self.get_connection();
+#[cfg(test)]
+fn block_on<R>(
+    fut: impl Future<Output = Result<R, DBConnectorError>>,
+) -> Result<R, DBConnectorError> {
+    use futures::{FutureExt, TryFutureExt};
 
-        let mut builder = diesel::mysql::MysqlQueryBuilder::new();
-        message.0.to_sql(&mut builder)?;
-        diesel::sql_query(builder.finish()).execute(&conn)
-    }
+    actix::System::new("test")
+        .block_on(Box::pin(fut.map(Ok::<_, ()>)).compat())
+        .unwrap()
 }
 
-// Run query and return the all rows
-// T represents the return type
-pub struct Load<T, Q>(Q, std::marker::PhantomData<T>);
-
-impl<T, Q> Load<T, Q> {
-    pub fn new(query: Q) -> Load<T, Q> {
-        Load(query, std::marker::PhantomData)
+#[test]
+fn submit_should_fail_fast_when_queue_is_full() {
+    let executor = test_executor(1, Duration::from_secs(5));
+    let (started, wait_started) = mpsc::channel();
+    let (release, wait_release) = mpsc::channel::<()>();
+
+    let running = executor.submit(
+        "running",
+        &Call::default(),
+        false,
+        None,
+        |_| None,
+        move |_| {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+            Ok(1)
+        },
+    );
+    wait_started.recv().unwrap();
+    let queued = executor.submit("queued", &Call::default(), false, None, |_| None, |_| Ok(2));
+
+    match block_on(executor.submit(
+        "rejected",
+        &Call::default(),
+        false,
+        None,
+        |_| None,
+        |_| Ok(3),
+    )) {
+        Err(DBConnectorError::Busy) => (),
+        _ => panic!("expected busy"),
     }
-}
 
-impl<T: 'static, Q> Message for Load<T, Q> {
-    type Result = Result<Vec<T>, diesel::result::Error>;
+    release.send(()).unwrap();
+    assert_eq!(1, block_on(running).unwrap());
+    assert_eq!(2, block_on(queued).unwrap());
 }
 
-impl<T: 'static, Q> Handler<Load<T, Q>> for DBExecutor
-where
-    Q: diesel::RunQueryDsl<diesel::MysqlConnection>,
-    Q: diesel::query_dsl::LoadQuery<diesel::MysqlConnection, T>,
-{
-    type Result = Result<Vec<T>, diesel::result::Error>;
-
-    fn handle(&mut self, message: Load<T, Q>, _: &mut Self::Context) -> Self::Result {
-        let conn = self.get_connection();
-        message.0.load(&conn)
+#[test]
+fn submit_should_skip_timed_out_jobs() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let executor = test_executor(2, Duration::from_millis(50));
+    let (started, wait_started) = mpsc::channel();
+    let (release, wait_release) = mpsc::channel::<()>();
+    let skipped_ran = Arc::new(AtomicBool::new(false));
+
+    let _running = executor.submit(
+        "running",
+        &Call::default(),
+        false,
+        None,
+        |_| None,
+        move |_| {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+            Ok(())
+        },
+    );
+    wait_started.recv().unwrap();
+
+    let ran = skipped_ran.clone();
+    match block_on(executor.submit(
+        "skipped",
+        &Call::default(),
+        false,
+        None,
+        |_| None,
+        move |_| {
+            ran.store(true, Ordering::SeqCst);
+            Ok(())
+        },
+    )) {
+        Err(DBConnectorError::Timeout) => (),
+        _ => panic!("expected timeout"),
     }
-}
-
-// Run query and return the first row
-// T represents the return type
-pub struct First<T, Q>(Q, std::marker::PhantomData<T>);
 
-impl<T, Q> First<T, Q> {
-    pub fn new(query: Q) -> First<T, Q> {
-        First(query, std::marker::PhantomData)
-    }
+    release.send(()).unwrap();
+    // jobs run in order, so "skipped" has been dequeued once this one has run
+    block_on(executor.submit("next", &Call::default(), false, None, |_| None, |_| Ok(()))).unwrap();
+    assert!(!skipped_ran.load(Ordering::SeqCst));
 }
 
-impl<T: 'static, Q> Message for First<T, Q> {
-    type Result = Result<T, diesel::result::Error>;
+#[test]
+fn slow_query_log_should_show_the_traced_statements() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let log_lines = lines.clone();
+    let mut executor = test_executor(1, Duration::from_secs(5));
+    executor.slow_query_threshold = Some(Duration::from_millis(0));
+    executor.slow_query_log =
+        Arc::new(move |line: &str| log_lines.lock().unwrap().push(line.to_owned()));
+    let call = Call {
+        caller: Some("slow_query_log_test"),
+        ..Call::default()
+    };
+
+    // what the closure of DBConnector::transaction does, without a connection
+    block_on(executor.submit(
+        "DBExecutor::Run",
+        &call,
+        false,
+        None,
+        |_| None,
+        |_| {
+            traced(
+                "insert",
+                diesel::sql_query("SELECT 1"),
+                |n| Some(*n),
+                |_| Ok(2),
+            )?;
+            traced("check", diesel::sql_query("SELECT 2"), |_| None, |_| Ok(()))?;
+            traced(
+                "update",
+                diesel::sql_query("SELECT 3"),
+                |n| Some(*n),
+                |_| Ok(1),
+            )?;
+            Ok(())
+        },
+    ))
+    .unwrap();
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(1, lines.len());
+    let line = &lines[0];
+    assert!(line.ends_with("pool=primary rows=3 SELECT 1; SELECT 2; SELECT 3"));
 }
 
-impl<T: 'static, Q> Handler<First<T, Q>> for DBExecutor
-where
-    Q: diesel::RunQueryDsl<diesel::MysqlConnection>,
-    Q: diesel::query_dsl::limit_dsl::LimitDsl,
-    diesel::helper_types::Limit<Q>: diesel::query_dsl::LoadQuery<diesel::MysqlConnection, T>,
-{
-    type Result = Result<T, diesel::result::Error>;
-
-    fn handle(&mut self, message: First<T, Q>, _: &mut Self::Context) -> Self::Result {
-        let conn = self.get_connection();
-        message.0.first(&conn)
-    }
+// Needs TEST_DATABASE_URL, like the repository tests on MySQL and PostgreSQL
+#[test]
+#[cfg(not(feature = "sqlite"))]
+#[ignore]
+fn timed_out_statements_should_be_cancelled() {
+    // not bounded by max_execution_time, which only applies to SELECTs
+    #[cfg(feature = "mysql")]
+    const SLEEP: &str = "DO SLEEP(10)";
+    #[cfg(feature = "postgres")]
+    const SLEEP: &str = "SELECT pg_sleep(10)";
+
+    let db = DBConnector::new(crate::serviceclient::test_executor());
+    let started = Instant::now();
+    block_on(async move {
+        match db.timeout(Duration::from_secs(1)).sql_query(SLEEP).await {
+            Err(DBConnectorError::Timeout) => (),
+            _ => panic!("expected timeout"),
+        }
+
+        // on the only connection and executor thread, which the sleep would hold for 10s
+        db.sql_query("SELECT 1").await
+    })
+    .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
 }
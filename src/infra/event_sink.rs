@@ -0,0 +1,460 @@
+use crate::domain::interface::{IEventSink, IWebhookClient};
+use crate::domain::model;
+use async_trait::async_trait;
+use futures::compat::*;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The X-Signature-256 header: "sha256=" and the hex of HMAC-SHA256(secret, body)
+// Receivers recompute it over the raw body and compare in constant time
+pub fn webhook_signature(secret: &[u8], body: &[u8]) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret);
+    format!("sha256={}", to_hex(ring::hmac::sign(&key, body).as_ref()))
+}
+
+// One JSON event per line, to stdout or appended to a file
+pub struct LineSink(Mutex<Box<dyn Write + Send>>);
+
+impl LineSink {
+    pub fn stdout() -> LineSink {
+        LineSink(Mutex::new(Box::new(std::io::stdout())))
+    }
+
+    pub fn file(path: impl AsRef<std::path::Path>) -> std::io::Result<LineSink> {
+        let path = path.as_ref();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| {
+                std::io::Error::new(
+                    err.kind(),
+                    format!("Failed to open the event file {}: {}", path.display(), err),
+                )
+            })?;
+
+        Ok(LineSink(Mutex::new(Box::new(file))))
+    }
+}
+
+#[async_trait]
+impl IEventSink for LineSink {
+    async fn deliver(&self, event: model::OutboxEvent) -> Result<(), failure::Error> {
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+
+        let mut out = self.0.lock().unwrap();
+        out.write_all(&line)?;
+        out.flush()?;
+
+        Ok(())
+    }
+}
+
+fn webhook_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .expect("Failed to build the webhook client")
+}
+
+// POSTs the JSON body with X-Signature-256 and the given headers; returns the status code
+pub fn post_signed(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &[u8],
+    headers: &[(&'static str, &str)],
+    body: Vec<u8>,
+) -> Result<u16, failure::Error> {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Signature-256", webhook_signature(secret, &body));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    Ok(request.body(body).send()?.status().as_u16())
+}
+
+// A request failing or answering anything but 2xx is an error
+pub fn post_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &[u8],
+    event: &model::OutboxEvent,
+) -> Result<(), failure::Error> {
+    let status = post_signed(
+        client,
+        url,
+        secret,
+        &[
+            ("X-Event-Id", event.id.as_str()),
+            ("X-Event-Type", event.event.event_type()),
+        ],
+        serde_json::to_vec(event)?,
+    )?;
+
+    if status < 200 || status >= 300 {
+        bail!("{} answered {}", url, status);
+    }
+
+    Ok(())
+}
+
+// POSTs the event as JSON, signed with the shared secret
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Vec<u8>,
+}
+
+impl WebhookSink {
+    pub fn new(url: String, secret: String) -> WebhookSink {
+        WebhookSink {
+            client: webhook_client(),
+            url: url,
+            secret: secret.into_bytes(),
+        }
+    }
+}
+
+#[async_trait]
+impl IEventSink for WebhookSink {
+    async fn deliver(&self, event: model::OutboxEvent) -> Result<(), failure::Error> {
+        let (client, url, secret) = (self.client.clone(), self.url.clone(), self.secret.clone());
+
+        actix_web::web::block(move || post_webhook(&client, &url, &secret, &event))
+            .compat()
+            .await
+            .map_err(|err| format_err!("Failed to deliver the event: {}", err))
+    }
+}
+
+// Addresses which only make sense next to this service: loopback, private, link-local
+// (e.g. cloud metadata), shared (carrier-grade NAT), unspecified and broadcast ones
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || ip.to_ipv4().map_or(false, |ip| is_internal(IpAddr::V4(ip)))
+        }
+    }
+}
+
+// The URL of an endpoint registered through the admin API, whose host is either in the
+// allowed hosts (WEBHOOK_ALLOWED_HOSTS) or not internal
+// Host names are only resolved by resolve_webhook_url, as their addresses may change
+pub fn check_webhook_url(url: &str, allowed_hosts: &[String]) -> Result<(), failure::Error> {
+    parse_webhook_url(url, allowed_hosts).map(|_| ())
+}
+
+// the URL, its host, and whether the host is allowed
+fn parse_webhook_url(
+    url: &str,
+    allowed_hosts: &[String],
+) -> Result<(reqwest::Url, String, bool), failure::Error> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| failure::err_msg("url is not a URL"))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        bail!("url must be an http or https URL");
+    }
+    let host = match parsed.host_str() {
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_lowercase(),
+        None => bail!("url must have a host"),
+    };
+    let allowed = allowed_hosts.iter().any(|h| h.eq_ignore_ascii_case(&host));
+
+    let internal = match host.parse::<IpAddr>() {
+        Ok(ip) => is_internal(ip),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    };
+    if internal && !allowed {
+        bail!("url must not point to an internal address");
+    }
+
+    Ok((parsed, host, allowed))
+}
+
+// Checks the addresses the host resolves to right before a delivery, so that a name cannot
+// be pointed at an internal address after the endpoint was registered
+// Returns the URL to send to, and the Host header it then needs
+// An http URL is pinned to the checked address, so that the name cannot resolve to another one
+// when connecting; https keeps the name for the certificate, which an internal server would
+// have to hold for it after such a change
+fn resolve_webhook_url(
+    url: &str,
+    allowed_hosts: &[String],
+) -> Result<(String, Option<String>), failure::Error> {
+    let (parsed, host, allowed) = parse_webhook_url(url, allowed_hosts)?;
+    if allowed || host.parse::<IpAddr>().is_ok() {
+        return Ok((url.to_owned(), None));
+    }
+
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let addrs = (host.as_str(), port).to_socket_addrs()?.collect::<Vec<_>>();
+    if addrs.is_empty() {
+        bail!("{} does not resolve", host);
+    }
+    if let Some(addr) = addrs.iter().find(|addr| is_internal(addr.ip())) {
+        bail!("{} resolves to the internal address {}", host, addr.ip());
+    }
+
+    if parsed.scheme() == "http" {
+        pin_webhook_url(parsed, addrs[0])
+    } else {
+        Ok((url.to_owned(), None))
+    }
+}
+
+fn pin_webhook_url(
+    mut url: reqwest::Url,
+    addr: SocketAddr,
+) -> Result<(String, Option<String>), failure::Error> {
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_owned(),
+        (None, _) => bail!("url must have a host"),
+    };
+    url.set_ip_host(addr.ip())
+        .map_err(|_| format_err!("cannot send to {}", addr))?;
+
+    Ok((url.into_string(), Some(host)))
+}
+
+// Sends the deliveries of the endpoints registered through the admin API
+pub struct WebhookClient {
+    client: reqwest::Client,
+    allowed_hosts: Vec<String>,
+}
+
+impl WebhookClient {
+    pub fn new(allowed_hosts: Vec<String>) -> WebhookClient {
+        WebhookClient {
+            // a redirect could lead anywhere, so it is a failed delivery like any other 3xx
+            client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .redirect(reqwest::RedirectPolicy::none())
+                .build()
+                .expect("Failed to build the webhook client"),
+            allowed_hosts: allowed_hosts,
+        }
+    }
+}
+
+#[async_trait]
+impl IWebhookClient for WebhookClient {
+    async fn post(
+        &self,
+        url: String,
+        secret: String,
+        delivery: model::WebhookDelivery,
+    ) -> Result<u16, failure::Error> {
+        let (client, allowed_hosts) = (self.client.clone(), self.allowed_hosts.clone());
+
+        actix_web::web::block(move || {
+            let (url, host) = resolve_webhook_url(&url, &allowed_hosts)?;
+            let mut headers = vec![
+                ("X-Delivery-Id", delivery.id.as_str()),
+                ("X-Event-Id", delivery.event_id.as_str()),
+                ("X-Event-Type", delivery.event_type.as_str()),
+            ];
+            if let Some(host) = &host {
+                headers.push(("Host", host.as_str()));
+            }
+            post_signed(
+                &client,
+                &url,
+                secret.as_bytes(),
+                &headers,
+                delivery.payload.into_bytes(),
+            )
+        })
+        .compat()
+        .await
+        .map_err(|err| format_err!("Failed to post the webhook: {}", err))
+    }
+}
+
+// A request received by start_http_stand_in
+#[cfg(test)]
+pub struct StandInRequest {
+    pub path: String,
+    // lowercase names
+    pub headers: std::collections::HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+// An HTTP server on a random local port, answering every request with the next status of
+// `statuses` (then 200), and handing the requests to the receiver
+#[cfg(test)]
+pub fn start_http_stand_in(
+    statuses: Vec<u16>,
+) -> (String, std::sync::mpsc::Receiver<StandInRequest>) {
+    use std::io::{BufRead, BufReader, Read};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut statuses = statuses.into_iter();
+        for stream in listener.incoming() {
+            let mut writer = stream.unwrap();
+            let mut reader = BufReader::new(writer.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or("")
+                .to_owned();
+            let mut headers = std::collections::HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(colon) = line.find(':') {
+                    headers.insert(
+                        line[..colon].to_lowercase(),
+                        line[colon + 1..].trim().to_owned(),
+                    );
+                }
+            }
+            let length = headers
+                .get("content-length")
+                .map_or(0, |l| l.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let status = statuses.next().unwrap_or(200);
+            write!(
+                writer,
+                "HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            sender
+                .send(StandInRequest {
+                    path: path,
+                    headers: headers,
+                    body: body,
+                })
+                .ok();
+        }
+    });
+
+    (url, receiver)
+}
+
+#[test]
+fn signature_should_be_hmac_sha256() {
+    assert_eq!(
+        "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+        webhook_signature(b"key", b"The quick brown fox jumps over the lazy dog")
+    );
+}
+
+#[test]
+fn webhook_should_post_signed_events() {
+    let (url, requests) = start_http_stand_in(vec![200, 500]);
+    let client = reqwest::Client::new();
+    let event = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: "u1".to_owned(),
+    });
+
+    post_webhook(&client, &format!("{}/hook", url), b"secret", &event).unwrap();
+    let request = requests.recv().unwrap();
+    assert_eq!("/hook", request.path);
+    assert_eq!(event.id, request.headers["x-event-id"]);
+    assert_eq!("login_enabled", request.headers["x-event-type"]);
+    assert_eq!(
+        webhook_signature(b"secret", &request.body),
+        request.headers["x-signature-256"]
+    );
+    let body = serde_json::from_slice::<serde_json::Value>(&request.body).unwrap();
+    assert_eq!("u1", body["data"]["user_id"]);
+
+    assert!(post_webhook(&client, &url, b"secret", &event).is_err());
+}
+
+#[test]
+fn webhook_urls_should_not_be_internal() {
+    let allowed = vec!["127.0.0.1".to_owned()];
+    let check = |url: &str| check_webhook_url(url, &allowed);
+
+    assert!(check("https://hooks.example.com/events").is_ok());
+    assert!(check("http://93.184.216.34:8080/").is_ok());
+    assert!(check("http://127.0.0.1:9000/hook").is_ok());
+    for url in &[
+        "ftp://hooks.example.com/",
+        "http://localhost/hook",
+        "http://api.localhost/hook",
+        "http://127.0.0.2/",
+        "http://10.0.0.1/",
+        "http://172.16.5.4/",
+        "http://192.168.1.1/",
+        "http://169.254.169.254/latest/meta-data/",
+        "http://100.64.0.1/",
+        "http://0.0.0.0/",
+        "http://[::1]/",
+        "http://[fd00::1]/",
+        "http://[fe80::1]/",
+        "http://[::ffff:10.0.0.1]/",
+    ] {
+        assert!(check(url).is_err(), "{} should be rejected", url);
+    }
+
+    // names are checked by what they resolve to, at every delivery
+    assert!(resolve_webhook_url("http://localhost./hook", &[]).is_err());
+}
+
+#[test]
+fn http_webhooks_should_be_sent_to_the_checked_address() {
+    let addr = "93.184.216.34:8080".parse().unwrap();
+    let url = reqwest::Url::parse("http://hooks.example.com:8080/hook?a=1").unwrap();
+    assert_eq!(
+        (
+            "http://93.184.216.34:8080/hook?a=1".to_owned(),
+            Some("hooks.example.com:8080".to_owned())
+        ),
+        pin_webhook_url(url, addr).unwrap()
+    );
+
+    let addr = "[2606:2800:220:1::1]:80".parse().unwrap();
+    let url = reqwest::Url::parse("http://hooks.example.com/hook").unwrap();
+    assert_eq!(
+        (
+            "http://[2606:2800:220:1::1]/hook".to_owned(),
+            Some("hooks.example.com".to_owned())
+        ),
+        pin_webhook_url(url, addr).unwrap()
+    );
+}
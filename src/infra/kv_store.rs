@@ -0,0 +1,746 @@
+use crate::domain::interface::IKeyValueStore;
+use async_trait::async_trait;
+use futures::compat::*;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const NOT_AN_INTEGER: &str = "ERR value is not an integer or out of range";
+const OVERFLOW: &str = "ERR increment or decrement would overflow";
+
+// like Redis' string2ll: no sign but '-', and no leading zeros or spaces
+fn parse_integer(value: &str) -> Option<i64> {
+    let digits = if value.starts_with('-') {
+        &value[1..]
+    } else {
+        value
+    };
+    if digits.is_empty()
+        || !digits.bytes().all(|b| b.is_ascii_digit())
+        || (digits.starts_with('0') && value != "0")
+    {
+        return None;
+    }
+
+    value.parse().ok()
+}
+
+// The keys of a store, with Redis' semantics for expiry and counters
+#[derive(Default)]
+struct Entries {
+    values: HashMap<String, (String, Option<Instant>)>,
+    // expired entries are dropped when they are read, or by a sweep once there are this many
+    next_sweep: usize,
+}
+
+impl Entries {
+    fn live(&mut self, key: &str, now: Instant) -> Option<&mut (String, Option<Instant>)> {
+        let expired = match self.values.get(key) {
+            Some((_, Some(expires_at))) => *expires_at <= now,
+            _ => false,
+        };
+        if expired {
+            self.values.remove(key);
+        }
+
+        self.values.get_mut(key)
+    }
+
+    fn get(&mut self, key: &str, now: Instant) -> Option<String> {
+        self.live(key, now).map(|(value, _)| value.clone())
+    }
+
+    fn set(&mut self, key: String, value: String, expires_at: Option<Instant>, now: Instant) {
+        if self.values.len() >= self.next_sweep {
+            self.values
+                .retain(|_, (_, expires_at)| expires_at.map_or(true, |at| at > now));
+            self.next_sweep = std::cmp::max(1024, self.values.len() * 2);
+        }
+
+        self.values.insert(key, (value, expires_at));
+    }
+
+    fn set_if_absent(
+        &mut self,
+        key: String,
+        value: String,
+        expires_at: Option<Instant>,
+        now: Instant,
+    ) -> bool {
+        if self.live(&key, now).is_some() {
+            return false;
+        }
+        self.set(key, value, expires_at, now);
+
+        true
+    }
+
+    fn delete(&mut self, key: &str, now: Instant) -> bool {
+        self.live(key, now).is_some() && self.values.remove(key).is_some()
+    }
+
+    // keeps the expiry of an existing counter; fails with Redis' errors, leaving the value as is
+    fn incr(&mut self, key: &str, now: Instant) -> Result<i64, &'static str> {
+        let (count, expires_at) = match self.live(key, now) {
+            Some((value, expires_at)) => {
+                let count = parse_integer(value).ok_or(NOT_AN_INTEGER)?;
+                (count.checked_add(1).ok_or(OVERFLOW)?, *expires_at)
+            }
+            None => (1, None),
+        };
+        self.set(key.to_owned(), count.to_string(), expires_at, now);
+
+        Ok(count)
+    }
+
+    fn expire(&mut self, key: &str, expires_at: Instant, now: Instant) -> bool {
+        match self.live(key, now) {
+            Some(entry) => {
+                entry.1 = Some(expires_at);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Kept in the process, so only shared by its workers (KV_STORE_URL unset)
+#[derive(Default)]
+pub struct MemoryKeyValueStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryKeyValueStore {
+    pub fn new() -> MemoryKeyValueStore {
+        MemoryKeyValueStore::default()
+    }
+}
+
+#[async_trait]
+impl IKeyValueStore for MemoryKeyValueStore {
+    async fn get(&self, key: String) -> Result<Option<String>, failure::Error> {
+        Ok(self.entries.lock().unwrap().get(&key, Instant::now()))
+    }
+
+    async fn set(
+        &self,
+        key: String,
+        value: String,
+        ttl: Option<Duration>,
+    ) -> Result<(), failure::Error> {
+        let now = Instant::now();
+        self.entries
+            .lock()
+            .unwrap()
+            .set(key, value, ttl.map(|ttl| now + ttl), now);
+
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: String,
+        value: String,
+        ttl: Duration,
+    ) -> Result<bool, failure::Error> {
+        let now = Instant::now();
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .set_if_absent(key, value, Some(now + ttl), now))
+    }
+
+    async fn delete(&self, key: String) -> Result<bool, failure::Error> {
+        Ok(self.entries.lock().unwrap().delete(&key, Instant::now()))
+    }
+
+    async fn incr(&self, key: String, ttl: Duration) -> Result<i64, failure::Error> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries
+            .incr(&key, now)
+            .map_err(|err| format_err!("Key-value store error: {}", err))?;
+        if count == 1 {
+            entries.expire(&key, now + ttl, now);
+        }
+
+        Ok(count)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// a RESP array of bulk strings, which is how clients send commands
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    buf
+}
+
+fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    let line = line.trim_end_matches("\r\n");
+    if line.is_empty() {
+        return Err(invalid_data("empty RESP line".to_owned()));
+    }
+    let (kind, rest) = line.split_at(1);
+    let len = || {
+        rest.parse::<i64>()
+            .map_err(|_| invalid_data(format!("invalid RESP length: {}", rest)))
+    };
+
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_owned())),
+        "-" => Ok(Reply::Error(rest.to_owned())),
+        ":" => Ok(Reply::Integer(len()?)),
+        "$" => {
+            let len = len()?;
+            if len < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            // followed by \r\n
+            let mut buf = vec![0; len as usize + 2];
+            reader.read_exact(&mut buf)?;
+            buf.truncate(len as usize);
+            Ok(Reply::Bulk(Some(buf)))
+        }
+        "*" => {
+            let len = len()?;
+            if len < 0 {
+                return Ok(Reply::Array(None));
+            }
+            let items = (0..len)
+                .map(|_| read_reply(reader))
+                .collect::<io::Result<Vec<_>>>()?;
+            Ok(Reply::Array(Some(items)))
+        }
+        _ => Err(invalid_data(format!("unknown RESP reply: {}", line))),
+    }
+}
+
+fn unexpected(reply: Reply) -> io::Error {
+    invalid_data(format!("unexpected reply: {:?}", reply))
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn command(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        self.writer.write_all(&encode_command(args))?;
+        match read_reply(&mut self.reader)? {
+            Reply::Error(message) => Err(io::Error::new(io::ErrorKind::Other, message)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+// from redis://[:password@]host:port[/db]
+#[derive(Debug, PartialEq)]
+struct RedisAddr {
+    addr: String,
+    password: Option<String>,
+    db: Option<String>,
+}
+
+fn parse_url(url: &str) -> Result<RedisAddr, failure::Error> {
+    let rest = if url.starts_with("redis://") {
+        &url["redis://".len()..]
+    } else {
+        return Err(format_err!("KV_STORE_URL must start with redis://"));
+    };
+
+    let (password, rest) = match rest.rfind('@') {
+        Some(at) => (
+            Some(rest[..at].trim_start_matches(':').to_owned()),
+            &rest[at + 1..],
+        ),
+        None => (None, rest),
+    };
+    let (addr, db) = match rest.find('/') {
+        Some(slash) if slash + 1 < rest.len() => {
+            (&rest[..slash], Some(rest[slash + 1..].to_owned()))
+        }
+        Some(slash) => (&rest[..slash], None),
+        None => (rest, None),
+    };
+    if addr.is_empty() || addr.starts_with(':') {
+        return Err(format_err!("KV_STORE_URL has no host"));
+    }
+
+    Ok(RedisAddr {
+        addr: if addr.contains(':') {
+            addr.to_owned()
+        } else {
+            format!("{}:6379", addr)
+        },
+        password: password,
+        db: db,
+    })
+}
+
+// idle connections kept for the next command
+const MAX_IDLE_CONNECTIONS: usize = 8;
+// per address the host resolves to
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// for each command, so that a server which stopped answering does not hold the thread
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the name is resolved without a timeout, by the system's resolver
+fn open(addr: &str) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} does not resolve to any address", addr),
+    );
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last = err,
+        }
+    }
+
+    Err(last)
+}
+
+struct RedisClient {
+    addr: RedisAddr,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl RedisClient {
+    fn connect(&self) -> io::Result<Connection> {
+        let stream = open(&self.addr.addr)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let mut conn = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        if let Some(password) = &self.addr.password {
+            conn.command(&[b"AUTH", password.as_bytes()])?;
+        }
+        if let Some(db) = &self.addr.db {
+            conn.command(&[b"SELECT", db.as_bytes()])?;
+        }
+
+        Ok(conn)
+    }
+
+    // a connection which failed is dropped, since a reply may still be pending on it
+    fn with_connection<R>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> io::Result<R>,
+    ) -> io::Result<R> {
+        let idle = self.idle.lock().unwrap().pop();
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => self.connect()?,
+        };
+
+        let result = f(&mut conn)?;
+
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(conn);
+        }
+        Ok(result)
+    }
+}
+
+// A Redis (or any server speaking RESP) shared by every instance (KV_STORE_URL)
+// Plain TCP without TLS, like SmtpMailer
+pub struct RedisKeyValueStore {
+    client: Arc<RedisClient>,
+}
+
+impl RedisKeyValueStore {
+    // connects on the first command
+    pub fn new(url: &str) -> Result<RedisKeyValueStore, failure::Error> {
+        Ok(RedisKeyValueStore {
+            client: Arc::new(RedisClient {
+                addr: parse_url(url)?,
+                idle: Mutex::new(Vec::new()),
+            }),
+        })
+    }
+
+    async fn call<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> io::Result<R> + Send + 'static,
+    ) -> Result<R, failure::Error> {
+        let client = self.client.clone();
+
+        actix_web::web::block(move || client.with_connection(f))
+            .compat()
+            .await
+            .map_err(|err| format_err!("Key-value store error: {}", err))
+    }
+}
+
+#[async_trait]
+impl IKeyValueStore for RedisKeyValueStore {
+    async fn get(&self, key: String) -> Result<Option<String>, failure::Error> {
+        let value = self
+            .call(move |conn| match conn.command(&[b"GET", key.as_bytes()])? {
+                Reply::Bulk(value) => Ok(value),
+                reply => Err(unexpected(reply)),
+            })
+            .await?;
+
+        match value {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(
+        &self,
+        key: String,
+        value: String,
+        ttl: Option<Duration>,
+    ) -> Result<(), failure::Error> {
+        self.call(move |conn| {
+            let reply = match ttl {
+                Some(ttl) => {
+                    let ms = ttl.as_millis().to_string();
+                    conn.command(&[
+                        b"SET",
+                        key.as_bytes(),
+                        value.as_bytes(),
+                        b"PX",
+                        ms.as_bytes(),
+                    ])?
+                }
+                None => conn.command(&[b"SET", key.as_bytes(), value.as_bytes()])?,
+            };
+            match reply {
+                Reply::Status(_) => Ok(()),
+                reply => Err(unexpected(reply)),
+            }
+        })
+        .await
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: String,
+        value: String,
+        ttl: Duration,
+    ) -> Result<bool, failure::Error> {
+        self.call(move |conn| {
+            let ms = ttl.as_millis().to_string();
+            let args: &[&[u8]] = &[
+                b"SET",
+                key.as_bytes(),
+                value.as_bytes(),
+                b"NX",
+                b"PX",
+                ms.as_bytes(),
+            ];
+            // a nil reply when the key is already set
+            match conn.command(args)? {
+                Reply::Status(_) => Ok(true),
+                Reply::Bulk(None) => Ok(false),
+                reply => Err(unexpected(reply)),
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, key: String) -> Result<bool, failure::Error> {
+        self.call(move |conn| match conn.command(&[b"DEL", key.as_bytes()])? {
+            Reply::Integer(deleted) => Ok(deleted > 0),
+            reply => Err(unexpected(reply)),
+        })
+        .await
+    }
+
+    // the counter would never expire if the process died between INCR and PEXPIRE;
+    // the window is short, and a stuck key only needs a DEL
+    async fn incr(&self, key: String, ttl: Duration) -> Result<i64, failure::Error> {
+        self.call(move |conn| {
+            let count = match conn.command(&[b"INCR", key.as_bytes()])? {
+                Reply::Integer(count) => count,
+                reply => return Err(unexpected(reply)),
+            };
+            if count == 1 {
+                let ms = ttl.as_millis().to_string();
+                conn.command(&[b"PEXPIRE", key.as_bytes(), ms.as_bytes()])?;
+            }
+
+            Ok(count)
+        })
+        .await
+    }
+}
+
+// Serves the commands used above from Entries, on a random local port
+// Only accepts commands after `AUTH password` when a password is given
+#[cfg(test)]
+fn start_fake_resp_server(password: Option<&'static str>) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let entries = Arc::new(Mutex::new(Entries::default()));
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut writer = stream.unwrap();
+            let mut reader = BufReader::new(writer.try_clone().unwrap());
+            let entries = entries.clone();
+
+            std::thread::spawn(move || {
+                let mut authenticated = password.is_none();
+                while let Ok(Reply::Array(Some(args))) = read_reply(&mut reader) {
+                    let args = args
+                        .into_iter()
+                        .map(|arg| match arg {
+                            Reply::Bulk(Some(arg)) => String::from_utf8(arg).unwrap(),
+                            arg => panic!("unexpected argument: {:?}", arg),
+                        })
+                        .collect::<Vec<_>>();
+                    let args = args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>();
+                    let now = Instant::now();
+                    let mut entries = entries.lock().unwrap();
+                    let ms = |arg: &str| Duration::from_millis(arg.parse().unwrap());
+
+                    let reply = match args.as_slice() {
+                        ["AUTH", given] if Some(*given) == password => {
+                            authenticated = true;
+                            "+OK\r\n".to_owned()
+                        }
+                        ["AUTH", _] => "-ERR invalid password\r\n".to_owned(),
+                        _ if !authenticated => "-NOAUTH Authentication required.\r\n".to_owned(),
+                        ["SELECT", _] => "+OK\r\n".to_owned(),
+                        ["GET", key] => match entries.get(key, now) {
+                            Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+                            None => "$-1\r\n".to_owned(),
+                        },
+                        ["SET", key, value] => {
+                            entries.set(key.to_string(), value.to_string(), None, now);
+                            "+OK\r\n".to_owned()
+                        }
+                        ["SET", key, value, "PX", ttl] => {
+                            entries.set(
+                                key.to_string(),
+                                value.to_string(),
+                                Some(now + ms(*ttl)),
+                                now,
+                            );
+                            "+OK\r\n".to_owned()
+                        }
+                        ["SET", key, value, "NX", "PX", ttl] => {
+                            let expires_at = Some(now + ms(*ttl));
+                            if entries.set_if_absent(
+                                key.to_string(),
+                                value.to_string(),
+                                expires_at,
+                                now,
+                            ) {
+                                "+OK\r\n".to_owned()
+                            } else {
+                                "$-1\r\n".to_owned()
+                            }
+                        }
+                        ["DEL", key] => format!(":{}\r\n", entries.delete(key, now) as i64),
+                        ["INCR", key] => match entries.incr(key, now) {
+                            Ok(count) => format!(":{}\r\n", count),
+                            Err(err) => format!("-{}\r\n", err),
+                        },
+                        ["PEXPIRE", key, ttl] => {
+                            format!(":{}\r\n", entries.expire(key, now + ms(*ttl), now) as i64)
+                        }
+                        _ => format!("-ERR unknown command {:?}\r\n", args),
+                    };
+                    writer.write_all(reply.as_bytes()).unwrap();
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+#[test]
+fn entries_should_expire() {
+    let mut entries = Entries::default();
+    let now = Instant::now();
+    let later = now + Duration::from_secs(10);
+
+    entries.set("kept".to_owned(), "1".to_owned(), None, now);
+    entries.set("expiring".to_owned(), "1".to_owned(), Some(later), now);
+    assert_eq!(Some("1".to_owned()), entries.get("expiring", now));
+    assert_eq!(None, entries.get("expiring", later));
+    assert!(!entries.delete("expiring", later));
+    assert!(entries.delete("kept", later));
+
+    assert!(entries.set_if_absent("once".to_owned(), "1".to_owned(), Some(later), now));
+    assert!(!entries.set_if_absent("once".to_owned(), "2".to_owned(), None, now));
+    assert_eq!(Some("1".to_owned()), entries.get("once", now));
+    assert!(entries.set_if_absent("once".to_owned(), "3".to_owned(), None, later));
+
+    // a counter keeps the expiry it was given when created
+    assert_eq!(Ok(1), entries.incr("counter", now));
+    assert!(entries.expire("counter", later, now));
+    assert_eq!(Ok(2), entries.incr("counter", now));
+    assert_eq!(Ok(1), entries.incr("counter", later));
+}
+
+#[test]
+fn incr_should_fail_like_redis() {
+    let mut entries = Entries::default();
+    let now = Instant::now();
+
+    entries.set("text".to_owned(), "abc".to_owned(), None, now);
+    assert_eq!(Err(NOT_AN_INTEGER), entries.incr("text", now));
+    assert_eq!(Some("abc".to_owned()), entries.get("text", now));
+    for value in &["", " 1", "+1", "01", "-0", "1.5"] {
+        entries.set("counter".to_owned(), value.to_string(), None, now);
+        assert_eq!(
+            Err(NOT_AN_INTEGER),
+            entries.incr("counter", now),
+            "{}",
+            value
+        );
+    }
+    entries.set("counter".to_owned(), "-2".to_owned(), None, now);
+    assert_eq!(Ok(-1), entries.incr("counter", now));
+    entries.set(
+        "counter".to_owned(),
+        i64::max_value().to_string(),
+        None,
+        now,
+    );
+    assert_eq!(Err(OVERFLOW), entries.incr("counter", now));
+}
+
+#[test]
+fn url_should_be_parsed() {
+    assert_eq!(
+        RedisAddr {
+            addr: "localhost:6379".to_owned(),
+            password: Some("secret".to_owned()),
+            db: Some("2".to_owned()),
+        },
+        parse_url("redis://:secret@localhost:6379/2").unwrap()
+    );
+    assert_eq!(
+        RedisAddr {
+            addr: "localhost:6379".to_owned(),
+            password: None,
+            db: None,
+        },
+        parse_url("redis://localhost:6379/").unwrap()
+    );
+    assert!(parse_url("localhost:6379").is_err());
+    assert_eq!(
+        "localhost:6379",
+        parse_url("redis://localhost").unwrap().addr
+    );
+    assert!(parse_url("redis://:secret@/0").is_err());
+}
+
+#[test]
+fn redis_store_should_talk_resp() {
+    use futures::{FutureExt, TryFutureExt};
+
+    let addr = start_fake_resp_server(Some("secret"));
+    let store = RedisKeyValueStore::new(&format!("redis://:secret@{}/1", addr)).unwrap();
+
+    let fut = async move {
+        let ttl = Duration::from_secs(60);
+        assert_eq!(None, store.get("key".to_owned()).await.unwrap());
+        store
+            .set("key".to_owned(), "value".to_owned(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("value".to_owned()),
+            store.get("key".to_owned()).await.unwrap()
+        );
+        assert!(!store
+            .set_if_absent("key".to_owned(), "other".to_owned(), ttl)
+            .await
+            .unwrap());
+        assert!(store.delete("key".to_owned()).await.unwrap());
+        assert!(!store.delete("key".to_owned()).await.unwrap());
+        assert!(store
+            .set_if_absent("key".to_owned(), "other".to_owned(), ttl)
+            .await
+            .unwrap());
+        assert_eq!(
+            Some("other".to_owned()),
+            store.get("key".to_owned()).await.unwrap()
+        );
+
+        assert_eq!(1, store.incr("counter".to_owned(), ttl).await.unwrap());
+        assert_eq!(2, store.incr("counter".to_owned(), ttl).await.unwrap());
+        store
+            .set("text".to_owned(), "abc".to_owned(), None)
+            .await
+            .unwrap();
+        assert!(store.incr("text".to_owned(), ttl).await.is_err());
+
+        store
+            .set(
+                "short".to_owned(),
+                "value".to_owned(),
+                Some(Duration::from_millis(1)),
+            )
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(None, store.get("short".to_owned()).await.unwrap());
+    };
+    actix_web::test::block_on(Box::pin(fut.map(Ok::<_, ()>)).compat()).unwrap();
+
+    // a server which never answers fails the command instead of hanging
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let silent_addr = silent.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut held = Vec::new();
+        for stream in silent.incoming() {
+            held.push(stream);
+        }
+    });
+    let store = RedisKeyValueStore::new(&format!("redis://{}", silent_addr)).unwrap();
+    let fut = async move { store.get("key".to_owned()).await.is_err() };
+    let started = Instant::now();
+    let failed = actix_web::test::block_on(Box::pin(fut.map(Ok::<_, ()>)).compat()).unwrap();
+    assert!(failed);
+    assert!(started.elapsed() >= IO_TIMEOUT);
+    assert!(started.elapsed() < IO_TIMEOUT + Duration::from_secs(1));
+
+    // the password is required
+    let store = RedisKeyValueStore::new(&format!("redis://{}", addr)).unwrap();
+    let fut = async move { store.get("key".to_owned()).await.is_err() };
+    let rejected = actix_web::test::block_on(Box::pin(fut.map(Ok::<_, ()>)).compat()).unwrap();
+    assert!(rejected);
+}
@@ -0,0 +1,139 @@
+use crate::domain::interface::{IMailer, Mail};
+use async_trait::async_trait;
+use futures::compat::*;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+fn to_message(from: &str, mail: &Mail) -> String {
+    // dot-stuffing: a line starting with "." would end the DATA section
+    let body = mail
+        .body
+        .lines()
+        .map(|l| {
+            if l.starts_with('.') {
+                format!(".{}", l)
+            } else {
+                l.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n");
+
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        from, mail.to, mail.subject, body
+    )
+}
+
+// Writes each mail into the directory as an .eml file (for local development and tests)
+pub struct FileMailer {
+    dir: std::path::PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<std::path::PathBuf>, from: String) -> FileMailer {
+        FileMailer {
+            dir: dir.into(),
+            from: from,
+        }
+    }
+}
+
+#[async_trait]
+impl IMailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), failure::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(
+            self.dir
+                .join(format!("{}.eml", ulid::Ulid::new().to_string())),
+            to_message(&self.from, &mail),
+        )?;
+
+        Ok(())
+    }
+}
+
+// Plain SMTP without TLS/AUTH, meant for a local sink such as MailHog (see docker-compose.yml)
+pub struct SmtpMailer {
+    addr: String,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(addr: String, from: String) -> SmtpMailer {
+        SmtpMailer {
+            addr: addr,
+            from: from,
+        }
+    }
+}
+
+fn read_reply(reader: &mut impl BufRead, expected: &str) -> std::io::Result<()> {
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with(expected) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("unexpected SMTP reply: {}", line.trim_end()),
+            ));
+        }
+        // "250-..." continues, "250 ..." is the last line
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn send_smtp(addr: &str, from: &str, mail: &Mail) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    read_reply(&mut reader, "220")?;
+    for (command, expected) in &[
+        ("HELO localhost".to_owned(), "250"),
+        (format!("MAIL FROM:<{}>", from), "250"),
+        (format!("RCPT TO:<{}>", mail.to), "250"),
+        ("DATA".to_owned(), "354"),
+    ] {
+        write!(stream, "{}\r\n", command)?;
+        read_reply(&mut reader, expected)?;
+    }
+
+    write!(stream, "{}.\r\n", to_message(from, mail))?;
+    read_reply(&mut reader, "250")?;
+    write!(stream, "QUIT\r\n")?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl IMailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), failure::Error> {
+        let addr = self.addr.clone();
+        let from = self.from.clone();
+
+        actix_web::web::block(move || send_smtp(&addr, &from, &mail))
+            .compat()
+            .await
+            .map_err(|err| format_err!("Failed to send mail: {}", err))
+    }
+}
+
+#[test]
+fn message_should_dot_stuff_body() {
+    let message = to_message(
+        "noreply@example.com",
+        &Mail {
+            to: "user@example.com".to_owned(),
+            subject: "subject".to_owned(),
+            body: "hello\n.\nbye".to_owned(),
+        },
+    );
+
+    assert!(message.starts_with("From: noreply@example.com\r\nTo: user@example.com\r\n"));
+    assert!(message.ends_with("\r\n\r\nhello\r\n..\r\nbye\r\n"));
+}
@@ -0,0 +1,150 @@
+use crate::config::ReplicaConfig;
+use crate::infra::DBConnPool;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable};
+use serde::*;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Arc;
+
+// -1 while the lag is unknown (not measured yet, or the last check failed)
+const UNKNOWN_LAG: i64 = -1;
+
+#[cfg(feature = "mysql")]
+const WRITE_HEARTBEAT: &str = "REPLACE INTO replica_heartbeats (id, beat_at) VALUES (1, NOW(6))";
+#[cfg(feature = "mysql")]
+const READ_LAG: &str = "SELECT TIMESTAMPDIFF(MICROSECOND, beat_at, NOW(6)) DIV 1000 AS lag_ms \
+     FROM replica_heartbeats WHERE id = 1";
+
+#[cfg(feature = "postgres")]
+const WRITE_HEARTBEAT: &str = "INSERT INTO replica_heartbeats (id, beat_at) \
+     VALUES (1, clock_timestamp()::timestamp) \
+     ON CONFLICT (id) DO UPDATE SET beat_at = EXCLUDED.beat_at";
+#[cfg(feature = "postgres")]
+const READ_LAG: &str =
+    "SELECT CAST(EXTRACT(EPOCH FROM clock_timestamp()::timestamp - beat_at) * 1000 AS BIGINT) \
+     AS lag_ms FROM replica_heartbeats WHERE id = 1";
+
+// e.g. a copy kept up to date by a replication tool
+#[cfg(feature = "sqlite")]
+const WRITE_HEARTBEAT: &str = "REPLACE INTO replica_heartbeats (id, beat_at) \
+     VALUES (1, strftime('%Y-%m-%d %H:%M:%f', 'now'))";
+#[cfg(feature = "sqlite")]
+const READ_LAG: &str =
+    "SELECT CAST((julianday('now') - julianday(beat_at)) * 86400000 AS INTEGER) \
+     AS lag_ms FROM replica_heartbeats WHERE id = 1";
+
+#[derive(QueryableByName)]
+struct Lag {
+    #[sql_type = "Nullable<BigInt>"]
+    lag_ms: Option<i64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReplicaStatus {
+    // reads go to the primary while this is false
+    pub healthy: bool,
+    pub lag_ms: Option<i64>,
+}
+
+// Updated by the monitor thread, read by DBExecutor to route the reads
+pub struct ReplicaHealth {
+    healthy: AtomicBool,
+    lag_ms: AtomicI64,
+}
+
+impl ReplicaHealth {
+    fn new() -> ReplicaHealth {
+        // not used until the first check has passed
+        ReplicaHealth {
+            healthy: AtomicBool::new(false),
+            lag_ms: AtomicI64::new(UNKNOWN_LAG),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> ReplicaStatus {
+        let lag_ms = self.lag_ms.load(Ordering::Relaxed);
+        ReplicaStatus {
+            healthy: self.is_healthy(),
+            lag_ms: if lag_ms == UNKNOWN_LAG {
+                None
+            } else {
+                Some(lag_ms)
+            },
+        }
+    }
+
+    fn update(&self, lag_ms: Option<i64>, max_lag_ms: i64) {
+        let healthy = lag_ms.map_or(false, |lag| lag <= max_lag_ms);
+        let was_healthy = self.healthy.swap(healthy, Ordering::Relaxed);
+        self.lag_ms
+            .store(lag_ms.unwrap_or(UNKNOWN_LAG), Ordering::Relaxed);
+
+        if was_healthy && !healthy {
+            warn!(
+                "Replica is unhealthy (lag: {:?}ms), reading from the primary",
+                lag_ms
+            );
+        } else if !was_healthy && healthy {
+            info!("Replica is healthy (lag: {:?}ms)", lag_ms);
+        }
+    }
+}
+
+// Writes a heartbeat on the primary, then measures how old the replicated one is
+// This relies on the clocks of the primary and the replica being in sync
+fn check(primary: &DBConnPool, replica: &DBConnPool) -> Result<Option<i64>, failure::Error> {
+    let conn = primary.get_connection()?;
+    diesel::sql_query(WRITE_HEARTBEAT).execute(&**conn)?;
+
+    let conn = replica.get_connection()?;
+    let lag = diesel::sql_query(READ_LAG).get_results::<Lag>(&**conn)?;
+
+    Ok(lag.into_iter().next().and_then(|l| l.lag_ms))
+}
+
+pub fn start_replica_monitor(
+    primary: DBConnPool,
+    replica: DBConnPool,
+    config: &ReplicaConfig,
+) -> Arc<ReplicaHealth> {
+    let health = Arc::new(ReplicaHealth::new());
+    let max_lag_ms = config.max_lag.as_millis() as i64;
+    let interval = config.check_interval;
+
+    let monitored = health.clone();
+    std::thread::Builder::new()
+        .name("replica-monitor".to_owned())
+        .spawn(move || loop {
+            let lag_ms = check(&primary, &replica).unwrap_or_else(|err| {
+                warn!("Failed to check the replica: {}", err);
+                None
+            });
+            monitored.update(lag_ms, max_lag_ms);
+
+            std::thread::sleep(interval);
+        })
+        .expect("Failed to spawn the replica monitor thread");
+
+    health
+}
+
+#[test]
+fn replica_should_be_unhealthy_when_lagging() {
+    let health = ReplicaHealth::new();
+    assert!(!health.is_healthy());
+
+    health.update(Some(100), 1000);
+    assert!(health.is_healthy());
+    assert_eq!(Some(100), health.status().lag_ms);
+
+    health.update(Some(1500), 1000);
+    assert!(!health.is_healthy());
+
+    health.update(None, 1000);
+    assert!(!health.is_healthy());
+    assert_eq!(None, health.status().lag_ms);
+}
@@ -0,0 +1,396 @@
+use futures::task::{Context, Poll};
+use serde::*;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Identifies a span inside a trace
+// This is the only thing which travels across thread/actor boundaries
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SpanContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    // a ULID made by this service for the root span, shared by its children
+    // (unlike the trace_id, a client cannot choose it, so it identifies one request)
+    pub request_id: u128,
+}
+
+impl SpanContext {
+    fn new_root() -> SpanContext {
+        SpanContext {
+            trace_id: ulid::Ulid::new().0,
+            span_id: new_span_id(),
+            request_id: ulid::Ulid::new().0,
+        }
+    }
+
+    // The parent of a trace continued from another service
+    pub fn remote(trace_id: u128, span_id: u64) -> SpanContext {
+        SpanContext {
+            trace_id: trace_id,
+            span_id: span_id,
+            request_id: ulid::Ulid::new().0,
+        }
+    }
+
+    fn new_child(&self) -> SpanContext {
+        SpanContext {
+            trace_id: self.trace_id,
+            span_id: new_span_id(),
+            request_id: self.request_id,
+        }
+    }
+
+    // as in the X-Request-Id header and the `request_id` of error responses
+    pub fn request_id(&self) -> String {
+        ulid::Ulid(self.request_id).to_string()
+    }
+}
+
+fn new_span_id() -> u64 {
+    // the lower 80 bits of ulid are random
+    ulid::Ulid::new().0 as u64
+}
+
+fn unix_nanos(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SpanData {
+    pub name: String,
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub start_time_unix_nano: u128,
+    pub end_time_unix_nano: u128,
+    pub attributes: Vec<(String, String)>,
+}
+
+pub trait SpanExporter {
+    fn export(&self, span: SpanData);
+}
+
+// -------------------
+// Global tracer
+// -------------------
+
+lazy_static! {
+    static ref EXPORTER: RwLock<Option<Arc<dyn SpanExporter + Send + Sync>>> = RwLock::new(None);
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<SpanContext>> = RefCell::new(None);
+}
+
+// Install the exporter. Spans are discarded until this is called.
+pub fn init_tracer(exporter: Arc<dyn SpanExporter + Send + Sync>) {
+    *EXPORTER.write().unwrap() = Some(exporter);
+}
+
+// Choose the exporter from TRACE_EXPORTER (otlp, file or none)
+// Fails when the trace file cannot be opened
+pub fn init_tracer_from_env() -> std::io::Result<()> {
+    match std::env::var("TRACE_EXPORTER").as_ref().map(|s| s.as_str()) {
+        Ok("otlp") => init_tracer(Arc::new(OtlpExporter::new(
+            std::env::var("OTLP_ENDPOINT")
+                .unwrap_or_else(|_| "http://localhost:4318/v1/traces".to_owned()),
+        ))),
+        Ok("file") => init_tracer(Arc::new(FileExporter::new(
+            std::env::var("TRACE_FILE").unwrap_or_else(|_| "trace.ndjson".to_owned()),
+        )?)),
+        _ => (),
+    }
+
+    Ok(())
+}
+
+// The span which is running on this thread (see Instrumented)
+pub fn current_span() -> Option<SpanContext> {
+    CURRENT.with(|c| *c.borrow())
+}
+
+// Runs f with the given span as the current one
+pub fn with_span<R>(context: Option<SpanContext>, f: impl FnOnce() -> R) -> R {
+    let prev = CURRENT.with(|c| c.replace(context));
+    let result = f();
+    CURRENT.with(|c| *c.borrow_mut() = prev);
+    result
+}
+
+// Span is recorded to the exporter when dropped
+pub struct Span {
+    name: String,
+    context: SpanContext,
+    parent: Option<SpanContext>,
+    start: SystemTime,
+    attributes: Vec<(String, String)>,
+}
+
+impl Span {
+    // Start a span as a child of the current one
+    pub fn new(name: impl Into<String>) -> Span {
+        Span::with_parent(name, current_span())
+    }
+
+    // Start a span as a child of the given one (use this after crossing a thread boundary)
+    pub fn with_parent(name: impl Into<String>, parent: Option<SpanContext>) -> Span {
+        Span {
+            name: name.into(),
+            context: parent
+                .map(|p| p.new_child())
+                .unwrap_or_else(SpanContext::new_root),
+            parent: parent,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn context(&self) -> SpanContext {
+        self.context
+    }
+
+    pub fn set_attribute(&mut self, key: impl Into<String>, value: impl ToString) {
+        self.attributes.push((key.into(), value.to_string()));
+    }
+
+    pub fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        with_span(Some(self.context), f)
+    }
+
+    fn to_data(&mut self) -> SpanData {
+        SpanData {
+            name: std::mem::replace(&mut self.name, String::new()),
+            trace_id: format!("{:032x}", self.context.trace_id),
+            span_id: format!("{:016x}", self.context.span_id),
+            parent_span_id: self.parent.map(|p| format!("{:016x}", p.span_id)),
+            start_time_unix_nano: unix_nanos(self.start),
+            end_time_unix_nano: unix_nanos(SystemTime::now()),
+            attributes: std::mem::replace(&mut self.attributes, Vec::new()),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(exporter) = EXPORTER.read().unwrap().as_ref() {
+            exporter.export(self.to_data());
+        }
+    }
+}
+
+// -------------------
+// Future adapters
+// -------------------
+
+// Makes the span current while the inner future is polled, and closes it on completion
+pub struct Instrumented<F> {
+    inner: F,
+    span: Option<Span>,
+}
+
+pub trait Instrument: Sized {
+    fn instrument(self, span: Span) -> Instrumented<Self> {
+        Instrumented {
+            inner: self,
+            span: Some(span),
+        }
+    }
+}
+
+impl<F> Instrument for F {}
+
+impl<F> Instrumented<F> {
+    pub fn span_mut(&mut self) -> Option<&mut Span> {
+        self.span.as_mut()
+    }
+}
+
+impl<F: Future + Unpin> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        let context = this.span.as_ref().map(|s| s.context);
+        let inner = &mut this.inner;
+        let result = with_span(context, || Pin::new(inner).poll(cx));
+        if result.is_ready() {
+            this.span.take();
+        }
+
+        result
+    }
+}
+
+impl<F: futures01::Future> futures01::Future for Instrumented<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> futures01::Poll<F::Item, F::Error> {
+        let context = self.span.as_ref().map(|s| s.context);
+        let inner = &mut self.inner;
+        let result = with_span(context, || inner.poll());
+        match result {
+            Ok(futures01::Async::NotReady) => (),
+            _ => {
+                self.span.take();
+            }
+        }
+
+        result
+    }
+}
+
+// Instrument an async block; async blocks are not Unpin so they are boxed here
+pub fn instrument<F: Future>(span: Span, fut: F) -> Instrumented<Pin<Box<F>>> {
+    Box::pin(fut).instrument(span)
+}
+
+// -------------------
+// Exporters
+// -------------------
+
+// Appends spans to a local file as newline-delimited JSON
+pub struct FileExporter(Mutex<std::fs::File>);
+
+impl FileExporter {
+    pub fn new(path: impl AsRef<std::path::Path>) -> std::io::Result<FileExporter> {
+        let path = path.as_ref();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| {
+                std::io::Error::new(
+                    err.kind(),
+                    format!("Failed to open the trace file {}: {}", path.display(), err),
+                )
+            })?;
+
+        Ok(FileExporter(Mutex::new(file)))
+    }
+}
+
+impl SpanExporter for FileExporter {
+    fn export(&self, span: SpanData) {
+        let mut file = self.0.lock().unwrap();
+        if let Err(err) = serde_json::to_writer(&mut *file, &span)
+            .and_then(|_| file.write_all(b"\n").map_err(serde_json::Error::io))
+        {
+            warn!("Failed to write span: {}", err);
+        }
+    }
+}
+
+// Sends spans to an OTLP/HTTP (JSON) collector in batches from a background thread
+pub struct OtlpExporter(Mutex<mpsc::Sender<SpanData>>);
+
+const OTLP_BATCH_SIZE: usize = 512;
+const OTLP_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+impl OtlpExporter {
+    pub fn new(endpoint: String) -> OtlpExporter {
+        let (sender, receiver) = mpsc::channel::<SpanData>();
+
+        std::thread::spawn(move || {
+            let client = reqwest::Client::new();
+            let mut batch = Vec::new();
+
+            loop {
+                let closed = match receiver.recv_timeout(OTLP_FLUSH_INTERVAL) {
+                    Ok(span) => {
+                        batch.push(span);
+                        if batch.len() < OTLP_BATCH_SIZE {
+                            continue;
+                        }
+                        false
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => false,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => true,
+                };
+
+                if !batch.is_empty() {
+                    let body = otlp_body(&batch);
+                    batch.clear();
+                    if let Err(err) = client.post(&endpoint).json(&body).send() {
+                        warn!("Failed to export spans: {}", err);
+                    }
+                }
+
+                if closed {
+                    break;
+                }
+            }
+        });
+
+        OtlpExporter(Mutex::new(sender))
+    }
+}
+
+impl SpanExporter for OtlpExporter {
+    fn export(&self, span: SpanData) {
+        self.0.lock().unwrap().send(span).ok();
+    }
+}
+
+fn otlp_body(spans: &[SpanData]) -> serde_json::Value {
+    let spans = spans
+        .iter()
+        .map(|span| {
+            serde_json::json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "parentSpanId": span.parent_span_id.clone().unwrap_or_default(),
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": span.start_time_unix_nano.to_string(),
+                "endTimeUnixNano": span.end_time_unix_nano.to_string(),
+                "attributes": span.attributes.iter().map(|(k, v)| serde_json::json!({
+                    "key": k,
+                    "value": { "stringValue": v },
+                })).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": "rustapp" },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "rustapp" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[test]
+fn child_span_should_share_trace_id() {
+    let parent = Span::new("parent");
+    let child = parent.in_scope(|| Span::new("child"));
+
+    assert_eq!(parent.context().trace_id, child.context().trace_id);
+    assert_ne!(parent.context().span_id, child.context().span_id);
+    assert_eq!(Some(parent.context()), child.parent);
+    assert_eq!(None, current_span());
+}
+
+#[test]
+fn instrumented_future_should_see_its_span() {
+    let span = Span::new("future");
+    let context = span.context();
+    let seen = futures::executor::block_on(instrument(span, async { current_span() }));
+
+    assert_eq!(Some(context), seen);
+    assert_eq!(None, current_span());
+}
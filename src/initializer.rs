@@ -1,23 +1,84 @@
+use crate::config;
 use crate::domain::interface;
 use crate::domain::service;
 use crate::infra;
 use crate::serviceclient;
+use futures::compat::*;
+use futures::prelude::*;
 use std::sync::Arc;
 
+// Created once in main and shared by the workers of both listeners
+#[derive(Clone)]
+pub struct Shared {
+    pub executor: infra::DBExecutor,
+    // only used with REPOSITORY=memory
+    pub memory: serviceclient::memory::MemoryStore,
+    pub user_cache: Option<serviceclient::cache::UserCache>,
+    pub kv_store: Arc<dyn interface::IKeyValueStore + Send + Sync>,
+}
+
+impl Shared {
+    // fails on a malformed KV_STORE_URL; nothing is connected yet
+    pub fn new(
+        config: &config::Config,
+        executor: infra::DBExecutor,
+    ) -> Result<Shared, failure::Error> {
+        Ok(Shared {
+            executor: executor,
+            memory: serviceclient::memory::MemoryStore::new(),
+            user_cache: config
+                .user_cache
+                .as_ref()
+                .map(serviceclient::cache::UserCache::new),
+            kv_store: match &config.kv_store_url {
+                Some(url) => Arc::new(infra::RedisKeyValueStore::new(url)?),
+                None => {
+                    warn!(
+                        "KV_STORE_URL is not set: sessions revoked on another instance are \
+                         accepted here for up to 60s"
+                    );
+                    Arc::new(infra::MemoryKeyValueStore::new())
+                }
+            },
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct Infras {
     pub db: infra::DBConnector,
     pub hash_manager: Arc<infra::HashManager>,
     pub jwt_handler: Arc<infra::JWTHandler>,
+    pub mailer: Arc<dyn interface::IMailer + Send + Sync>,
+    // only used with REPOSITORY=memory
+    pub memory: serviceclient::memory::MemoryStore,
+    pub user_cache: Option<serviceclient::cache::UserCache>,
+    pub kv_store: Arc<dyn interface::IKeyValueStore + Send + Sync>,
+    pub webhook_client: Arc<dyn interface::IWebhookClient + Send + Sync>,
 }
 
-pub fn infras(database_url: String, private_key: &str) -> Infras {
-    let db = actix::SyncArbiter::start(5, move || infra::DBExecutor::new(database_url.clone()));
+pub fn infras(config: &config::Config, shared: &Shared) -> Infras {
+    let mailer: Arc<dyn interface::IMailer + Send + Sync> = match &config.mail.transport {
+        config::MailTransport::File(dir) => {
+            Arc::new(infra::FileMailer::new(dir, config.mail.from.clone()))
+        }
+        config::MailTransport::Smtp(addr) => Arc::new(infra::SmtpMailer::new(
+            addr.clone(),
+            config.mail.from.clone(),
+        )),
+    };
 
     Infras {
-        db: infra::DBConnector::new(db),
+        db: infra::DBConnector::new(shared.executor.clone()),
         hash_manager: Arc::new(infra::HashManager::new()),
-        jwt_handler: Arc::new(infra::JWTHandler::new(private_key)),
+        jwt_handler: Arc::new(infra::JWTHandler::new(&config.private_key_file)),
+        mailer: mailer,
+        memory: shared.memory.clone(),
+        user_cache: shared.user_cache.clone(),
+        kv_store: shared.kv_store.clone(),
+        webhook_client: Arc::new(infra::WebhookClient::new(
+            config.events.webhook_allowed_hosts.clone(),
+        )),
     }
 }
 
@@ -25,9 +86,66 @@ pub fn infras(database_url: String, private_key: &str) -> Infras {
 pub struct ServiceClients {
     pub user_repository: Arc<dyn interface::IUserRepository + Send + Sync>,
     pub login_repository: Arc<dyn interface::IUserLoginRepository + Send + Sync>,
+    pub unit_of_work: Arc<dyn interface::IUnitOfWork + Send + Sync>,
+    pub audit_log: Arc<dyn interface::IAuditLog + Send + Sync>,
+    pub session_repository: Arc<dyn interface::ILoginSessionRepository + Send + Sync>,
+    pub outbox_repository: Arc<dyn interface::IOutboxRepository + Send + Sync>,
+    pub webhook_repository: Arc<dyn interface::IWebhookRepository + Send + Sync>,
 }
 
-pub fn serviceclients(infras: &Infras) -> ServiceClients {
+pub fn serviceclients(config: &config::Config, infras: &Infras) -> ServiceClients {
+    let sc = repositories(config, infras);
+
+    match &infras.user_cache {
+        Some(cache) => ServiceClients {
+            user_repository: Arc::new(serviceclient::cache::CachingUserRepository::new(
+                sc.user_repository,
+                cache.clone(),
+            )),
+            login_repository: Arc::new(serviceclient::cache::CachingUserLoginRepository::new(
+                sc.login_repository,
+                cache.clone(),
+            )),
+            unit_of_work: Arc::new(serviceclient::cache::CachingUnitOfWork::new(
+                sc.unit_of_work,
+                cache.clone(),
+            )),
+            audit_log: sc.audit_log,
+            session_repository: sc.session_repository,
+            outbox_repository: sc.outbox_repository,
+            webhook_repository: sc.webhook_repository,
+        },
+        None => sc,
+    }
+}
+
+fn repositories(config: &config::Config, infras: &Infras) -> ServiceClients {
+    if config.repository == config::RepositoryKind::Memory {
+        return ServiceClients {
+            user_repository: Arc::new(serviceclient::memory::MemoryUserRepository::new(
+                infras.memory.clone(),
+            )),
+            login_repository: Arc::new(serviceclient::memory::MemoryUserLoginRepository::new(
+                infras.memory.clone(),
+            )),
+            unit_of_work: Arc::new(serviceclient::memory::MemoryUnitOfWork::new(
+                infras.memory.clone(),
+            )),
+            audit_log: Arc::new(serviceclient::memory::MemoryAuditLog::new(
+                infras.memory.clone(),
+            )),
+            session_repository: Arc::new(serviceclient::memory::MemoryLoginSessionRepository::new(
+                infras.memory.clone(),
+            )),
+            outbox_repository: Arc::new(serviceclient::memory::MemoryOutboxRepository::new(
+                infras.memory.clone(),
+            )),
+            webhook_repository: Arc::new(serviceclient::memory::MemoryWebhookRepository::new(
+                infras.memory.clone(),
+            )),
+        };
+    }
+
     ServiceClients {
         user_repository: Arc::new(serviceclient::user_repo::UserRepository::new(
             infras.db.clone(),
@@ -35,6 +153,19 @@ pub fn serviceclients(infras: &Infras) -> ServiceClients {
         login_repository: Arc::new(serviceclient::user_login_repo::UserLoginRepository::new(
             infras.db.clone(),
         )),
+        unit_of_work: Arc::new(serviceclient::unit_of_work::UnitOfWork::new(
+            infras.db.clone(),
+        )),
+        audit_log: Arc::new(serviceclient::audit_log::AuditLog::new(infras.db.clone())),
+        session_repository: Arc::new(
+            serviceclient::login_session_repo::LoginSessionRepository::new(infras.db.clone()),
+        ),
+        outbox_repository: Arc::new(serviceclient::outbox_repo::OutboxRepository::new(
+            infras.db.clone(),
+        )),
+        webhook_repository: Arc::new(serviceclient::webhook_repo::WebhookRepository::new(
+            infras.db.clone(),
+        )),
     }
 }
 
@@ -42,15 +173,51 @@ pub fn serviceclients(infras: &Infras) -> ServiceClients {
 pub struct Services {
     pub user_service: service::UserService,
     pub login_service: service::LoginService,
+    pub signup_service: service::SignupService,
+    pub audit_service: service::AuditService,
+    pub session_service: service::SessionService,
+    pub webhook_service: service::WebhookService,
 }
 
-pub fn services(infras: &Infras, serviceclients: &ServiceClients) -> Services {
+pub fn services(
+    config: &config::Config,
+    infras: &Infras,
+    serviceclients: &ServiceClients,
+) -> Services {
     Services {
-        user_service: service::UserService::new(serviceclients.user_repository.clone()),
+        user_service: service::UserService::new(
+            serviceclients.user_repository.clone(),
+            serviceclients.unit_of_work.clone(),
+            infras.hash_manager.clone(),
+            serviceclients.audit_log.clone(),
+        ),
         login_service: service::LoginService::new(
             serviceclients.login_repository.clone(),
+            serviceclients.unit_of_work.clone(),
             infras.hash_manager.clone(),
             infras.jwt_handler.clone(),
+            serviceclients.session_repository.clone(),
+            serviceclients.audit_log.clone(),
+            infras.kv_store.clone(),
+        ),
+        signup_service: service::SignupService::new(
+            serviceclients.login_repository.clone(),
+            infras.hash_manager.clone(),
+            infras.mailer.clone(),
+            infras.kv_store.clone(),
+            config.signup.clone(),
+        ),
+        audit_service: service::AuditService::new(serviceclients.audit_log.clone()),
+        session_service: service::SessionService::new(
+            serviceclients.session_repository.clone(),
+            serviceclients.audit_log.clone(),
+            infras.kv_store.clone(),
+        ),
+        webhook_service: service::WebhookService::new(
+            serviceclients.webhook_repository.clone(),
+            infras.webhook_client.clone(),
+            config.events.max_attempts,
+            config.events.webhook_allowed_hosts.clone(),
         ),
     }
 }
@@ -62,10 +229,10 @@ pub struct AppContext {
     pub services: Services,
 }
 
-pub fn new(database_url: String, private_key: &str) -> AppContext {
-    let i = infras(database_url, private_key);
-    let sc = serviceclients(&i);
-    let s = services(&i, &sc);
+pub fn new(config: &config::Config, shared: &Shared) -> AppContext {
+    let i = infras(config, shared);
+    let sc = serviceclients(config, &i);
+    let s = services(config, &i, &sc);
 
     AppContext {
         infras: i,
@@ -73,3 +240,77 @@ pub fn new(database_url: String, private_key: &str) -> AppContext {
         services: s,
     }
 }
+
+// Polls the outbox and the webhook deliveries of the app on the current actix system
+// Fails when the event file cannot be opened
+pub fn start_event_dispatcher(config: &config::Config, app: &AppContext) -> std::io::Result<()> {
+    let webhooks = app.services.webhook_service.clone();
+    let sink: Option<Arc<dyn interface::IEventSink + Send + Sync>> = match &config.events.sink {
+        Some(config::EventSinkConfig::Stdout) => Some(Arc::new(infra::LineSink::stdout())),
+        Some(config::EventSinkConfig::File(path)) => Some(Arc::new(infra::LineSink::file(path)?)),
+        Some(config::EventSinkConfig::Webhook { url, secret }) => Some(Arc::new(
+            infra::WebhookSink::new(url.clone(), secret.clone()),
+        )),
+        Some(config::EventSinkConfig::Webhooks) => Some(Arc::new(webhooks.clone())),
+        None => None,
+    };
+    let dispatcher = sink.map(|sink| {
+        service::EventDispatcher::new(
+            app.serviceclients.outbox_repository.clone(),
+            sink,
+            config.events.max_attempts,
+        )
+    });
+    let interval = config.events.poll_interval;
+
+    // separate loops, so that slow webhook endpoints do not hold back the other sinks
+    if let Some(dispatcher) = dispatcher {
+        let dispatcher = Arc::new(dispatcher);
+        spawn_every(interval, move || {
+            let dispatcher = dispatcher.clone();
+            async move {
+                if let Err(err) = dispatcher
+                    .dispatch_due(chrono::Utc::now().naive_utc())
+                    .await
+                {
+                    warn!("Failed to dispatch the outbox: {}", err);
+                }
+            }
+        });
+    }
+    // deliveries retried by an admin are sent whatever the sink
+    spawn_every(interval, move || {
+        let webhooks = webhooks.clone();
+        async move {
+            if let Err(err) = webhooks.deliver_due(chrono::Utc::now().naive_utc()).await {
+                warn!("Failed to send the webhook deliveries: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// Runs the job on the current actix system, then again `interval` after it completes
+fn spawn_every<F, Fut>(interval: std::time::Duration, job: F)
+where
+    F: Fn() -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    actix::spawn(
+        Box::pin(
+            async move {
+                loop {
+                    job().await;
+
+                    tokio_timer::Delay::new(std::time::Instant::now() + interval)
+                        .compat()
+                        .await
+                        .ok();
+                }
+            }
+            .map(Ok::<(), ()>),
+        )
+        .compat(),
+    );
+}
@@ -5,8 +5,11 @@ extern crate diesel;
 #[macro_use]
 extern crate failure;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 
+mod config;
 mod domain;
 mod infra;
 mod initializer;
@@ -19,7 +22,6 @@ mod error;
 
 use actix::prelude::*;
 use dotenv::dotenv;
-use std::env;
 
 fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "warn,actix_web=info");
@@ -27,20 +29,66 @@ fn main() -> std::io::Result<()> {
     env_logger::init();
 
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL").unwrap();
-    let private_key_file = env::var("JWT_PRIVATE_KEY_FILE").unwrap();
+    infra::init_tracer_from_env()?;
+
+    let config = config::Config::from_env()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string()))?;
+    let pool = match config.repository {
+        config::RepositoryKind::Sql => {
+            infra::DBConnPool::connect(config.database_url.clone(), &config.pool)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?
+        }
+        // the repositories do not need the database
+        config::RepositoryKind::Memory => {
+            infra::DBConnPool::new_lazy(config.database_url.clone(), &config.pool)
+        }
+    };
+    // the replica connects in the background, reads stay on the primary until its first check
+    let replica = config.replica.as_ref().map(|replica| {
+        let replica_pool = infra::DBConnPool::new_lazy(replica.database_url.clone(), &config.pool);
+        let health = infra::start_replica_monitor(pool.clone(), replica_pool.clone(), replica);
+        (replica_pool, health)
+    });
+    let executor = infra::DBExecutor::start(pool, replica, &config.pool);
+
+    let shared = initializer::Shared::new(&config, executor)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string()))?;
 
-    System::new("rustapp");
+    let sys = System::new("rustapp");
+    initializer::start_event_dispatcher(&config, &initializer::new(&config, &shared))?;
 
+    let public_config = config.clone();
+    let public_shared = shared.clone();
     actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .wrap(actix_web::middleware::Logger::default())
+            .wrap(web::Tracing)
             .data(web::WebContext {
-                app: initializer::new(database_url.clone(), &private_key_file),
+                app: initializer::new(&public_config, &public_shared),
+                internal_secret: None,
             })
             .configure(web::handlers)
     })
-    .bind("127.0.0.1:8080")?
+    .bind(&config.bind)?
     .workers(1) // for local development
-    .run()
+    .start();
+
+    if let Some(private) = config.private.clone() {
+        let private_config = config.clone();
+        actix_web::HttpServer::new(move || {
+            actix_web::App::new()
+                .wrap(actix_web::middleware::Logger::default())
+                .wrap(web::Tracing)
+                .data(web::WebContext {
+                    app: initializer::new(&private_config, &shared),
+                    internal_secret: private.shared_secret.clone(),
+                })
+                .configure(web::private_handlers)
+        })
+        .bind(&private.bind)?
+        .workers(1)
+        .start();
+    }
+
+    sys.run()
 }
@@ -1,8 +1,63 @@
+table! {
+    audit_log_records (id) {
+        id -> Varchar,
+        occurred_at -> Timestamp,
+        actor_id -> Nullable<Varchar>,
+        target_id -> Nullable<Varchar>,
+        action -> Varchar,
+        outcome -> Varchar,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        detail -> Nullable<Text>,
+    }
+}
+
+table! {
+    login_session_records (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        created_at -> Timestamp,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        succeeded -> Bool,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    outbox_events (id) {
+        id -> Varchar,
+        occurred_at -> Timestamp,
+        event_type -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+    }
+}
+
+table! {
+    replica_heartbeats (id) {
+        id -> Integer,
+        beat_at -> Timestamp,
+    }
+}
+
+table! {
+    user_email_verifications (user_id) {
+        user_id -> Varchar,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
 table! {
     user_login_records (user_id) {
         user_id -> Varchar,
         password_hash -> Varchar,
         status -> Nullable<Varchar>,
+        version -> Integer,
     }
 }
 
@@ -12,12 +67,51 @@ table! {
         name -> Varchar,
         display_name -> Varchar,
         role -> Nullable<Varchar>,
+        deleted_at -> Nullable<Timestamp>,
+        email -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Varchar,
+        endpoint_id -> Varchar,
+        event_id -> Varchar,
+        event_type -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        last_status_code -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    webhook_endpoints (id) {
+        id -> Varchar,
+        url -> Text,
+        secret -> Varchar,
+        event_types -> Text,
+        created_at -> Timestamp,
     }
 }
 
+joinable!(login_session_records -> user_records (user_id));
+joinable!(user_email_verifications -> user_records (user_id));
 joinable!(user_login_records -> user_records (user_id));
+joinable!(webhook_deliveries -> webhook_endpoints (endpoint_id));
 
 allow_tables_to_appear_in_same_query!(
+    audit_log_records,
+    login_session_records,
+    outbox_events,
+    replica_heartbeats,
+    user_email_verifications,
     user_login_records,
     user_records,
+    webhook_deliveries,
+    webhook_endpoints,
 );
@@ -1,2 +1,153 @@
+pub mod audit_log;
+pub mod cache;
+#[cfg(test)]
+mod conformance;
+pub mod login_session_repo;
+pub mod memory;
+pub mod outbox_repo;
+pub mod unit_of_work;
 pub mod user_login_repo;
 pub mod user_repo;
+pub mod webhook_repo;
+
+// Runs the test against a real database with a fresh user, see "Repository tests" in README.md
+#[cfg(test)]
+fn with_test_user<F, Fut>(f: F)
+where
+    F: FnOnce(crate::infra::DBConnector, String) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    use crate::domain::model;
+    use crate::schema::*;
+    use diesel::prelude::*;
+    use futures::{FutureExt, TryFutureExt};
+
+    let mut sys = actix::System::new("test");
+    let pool = test_pool();
+    let db = crate::infra::DBConnector::new(crate::infra::DBExecutor::start(
+        pool.clone(),
+        None,
+        &test_pool_config(),
+    ));
+
+    let user_id = ulid::Ulid::new().to_string();
+    // dropped after the test, even a failed one
+    let _user = TestUser {
+        pool: pool,
+        id: user_id.clone(),
+    };
+    let record_id = user_id.clone();
+    let fut = async move {
+        db.run(move |conn| {
+            diesel::insert_into(user_records::table)
+                .values((
+                    user_records::id.eq(&record_id),
+                    user_records::name.eq(&record_id),
+                    user_records::display_name.eq("test"),
+                    user_records::role.eq(model::Role::User.as_string()),
+                ))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+
+        f(db, user_id).await;
+    };
+
+    sys.block_on(Box::pin(fut.map(Ok::<_, ()>)).compat())
+        .unwrap();
+}
+
+// Deletes the user of with_test_user, and what cascades from it
+#[cfg(test)]
+struct TestUser {
+    pool: crate::infra::DBConnPool,
+    id: String,
+}
+
+#[cfg(test)]
+impl Drop for TestUser {
+    fn drop(&mut self) {
+        use crate::schema::*;
+        use diesel::prelude::*;
+
+        let deleted = self
+            .pool
+            .get_connection()
+            .map_err(failure::Error::from)
+            .and_then(|conn| {
+                diesel::delete(user_records::table.find(&self.id))
+                    .execute(&**conn)
+                    .map_err(failure::Error::from)
+            });
+        if let Err(err) = deleted {
+            eprintln!("Failed to delete the test user {}: {}", self.id, err);
+        }
+    }
+}
+
+// A single connection, failing fast
+#[cfg(test)]
+pub fn test_pool_config() -> crate::config::PoolConfig {
+    crate::config::PoolConfig {
+        max_size: 1,
+        min_idle: None,
+        connection_timeout: std::time::Duration::from_secs(5),
+        idle_timeout: None,
+        max_lifetime: None,
+        init_sql: None,
+        startup_retries: 0,
+        executors: 1,
+        queue_size: 10,
+        query_timeout: std::time::Duration::from_secs(10),
+        slow_query_threshold: None,
+    }
+}
+
+// An executor on the test database, with its tables (see "Repository tests" in README.md)
+#[cfg(test)]
+pub fn test_executor() -> crate::infra::DBExecutor {
+    crate::infra::DBExecutor::start(test_pool(), None, &test_pool_config())
+}
+
+#[cfg(test)]
+fn test_pool() -> crate::infra::DBConnPool {
+    let pool = crate::infra::DBConnPool::new(test_database_url(), &test_pool_config()).unwrap();
+    #[cfg(feature = "sqlite")]
+    migrate(&pool);
+
+    pool
+}
+
+#[cfg(all(test, not(feature = "sqlite")))]
+fn test_database_url() -> String {
+    std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set")
+}
+
+// a new database file for every test, which needs no server
+#[cfg(all(test, feature = "sqlite"))]
+fn test_database_url() -> String {
+    std::env::temp_dir()
+        .join(format!("rustapp-test-{}.sqlite", ulid::Ulid::new()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Creates the tables of migrations/sqlite in the new database
+#[cfg(all(test, feature = "sqlite"))]
+fn migrate(pool: &crate::infra::DBConnPool) {
+    use diesel::connection::SimpleConnection;
+
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations/sqlite");
+    let mut migrations = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path().join("up.sql"))
+        .collect::<Vec<_>>();
+    migrations.sort();
+
+    let conn = pool.get_connection().unwrap();
+    for migration in migrations {
+        conn.batch_execute(&std::fs::read_to_string(migration).unwrap())
+            .unwrap();
+    }
+}
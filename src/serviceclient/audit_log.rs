@@ -0,0 +1,126 @@
+use crate::domain::interface::IAuditLog;
+use crate::domain::model;
+use crate::infra::{traced, DBBackend, DBConnector, DBConnectorError};
+use crate::schema::*;
+use async_trait::async_trait;
+use diesel::dsl::*;
+use diesel::prelude::*;
+
+#[derive(Queryable, Insertable, Clone)]
+pub struct AuditLogRecord {
+    pub id: String,
+    pub occurred_at: chrono::NaiveDateTime,
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub action: String,
+    pub outcome: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl AuditLogRecord {
+    pub fn to_model(self) -> model::AuditEntry {
+        model::AuditEntry {
+            id: self.id,
+            occurred_at: self.occurred_at,
+            actor_id: self.actor_id,
+            target_id: self.target_id,
+            action: model::AuditAction::new_from_str(&self.action),
+            // only success and failure are ever written
+            outcome: model::AuditOutcome::new_from_str(&self.outcome)
+                .unwrap_or(model::AuditOutcome::Failure),
+            ip: self.ip,
+            user_agent: self.user_agent,
+            detail: self.detail,
+        }
+    }
+
+    pub fn from_model(entry: model::AuditEntry) -> Self {
+        AuditLogRecord {
+            id: entry.id,
+            occurred_at: entry.occurred_at,
+            actor_id: entry.actor_id,
+            target_id: entry.target_id,
+            action: entry.action.as_string(),
+            outcome: entry.outcome.as_string(),
+            ip: entry.ip,
+            user_agent: entry.user_agent,
+            detail: entry.detail,
+        }
+    }
+}
+
+fn paginated<'a>(query: model::AuditQuery) -> audit_log_records::BoxedQuery<'a, DBBackend> {
+    let filter = query.filter;
+    let mut q = audit_log_records::table.into_boxed();
+
+    if let Some(actor_id) = filter.actor_id {
+        q = q.filter(audit_log_records::actor_id.eq(actor_id));
+    }
+    if let Some(target_id) = filter.target_id {
+        q = q.filter(audit_log_records::target_id.eq(target_id));
+    }
+    if let Some(action) = filter.action {
+        q = q.filter(audit_log_records::action.eq(action.as_string()));
+    }
+    if let Some(outcome) = filter.outcome {
+        q = q.filter(audit_log_records::outcome.eq(outcome.as_string()));
+    }
+    if let Some(since) = filter.since {
+        q = q.filter(audit_log_records::occurred_at.ge(since));
+    }
+    if let Some(until) = filter.until {
+        q = q.filter(audit_log_records::occurred_at.lt(until));
+    }
+    if let Some(before_id) = query.before_id {
+        q = q.filter(audit_log_records::id.lt(before_id));
+    }
+
+    q.order(audit_log_records::id.desc()).limit(query.limit)
+}
+
+pub struct AuditLog {
+    db: DBConnector,
+}
+
+impl AuditLog {
+    pub fn new(db: DBConnector) -> AuditLog {
+        AuditLog { db: db }
+    }
+}
+
+#[async_trait]
+impl IAuditLog for AuditLog {
+    async fn append(&self, entry: model::AuditEntry) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("AuditLog::append")
+            .execute(
+                insert_into(audit_log_records::table)
+                    .values::<AuditLogRecord>(AuditLogRecord::from_model(entry)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        query: model::AuditQuery,
+    ) -> Result<Vec<model::AuditEntry>, DBConnectorError> {
+        let records = self
+            .db
+            .caller("AuditLog::list")
+            .run_read_only(move |conn| {
+                traced(
+                    "AuditLog::list",
+                    paginated(query),
+                    |v| Some(v.len()),
+                    |query| query.load::<AuditLogRecord>(conn),
+                )
+            })
+            .await?;
+
+        Ok(records.into_iter().map(|r| r.to_model()).collect())
+    }
+}
@@ -0,0 +1,436 @@
+// Decorators caching the user lookups of the repositories they wrap (USER_CACHE_TTL_SECS)
+// Every write through them invalidates the entries it may have changed
+use crate::config::CacheConfig;
+use crate::domain::interface::{
+    IOutboxRepositoryTx, ITransaction, IUnitOfWork, IUserLoginRepository, IUserLoginRepositoryTx,
+    IUserRepository, IUserRepositoryTx, Work,
+};
+use crate::domain::model;
+use crate::infra::{CacheStats, DBConnectorError, LruCache};
+use async_trait::async_trait;
+use serde::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct UserCacheStats {
+    pub users: CacheStats,
+    pub logins: CacheStats,
+}
+
+// Shared by the repositories of every worker, so that a write through one of them
+// invalidates what the others read
+#[derive(Clone)]
+pub struct UserCache {
+    // None is cached too, e.g. for a token of a deleted user
+    users: Arc<LruCache<String, Option<model::User>>>,
+    // by user name, the lookup of every login attempt
+    logins: Arc<LruCache<String, Option<(model::Login, model::User)>>>,
+}
+
+impl UserCache {
+    pub fn new(config: &CacheConfig) -> UserCache {
+        UserCache {
+            users: Arc::new(LruCache::new(config.max_entries, config.ttl)),
+            logins: Arc::new(LruCache::new(config.max_entries, config.ttl)),
+        }
+    }
+
+    // The logins are keyed by name, which a write may not know (e.g. a new login only has the
+    // user id), so this also drops the cached misses: one of them may be for this user's name
+    fn forget(&self, user_id: &str) {
+        self.users.remove(&user_id.to_owned());
+        self.logins.remove_where(|_, login| match login {
+            Some((login, _)) => login.user_id == user_id,
+            None => true,
+        });
+    }
+
+    pub fn stats(&self) -> UserCacheStats {
+        UserCacheStats {
+            users: self.users.stats(),
+            logins: self.logins.stats(),
+        }
+    }
+}
+
+pub struct CachingUserRepository {
+    inner: Arc<dyn IUserRepository + Send + Sync>,
+    cache: UserCache,
+}
+
+impl CachingUserRepository {
+    pub fn new(
+        inner: Arc<dyn IUserRepository + Send + Sync>,
+        cache: UserCache,
+    ) -> CachingUserRepository {
+        CachingUserRepository {
+            inner: inner,
+            cache: cache,
+        }
+    }
+}
+
+#[async_trait]
+impl IUserRepository for CachingUserRepository {
+    async fn get_by_id(&self, user_id: String) -> Result<Option<model::User>, DBConnectorError> {
+        if let Some(user) = self.cache.users.get(&user_id) {
+            return Ok(user);
+        }
+
+        let generation = self.cache.users.generation();
+        let user = self.inner.get_by_id(user_id.clone()).await?;
+        self.cache.users.insert(user_id, user.clone(), generation);
+
+        Ok(user)
+    }
+
+    // for read-your-writes, so never from the cache either
+    async fn get_by_id_from_primary(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::User>, DBConnectorError> {
+        self.inner.get_by_id_from_primary(user_id).await
+    }
+
+    async fn list(
+        &self,
+        query: model::UserListQuery,
+    ) -> Result<Vec<model::User>, DBConnectorError> {
+        self.inner.list(query).await
+    }
+
+    async fn count(&self, filter: model::UserFilter) -> Result<i64, DBConnectorError> {
+        self.inner.count(filter).await
+    }
+
+    async fn save(&self, user: model::User) -> Result<(), DBConnectorError> {
+        let user_id = user.id.clone();
+        let result = self.inner.save(user).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn update(&self, user: model::User) -> Result<(), DBConnectorError> {
+        let user_id = user.id.clone();
+        let result = self.inner.update(user).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn delete(&self, user_id: String) -> Result<bool, DBConnectorError> {
+        let result = self.inner.delete(user_id.clone()).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn purge(&self, user_id: String) -> Result<bool, DBConnectorError> {
+        let result = self.inner.purge(user_id.clone()).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+}
+
+pub struct CachingUserLoginRepository {
+    inner: Arc<dyn IUserLoginRepository + Send + Sync>,
+    cache: UserCache,
+}
+
+impl CachingUserLoginRepository {
+    pub fn new(
+        inner: Arc<dyn IUserLoginRepository + Send + Sync>,
+        cache: UserCache,
+    ) -> CachingUserLoginRepository {
+        CachingUserLoginRepository {
+            inner: inner,
+            cache: cache,
+        }
+    }
+}
+
+// Only get_by_user_name is cached: the other lookups come before a write, which needs the
+// current version of the login
+#[async_trait]
+impl IUserLoginRepository for CachingUserLoginRepository {
+    async fn get_by_user_name(
+        &self,
+        user_name: String,
+    ) -> Result<Option<(model::Login, model::User)>, DBConnectorError> {
+        if let Some(login) = self.cache.logins.get(&user_name) {
+            return Ok(login);
+        }
+
+        let generation = self.cache.logins.generation();
+        let login = self.inner.get_by_user_name(user_name.clone()).await?;
+        self.cache
+            .logins
+            .insert(user_name, login.clone(), generation);
+
+        Ok(login)
+    }
+
+    async fn get_by_user_id(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::Login>, DBConnectorError> {
+        self.inner.get_by_user_id(user_id).await
+    }
+
+    async fn save(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        let user_id = login.user_id.clone();
+        let result = self.inner.save(login).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn create(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        let user_id = login.user_id.clone();
+        let result = self.inner.create(login).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn update(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        let user_id = login.user_id.clone();
+        let result = self.inner.update(login).await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn create_pending(
+        &self,
+        user: model::User,
+        login: model::Login,
+        verification: model::EmailVerification,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError> {
+        let user_id = user.id.clone();
+        let result = self
+            .inner
+            .create_pending(user, login, verification, events)
+            .await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    async fn get_verification(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<model::EmailVerification>, DBConnectorError> {
+        self.inner.get_verification(token_hash).await
+    }
+
+    async fn complete_verification(
+        &self,
+        user_id: String,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError> {
+        let result = self
+            .inner
+            .complete_verification(user_id.clone(), events)
+            .await;
+        self.cache.forget(&user_id);
+
+        result
+    }
+
+    // only the verification changes, which is never cached
+    async fn renew_verification(
+        &self,
+        email: String,
+        token_hash: String,
+        expires_at: chrono::NaiveDateTime,
+    ) -> Result<bool, DBConnectorError> {
+        self.inner
+            .renew_verification(email, token_hash, expires_at)
+            .await
+    }
+}
+
+pub struct CachingUnitOfWork {
+    inner: Arc<dyn IUnitOfWork + Send + Sync>,
+    cache: UserCache,
+}
+
+impl CachingUnitOfWork {
+    pub fn new(inner: Arc<dyn IUnitOfWork + Send + Sync>, cache: UserCache) -> CachingUnitOfWork {
+        CachingUnitOfWork {
+            inner: inner,
+            cache: cache,
+        }
+    }
+}
+
+// Records the users the work writes to, so that only they are invalidated
+struct RecordingTransaction<'a> {
+    inner: &'a dyn ITransaction,
+    written: Arc<Mutex<Vec<String>>>,
+}
+
+impl<'a> RecordingTransaction<'a> {
+    fn record(&self, user_id: &str) {
+        self.written.lock().unwrap().push(user_id.to_owned());
+    }
+}
+
+impl<'a> ITransaction for RecordingTransaction<'a> {
+    fn users(&self) -> &dyn IUserRepositoryTx {
+        self
+    }
+
+    fn logins(&self) -> &dyn IUserLoginRepositoryTx {
+        self
+    }
+
+    fn outbox(&self) -> &dyn IOutboxRepositoryTx {
+        self.inner.outbox()
+    }
+}
+
+impl<'a> IUserRepositoryTx for RecordingTransaction<'a> {
+    fn get_by_id(&self, user_id: &str) -> Result<Option<model::User>, DBConnectorError> {
+        self.inner.users().get_by_id(user_id)
+    }
+
+    fn save(&self, user: model::User) -> Result<(), DBConnectorError> {
+        self.record(&user.id);
+        self.inner.users().save(user)
+    }
+
+    fn update(&self, user: model::User) -> Result<(), DBConnectorError> {
+        self.record(&user.id);
+        self.inner.users().update(user)
+    }
+}
+
+impl<'a> IUserLoginRepositoryTx for RecordingTransaction<'a> {
+    fn get_by_user_id(&self, user_id: &str) -> Result<Option<model::Login>, DBConnectorError> {
+        self.inner.logins().get_by_user_id(user_id)
+    }
+
+    fn save(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.record(&login.user_id);
+        self.inner.logins().save(login)
+    }
+
+    fn create(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.record(&login.user_id);
+        self.inner.logins().create(login)
+    }
+
+    fn update(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.record(&login.user_id);
+        self.inner.logins().update(login)
+    }
+}
+
+#[async_trait]
+impl IUnitOfWork for CachingUnitOfWork {
+    async fn run(&self, work: Work) -> Result<(), DBConnectorError> {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let recorded = written.clone();
+        let result = self
+            .inner
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                work(&RecordingTransaction {
+                    inner: tx,
+                    written: recorded,
+                })
+            }))
+            .await;
+        // after a rollback too, which only costs the next lookups
+        for user_id in written.lock().unwrap().iter() {
+            self.cache.forget(user_id);
+        }
+
+        result
+    }
+}
+
+#[test]
+fn cache_should_serve_lookups_until_a_write() {
+    use crate::serviceclient::memory::*;
+
+    let store = MemoryStore::new();
+    let cache = UserCache::new(&CacheConfig {
+        ttl: std::time::Duration::from_secs(60),
+        max_entries: 10,
+    });
+    let users = CachingUserRepository::new(
+        Arc::new(MemoryUserRepository::new(store.clone())),
+        cache.clone(),
+    );
+    // writes which bypass the cache
+    let uncached = MemoryUserRepository::new(store);
+
+    let mut user = test_user("cached");
+    user.display_name = "before".to_owned();
+    futures::executor::block_on(async {
+        users.save(user.clone()).await.unwrap();
+        assert!(users.get_by_id(user.id.clone()).await.unwrap().is_some());
+
+        user.display_name = "after".to_owned();
+        uncached.update(user.clone()).await.unwrap();
+        let cached = users.get_by_id(user.id.clone()).await.unwrap().unwrap();
+        assert_eq!("before", cached.display_name);
+
+        users.update(user.clone()).await.unwrap();
+        let updated = users.get_by_id(user.id.clone()).await.unwrap().unwrap();
+        assert_eq!("after", updated.display_name);
+    });
+
+    let stats = cache.stats().users;
+    assert_eq!(1, stats.hits);
+    assert_eq!(2, stats.misses);
+}
+
+#[test]
+fn unit_of_work_should_only_invalidate_the_users_it_writes() {
+    use crate::serviceclient::memory::*;
+
+    let store = MemoryStore::new();
+    let cache = UserCache::new(&CacheConfig {
+        ttl: std::time::Duration::from_secs(60),
+        max_entries: 10,
+    });
+    let users = CachingUserRepository::new(
+        Arc::new(MemoryUserRepository::new(store.clone())),
+        cache.clone(),
+    );
+    let unit_of_work = CachingUnitOfWork::new(
+        Arc::new(MemoryUnitOfWork::new(store.clone())),
+        cache.clone(),
+    );
+
+    let (mut written, untouched) = (test_user("written"), test_user("untouched"));
+    futures::executor::block_on(async {
+        users.save(written.clone()).await.unwrap();
+        users.save(untouched.clone()).await.unwrap();
+        users.get_by_id(written.id.clone()).await.unwrap();
+        users.get_by_id(untouched.id.clone()).await.unwrap();
+
+        written.display_name = "renamed".to_owned();
+        let updated = written.clone();
+        unit_of_work
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                tx.users().update(updated)
+            }))
+            .await
+            .unwrap();
+
+        let found = users.get_by_id(written.id.clone()).await.unwrap().unwrap();
+        assert_eq!("renamed", found.display_name);
+        users.get_by_id(untouched.id.clone()).await.unwrap();
+    });
+
+    let stats = cache.stats().users;
+    assert_eq!(1, stats.hits);
+    assert_eq!(3, stats.misses);
+}
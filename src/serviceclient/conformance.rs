@@ -0,0 +1,922 @@
+// The behaviour every implementation of the repositories must share, run against both the
+// in-memory and the SQL ones (see the tests at the bottom)
+// Names are made unique per run, so that the cases can share a database
+use super::memory::test_user;
+use crate::domain::interface::{
+    IAuditLog, ILoginSessionRepository, IOutboxRepository, ITransaction, IUnitOfWork,
+    IUserLoginRepository, IUserRepository, IWebhookRepository,
+};
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use std::sync::Arc;
+
+pub struct Repositories {
+    pub users: Arc<dyn IUserRepository + Send + Sync>,
+    pub logins: Arc<dyn IUserLoginRepository + Send + Sync>,
+    pub unit_of_work: Arc<dyn IUnitOfWork + Send + Sync>,
+    pub audit_log: Arc<dyn IAuditLog + Send + Sync>,
+    pub sessions: Arc<dyn ILoginSessionRepository + Send + Sync>,
+    pub outbox: Arc<dyn IOutboxRepository + Send + Sync>,
+    pub webhooks: Arc<dyn IWebhookRepository + Send + Sync>,
+}
+
+fn login(user_id: &str, version: i32) -> model::Login {
+    model::Login {
+        user_id: user_id.to_owned(),
+        password_hash: "hash".to_owned(),
+        status: model::LoginUserStatus::Enabled,
+        version: version,
+    }
+}
+
+fn unique_name(name: &str) -> String {
+    format!("{}-{}", name, ulid::Ulid::new())
+}
+
+fn assert_not_found<T>(result: Result<T, DBConnectorError>) {
+    match result {
+        Err(DBConnectorError::NotFound) => (),
+        _ => panic!("expected not found"),
+    }
+}
+
+fn assert_unique_violation<T>(result: Result<T, DBConnectorError>) {
+    match result {
+        Err(DBConnectorError::UniqueViolation(_)) => (),
+        _ => panic!("expected a unique violation"),
+    }
+}
+
+pub async fn run(r: Repositories) {
+    users_should_be_found_by_id(&r).await;
+    user_names_should_be_unique(&r).await;
+    deleted_users_should_be_hidden(&r).await;
+    users_should_be_listed_page_by_page(&r).await;
+    logins_should_be_joined_with_their_user(&r).await;
+    logins_should_check_their_version(&r).await;
+    verifications_should_enable_the_login(&r).await;
+    pending_verifications_should_be_renewed(&r).await;
+    unit_of_work_should_be_rolled_back_on_error(&r).await;
+    audit_log_should_be_listed_newest_first(&r).await;
+    sessions_should_be_revoked_once(&r).await;
+    outbox_events_should_commit_with_the_work(&r).await;
+    outbox_events_should_be_claimed_once(&r).await;
+    webhook_deliveries_should_be_queued_once(&r).await;
+    webhook_deliveries_should_be_claimed_once(&r).await;
+}
+
+async fn users_should_be_found_by_id(r: &Repositories) {
+    let saved = test_user(&unique_name("found"));
+    r.users.save(saved.clone()).await.unwrap();
+
+    let found = r.users.get_by_id(saved.id.clone()).await.unwrap().unwrap();
+    assert_eq!(saved.name, found.name);
+    assert_eq!(model::Role::User, found.role);
+    let found = r.users.get_by_id_from_primary(saved.id).await.unwrap();
+    assert!(found.is_some());
+
+    let missing = ulid::Ulid::new().to_string();
+    assert!(r.users.get_by_id(missing).await.unwrap().is_none());
+}
+
+async fn user_names_should_be_unique(r: &Repositories) {
+    let taken = test_user(&unique_name("taken"));
+    r.users.save(taken.clone()).await.unwrap();
+    assert_unique_violation(r.users.save(test_user(&taken.name)).await);
+
+    let mut other = test_user(&unique_name("other"));
+    r.users.save(other.clone()).await.unwrap();
+    other.name = taken.name.clone();
+    assert_unique_violation(r.users.update(other).await);
+
+    // soft deleted users keep their name
+    assert!(r.users.delete(taken.id).await.unwrap());
+    assert_unique_violation(r.users.save(test_user(&taken.name)).await);
+}
+
+async fn deleted_users_should_be_hidden(r: &Repositories) {
+    let deleted = test_user(&unique_name("deleted"));
+    r.users.save(deleted.clone()).await.unwrap();
+    r.logins.save(login(&deleted.id, 0)).await.unwrap();
+
+    assert!(r.users.delete(deleted.id.clone()).await.unwrap());
+    assert!(!r.users.delete(deleted.id.clone()).await.unwrap());
+    assert!(r
+        .users
+        .get_by_id(deleted.id.clone())
+        .await
+        .unwrap()
+        .is_none());
+    assert!(r
+        .logins
+        .get_by_user_name(deleted.name.clone())
+        .await
+        .unwrap()
+        .is_none());
+
+    // purge also removes the login
+    assert!(r.users.purge(deleted.id.clone()).await.unwrap());
+    assert!(!r.users.purge(deleted.id.clone()).await.unwrap());
+    assert!(r.logins.get_by_user_id(deleted.id).await.unwrap().is_none());
+}
+
+async fn users_should_be_listed_page_by_page(r: &Repositories) {
+    let prefix = unique_name("list");
+    for suffix in &["c", "a", "b"] {
+        r.users
+            .save(test_user(&format!("{}-{}", prefix, suffix)))
+            .await
+            .unwrap();
+    }
+    let filter = model::UserFilter {
+        role: Some(model::Role::User),
+        name_prefix: Some(prefix.clone()),
+    };
+
+    let first = r
+        .users
+        .list(model::UserListQuery {
+            filter: filter.clone(),
+            sort: model::UserSort::NameAsc,
+            cursor: None,
+            limit: 2,
+        })
+        .await
+        .unwrap();
+    let second = r
+        .users
+        .list(model::UserListQuery {
+            filter: filter.clone(),
+            sort: model::UserSort::NameAsc,
+            cursor: Some(model::UserCursor::from_user(&first[1])),
+            limit: 2,
+        })
+        .await
+        .unwrap();
+
+    let names = first
+        .iter()
+        .chain(second.iter())
+        .map(|u| u.name.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            format!("{}-a", prefix),
+            format!("{}-b", prefix),
+            format!("{}-c", prefix),
+        ],
+        names
+    );
+    assert_eq!(3, r.users.count(filter).await.unwrap());
+
+    let admins = model::UserFilter {
+        role: Some(model::Role::Admin),
+        name_prefix: Some(prefix),
+    };
+    assert_eq!(0, r.users.count(admins).await.unwrap());
+}
+
+async fn logins_should_be_joined_with_their_user(r: &Repositories) {
+    let saved = test_user(&unique_name("joined"));
+    r.users.save(saved.clone()).await.unwrap();
+    assert!(r
+        .logins
+        .get_by_user_name(saved.name.clone())
+        .await
+        .unwrap()
+        .is_none());
+
+    r.logins.save(login(&saved.id, 0)).await.unwrap();
+    let (found_login, found_user) = r
+        .logins
+        .get_by_user_name(saved.name)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(saved.id, found_login.user_id);
+    assert_eq!(saved.id, found_user.id);
+
+    // a login needs its user
+    match r
+        .logins
+        .create(login(&ulid::Ulid::new().to_string(), 0))
+        .await
+    {
+        Err(DBConnectorError::DBError(_)) => (),
+        _ => panic!("expected a foreign key violation"),
+    }
+}
+
+async fn logins_should_check_their_version(r: &Repositories) {
+    let saved = test_user(&unique_name("versioned"));
+    r.users.save(saved.clone()).await.unwrap();
+
+    match r.logins.update(login(&saved.id, 0)).await {
+        Err(DBConnectorError::NotFound) => (),
+        _ => panic!("expected not found"),
+    }
+    r.logins.create(login(&saved.id, 0)).await.unwrap();
+    assert_unique_violation(r.logins.create(login(&saved.id, 0)).await);
+
+    r.logins.update(login(&saved.id, 0)).await.unwrap();
+    match r.logins.update(login(&saved.id, 0)).await {
+        Err(DBConnectorError::VersionConflict) => (),
+        _ => panic!("expected a version conflict"),
+    }
+
+    // save overwrites whatever the version
+    let mut overwritten = login(&saved.id, 0);
+    overwritten.password_hash = "overwritten".to_owned();
+    r.logins.save(overwritten).await.unwrap();
+    let stored = r.logins.get_by_user_id(saved.id).await.unwrap().unwrap();
+    assert_eq!("overwritten", stored.password_hash);
+    assert_eq!(2, stored.version);
+
+    // and inserts a missing login
+    let inserted = test_user(&unique_name("inserted"));
+    r.users.save(inserted.clone()).await.unwrap();
+    r.logins.save(login(&inserted.id, 0)).await.unwrap();
+    let stored = r.logins.get_by_user_id(inserted.id).await.unwrap().unwrap();
+    assert_eq!(0, stored.version);
+}
+
+async fn verifications_should_enable_the_login(r: &Repositories) {
+    let pending = test_user(&unique_name("pending"));
+    let token_hash = ulid::Ulid::new().to_string();
+    let created = model::OutboxEvent::new(model::DomainEvent::UserCreated {
+        user_id: pending.id.clone(),
+        name: pending.name.clone(),
+        role: pending.role.clone(),
+    });
+    let enabled = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: pending.id.clone(),
+    });
+    let ids = vec![created.id.clone(), enabled.id.clone()];
+    let published = || {
+        let ids = ids.clone();
+        async move {
+            r.outbox
+                .list_due(chrono::Utc::now().naive_utc(), 1000)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|e| ids.contains(&e.id))
+                .map(|e| e.event.event_type())
+                .collect::<Vec<_>>()
+        }
+    };
+    r.logins
+        .create_pending(
+            pending.clone(),
+            model::Login {
+                status: model::LoginUserStatus::PendingVerification,
+                ..login(&pending.id, 0)
+            },
+            model::EmailVerification {
+                user_id: pending.id.clone(),
+                token_hash: token_hash.clone(),
+                expires_at: chrono::NaiveDate::from_ymd(2100, 1, 1).and_hms(0, 0, 0),
+            },
+            vec![created],
+        )
+        .await
+        .unwrap();
+    assert_eq!(vec!["user_created"], published().await);
+
+    let verification = r
+        .logins
+        .get_verification(token_hash.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pending.id, verification.user_id);
+
+    r.logins
+        .complete_verification(pending.id.clone(), vec![enabled])
+        .await
+        .unwrap();
+    let login_enabled = r
+        .logins
+        .get_by_user_id(pending.id.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(model::LoginUserStatus::Enabled, login_enabled.status);
+    assert_eq!(1, login_enabled.version);
+    assert_eq!(2, published().await.len());
+
+    // the token is consumed once, without another event
+    let again = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: pending.id.clone(),
+    });
+    let result = r
+        .logins
+        .complete_verification(pending.id.clone(), vec![again])
+        .await;
+    assert_not_found(result);
+    assert_eq!(2, published().await.len());
+    assert!(r
+        .logins
+        .get_verification(token_hash)
+        .await
+        .unwrap()
+        .is_none());
+
+    // nothing is created when a part of the signup fails
+    let taken = test_user(&pending.name);
+    let result = r
+        .logins
+        .create_pending(
+            taken.clone(),
+            login(&taken.id, 0),
+            model::EmailVerification {
+                user_id: taken.id.clone(),
+                token_hash: ulid::Ulid::new().to_string(),
+                expires_at: chrono::NaiveDate::from_ymd(2100, 1, 1).and_hms(0, 0, 0),
+            },
+            vec![],
+        )
+        .await;
+    assert_unique_violation(result);
+    assert!(r.logins.get_by_user_id(taken.id).await.unwrap().is_none());
+
+    // the token of a deleted user does not enable its login
+    let deleted = test_user(&unique_name("deleted"));
+    r.logins
+        .create_pending(
+            deleted.clone(),
+            model::Login {
+                status: model::LoginUserStatus::PendingVerification,
+                ..login(&deleted.id, 0)
+            },
+            model::EmailVerification {
+                user_id: deleted.id.clone(),
+                token_hash: ulid::Ulid::new().to_string(),
+                expires_at: chrono::NaiveDate::from_ymd(2100, 1, 1).and_hms(0, 0, 0),
+            },
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert!(r.users.delete(deleted.id.clone()).await.unwrap());
+    let result = r
+        .logins
+        .complete_verification(deleted.id.clone(), vec![])
+        .await;
+    assert_not_found(result);
+    let login_pending = r.logins.get_by_user_id(deleted.id).await.unwrap().unwrap();
+    assert_eq!(
+        model::LoginUserStatus::PendingVerification,
+        login_pending.status
+    );
+}
+
+async fn pending_verifications_should_be_renewed(r: &Repositories) {
+    let name = unique_name("renewed");
+    let email = format!("{}@example.com", name);
+    let pending = model::User {
+        email: Some(email.clone()),
+        ..test_user(&name)
+    };
+    let expires_at = chrono::NaiveDate::from_ymd(2100, 1, 1).and_hms(0, 0, 0);
+    let (first, second) = (ulid::Ulid::new().to_string(), ulid::Ulid::new().to_string());
+    r.logins
+        .create_pending(
+            pending.clone(),
+            model::Login {
+                status: model::LoginUserStatus::PendingVerification,
+                ..login(&pending.id, 0)
+            },
+            model::EmailVerification {
+                user_id: pending.id.clone(),
+                token_hash: first.clone(),
+                expires_at: expires_at,
+            },
+            vec![],
+        )
+        .await
+        .unwrap();
+
+    let renewed = r
+        .logins
+        .renew_verification(email.clone(), second.clone(), expires_at)
+        .await
+        .unwrap();
+    assert!(renewed);
+    assert!(r.logins.get_verification(first).await.unwrap().is_none());
+    let verification = r.logins.get_verification(second).await.unwrap().unwrap();
+    assert_eq!(pending.id, verification.user_id);
+
+    // nothing to renew once verified, or for an unknown email
+    r.logins
+        .complete_verification(pending.id, vec![])
+        .await
+        .unwrap();
+    let renewed = r
+        .logins
+        .renew_verification(email, ulid::Ulid::new().to_string(), expires_at)
+        .await
+        .unwrap();
+    assert!(!renewed);
+    let renewed = r
+        .logins
+        .renew_verification(
+            format!("unknown-{}", name),
+            ulid::Ulid::new().to_string(),
+            expires_at,
+        )
+        .await
+        .unwrap();
+    assert!(!renewed);
+}
+
+async fn unit_of_work_should_be_rolled_back_on_error(r: &Repositories) {
+    let saved = test_user(&unique_name("work"));
+    r.users.save(saved.clone()).await.unwrap();
+
+    let id = saved.id.clone();
+    let result = r
+        .unit_of_work
+        .run(Box::new(move |tx: &dyn ITransaction| {
+            let mut user = tx.users().get_by_id(&id)?.unwrap();
+            user.display_name = "renamed".to_owned();
+            tx.users().update(user)?;
+            tx.logins().create(login(&id, 0))?;
+            tx.logins().create(login(&id, 0))
+        }))
+        .await;
+    assert_unique_violation(result);
+
+    let stored = r.users.get_by_id(saved.id.clone()).await.unwrap().unwrap();
+    assert_eq!("test", stored.display_name);
+    assert!(r.logins.get_by_user_id(saved.id).await.unwrap().is_none());
+}
+
+async fn audit_log_should_be_listed_newest_first(r: &Repositories) {
+    let actor = model::Actor {
+        user_id: Some(unique_name("actor")),
+        ..Default::default()
+    };
+    let mut appended = Vec::new();
+    for action in &[
+        model::AuditAction::UserCreated,
+        model::AuditAction::UserUpdated,
+        model::AuditAction::UserDeleted,
+    ] {
+        let entry = model::AuditEntry::new(&actor, *action, Some("target".to_owned()));
+        appended.push(entry.id.clone());
+        r.audit_log.append(entry).await.unwrap();
+        // ids are only ordered to the millisecond
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+    let failed = model::AuditEntry::new(&actor, model::AuditAction::LoginFailed, None)
+        .failed(&"bad password");
+    appended.push(failed.id.clone());
+    r.audit_log.append(failed).await.unwrap();
+    appended.reverse();
+
+    let filter = model::AuditFilter {
+        actor_id: actor.user_id.clone(),
+        ..Default::default()
+    };
+    let ids =
+        |entries: Vec<model::AuditEntry>| entries.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+    let first = r
+        .audit_log
+        .list(model::AuditQuery {
+            filter: filter.clone(),
+            before_id: None,
+            limit: 3,
+        })
+        .await
+        .unwrap();
+    assert_eq!(appended[..3].to_vec(), ids(first));
+    let second = r
+        .audit_log
+        .list(model::AuditQuery {
+            filter: filter.clone(),
+            before_id: Some(appended[2].clone()),
+            limit: 3,
+        })
+        .await
+        .unwrap();
+    assert_eq!(appended[3..].to_vec(), ids(second));
+
+    let failures = r
+        .audit_log
+        .list(model::AuditQuery {
+            filter: model::AuditFilter {
+                outcome: Some(model::AuditOutcome::Failure),
+                ..filter.clone()
+            },
+            before_id: None,
+            limit: 10,
+        })
+        .await
+        .unwrap();
+    assert_eq!(1, failures.len());
+    assert_eq!(model::AuditAction::LoginFailed, failures[0].action);
+    assert_eq!(Some("bad password".to_owned()), failures[0].detail);
+
+    let updates = r
+        .audit_log
+        .list(model::AuditQuery {
+            filter: model::AuditFilter {
+                action: Some(model::AuditAction::UserUpdated),
+                ..filter
+            },
+            before_id: None,
+            limit: 10,
+        })
+        .await
+        .unwrap();
+    assert_eq!(vec![appended[2].clone()], ids(updates));
+}
+
+async fn sessions_should_be_revoked_once(r: &Repositories) {
+    let saved = test_user(&unique_name("sessions"));
+    r.users.save(saved.clone()).await.unwrap();
+
+    let actor = model::Actor::default();
+    let mut created = Vec::new();
+    for succeeded in &[true, false, true] {
+        let session = model::LoginSession::new(saved.id.clone(), &actor, *succeeded);
+        created.push(session.id.clone());
+        r.sessions.create(session).await.unwrap();
+        // ids are only ordered to the millisecond
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+    created.reverse();
+
+    let query = |before_id: Option<String>| model::LoginSessionQuery {
+        user_id: saved.id.clone(),
+        before_id: before_id,
+        limit: 2,
+    };
+    let first = r.sessions.list(query(None)).await.unwrap();
+    assert_eq!(
+        created[..2].to_vec(),
+        first.iter().map(|s| s.id.clone()).collect::<Vec<_>>()
+    );
+    let second = r
+        .sessions
+        .list(query(Some(created[1].clone())))
+        .await
+        .unwrap();
+    assert_eq!(1, second.len());
+    assert_eq!(created[2], second[0].id);
+
+    // failed attempts cannot be revoked
+    assert!(!r.sessions.revoke(created[1].clone()).await.unwrap());
+    assert!(r.sessions.revoke(created[0].clone()).await.unwrap());
+    assert!(!r.sessions.revoke(created[0].clone()).await.unwrap());
+    let revoked = r
+        .sessions
+        .get_by_id(created[0].clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!revoked.is_active());
+    assert!(revoked.revoked_at.is_some());
+
+    // sessions need their user, and go with it
+    let orphan = model::LoginSession::new(ulid::Ulid::new().to_string(), &actor, true);
+    match r.sessions.create(orphan).await {
+        Err(DBConnectorError::DBError(_)) => (),
+        _ => panic!("expected a foreign key violation"),
+    }
+    assert!(r.users.purge(saved.id).await.unwrap());
+    assert!(r
+        .sessions
+        .get_by_id(created[2].clone())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn outbox_events_should_commit_with_the_work(r: &Repositories) {
+    let saved = test_user(&unique_name("outbox"));
+    let created = model::OutboxEvent::new(model::DomainEvent::UserCreated {
+        user_id: saved.id.clone(),
+        name: saved.name.clone(),
+        role: model::Role::User,
+    });
+    // ids are only ordered to the millisecond
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let enabled = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: saved.id.clone(),
+    });
+    let ids = vec![created.id.clone(), enabled.id.clone()];
+
+    // rolled back with the login, which has no user
+    let (user_id, event) = (saved.id.clone(), enabled.clone());
+    let result = r
+        .unit_of_work
+        .run(Box::new(move |tx: &dyn ITransaction| {
+            tx.outbox().append(event)?;
+            tx.logins().create(login(&user_id, 0))
+        }))
+        .await;
+    assert!(result.is_err());
+
+    r.unit_of_work
+        .run(Box::new(move |tx: &dyn ITransaction| {
+            tx.users().save(saved)?;
+            tx.outbox().append(created)?;
+            tx.outbox().append(enabled)
+        }))
+        .await
+        .unwrap();
+
+    let due = |at: chrono::NaiveDateTime| {
+        let ids = ids.clone();
+        async move {
+            r.outbox
+                .list_due(at, 1000)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|e| ids.contains(&e.id))
+                .collect::<Vec<_>>()
+        }
+    };
+    let now = chrono::Utc::now().naive_utc();
+    let pending = due(now).await;
+    assert_eq!(
+        ids,
+        pending.iter().map(|e| e.id.clone()).collect::<Vec<_>>()
+    );
+    assert_eq!("user_created", pending[0].event.event_type());
+
+    // a failed event waits for its next attempt, a dispatched one is gone
+    let later = now + chrono::Duration::minutes(1);
+    r.outbox
+        .mark_failed(ids[0].clone(), "refused".to_owned(), Some(later))
+        .await
+        .unwrap();
+    r.outbox.mark_dispatched(ids[1].clone()).await.unwrap();
+    assert!(due(now).await.is_empty());
+    let retried = due(later).await;
+    assert_eq!(1, retried.len());
+    assert_eq!(1, retried[0].attempts);
+
+    // and a dead one is never retried
+    r.outbox
+        .mark_failed(ids[0].clone(), "refused".to_owned(), None)
+        .await
+        .unwrap();
+    assert!(due(later).await.is_empty());
+}
+
+async fn outbox_events_should_be_claimed_once(r: &Repositories) {
+    let saved = test_user(&unique_name("claimed"));
+    // due long before the events of the other cases, which are left alone
+    let past = chrono::NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0);
+    let mut event = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: saved.id.clone(),
+    });
+    event.occurred_at = past;
+    let id = event.id.clone();
+    r.unit_of_work
+        .run(Box::new(move |tx: &dyn ITransaction| {
+            tx.users().save(saved)?;
+            tx.outbox().append(event)
+        }))
+        .await
+        .unwrap();
+
+    let claim = |at: chrono::NaiveDateTime| {
+        let id = id.clone();
+        async move {
+            r.outbox
+                .claim_due(at, at + chrono::Duration::minutes(1), 1000)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.id == id)
+                .count()
+        }
+    };
+    assert_eq!(1, claim(past).await);
+    assert_eq!(0, claim(past).await);
+    // until the lease runs out
+    assert_eq!(1, claim(past + chrono::Duration::minutes(1)).await);
+
+    r.outbox.mark_dispatched(id).await.unwrap();
+}
+
+async fn webhook_deliveries_should_be_queued_once(r: &Repositories) {
+    let endpoint = model::WebhookEndpoint {
+        id: ulid::Ulid::new().to_string(),
+        url: "http://localhost/hook".to_owned(),
+        secret: "secret".to_owned(),
+        event_types: vec!["login_enabled".to_owned(), "user_created".to_owned()],
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    r.webhooks.create_endpoint(endpoint.clone()).await.unwrap();
+    let found = r
+        .webhooks
+        .get_endpoint(endpoint.id.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(endpoint.event_types, found.event_types);
+    assert_eq!("secret", found.secret);
+
+    let event = |user_id: &str| {
+        model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+            user_id: user_id.to_owned(),
+        })
+    };
+    let first = model::WebhookDelivery::new(endpoint.id.clone(), &event("u1"));
+    // ids are only ordered to the millisecond
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let second = model::WebhookDelivery::new(endpoint.id.clone(), &event("u2"));
+    r.webhooks
+        .enqueue(vec![first.clone(), second.clone()])
+        .await
+        .unwrap();
+
+    // the same event again is skipped, whatever the delivery id
+    let mut again = model::WebhookDelivery::new(endpoint.id.clone(), &event("u1"));
+    again.event_id = first.event_id.clone();
+    r.webhooks.enqueue(vec![again]).await.unwrap();
+
+    let listed = |status: Option<model::WebhookDeliveryStatus>| {
+        let endpoint_id = endpoint.id.clone();
+        async move {
+            r.webhooks
+                .list_deliveries(model::WebhookDeliveryQuery {
+                    endpoint_id: endpoint_id,
+                    status: status,
+                    before_id: None,
+                    limit: 10,
+                })
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|d| d.id)
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(
+        vec![second.id.clone(), first.id.clone()],
+        listed(None).await
+    );
+
+    let now = chrono::Utc::now().naive_utc();
+    let due = r
+        .webhooks
+        .list_due(now, 1000)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|(_, e)| e.id == endpoint.id)
+        .collect::<Vec<_>>();
+    assert_eq!(2, due.len());
+    assert_eq!(first.id, due[0].0.id);
+    assert_eq!(first.payload, due[0].0.payload);
+    assert_eq!("secret", due[0].1.secret);
+
+    let mut dead = due[0].0.clone();
+    dead.status = model::WebhookDeliveryStatus::Dead;
+    dead.attempts = 3;
+    dead.last_status_code = Some(500);
+    dead.last_error = Some("answered 500".to_owned());
+    r.webhooks.update_delivery(dead).await.unwrap();
+    let updated = r
+        .webhooks
+        .get_delivery(first.id.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(model::WebhookDeliveryStatus::Dead, updated.status);
+    assert_eq!(3, updated.attempts);
+    assert_eq!(Some(500), updated.last_status_code);
+    assert_eq!(
+        vec![first.id.clone()],
+        listed(Some(model::WebhookDeliveryStatus::Dead)).await
+    );
+
+    // the deliveries go with their endpoint
+    assert!(r
+        .webhooks
+        .delete_endpoint(endpoint.id.clone())
+        .await
+        .unwrap());
+    assert!(!r
+        .webhooks
+        .delete_endpoint(endpoint.id.clone())
+        .await
+        .unwrap());
+    assert!(r.webhooks.get_delivery(second.id).await.unwrap().is_none());
+    assert!(listed(None).await.is_empty());
+}
+
+async fn webhook_deliveries_should_be_claimed_once(r: &Repositories) {
+    let endpoint = model::WebhookEndpoint {
+        id: ulid::Ulid::new().to_string(),
+        url: "http://localhost/hook".to_owned(),
+        secret: "secret".to_owned(),
+        event_types: vec!["login_enabled".to_owned()],
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    r.webhooks.create_endpoint(endpoint.clone()).await.unwrap();
+    let event = model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: "u1".to_owned(),
+    });
+    // due long before the deliveries of the other cases, which are left alone
+    let past = chrono::NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0);
+    let mut delivery = model::WebhookDelivery::new(endpoint.id.clone(), &event);
+    delivery.next_attempt_at = past;
+    r.webhooks.enqueue(vec![delivery]).await.unwrap();
+
+    let claim = |at: chrono::NaiveDateTime| {
+        let endpoint_id = endpoint.id.clone();
+        async move {
+            r.webhooks
+                .claim_due(at, at + chrono::Duration::minutes(1), 1000)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|(_, e)| e.id == endpoint_id)
+                .count()
+        }
+    };
+    assert_eq!(1, claim(past).await);
+    assert_eq!(0, claim(past).await);
+    // until the lease runs out
+    assert_eq!(1, claim(past + chrono::Duration::minutes(1)).await);
+
+    assert!(r.webhooks.delete_endpoint(endpoint.id).await.unwrap());
+}
+
+#[test]
+fn memory_repositories_should_conform() {
+    use super::memory::*;
+
+    let store = MemoryStore::new();
+    futures::executor::block_on(run(Repositories {
+        users: Arc::new(MemoryUserRepository::new(store.clone())),
+        logins: Arc::new(MemoryUserLoginRepository::new(store.clone())),
+        unit_of_work: Arc::new(MemoryUnitOfWork::new(store.clone())),
+        audit_log: Arc::new(MemoryAuditLog::new(store.clone())),
+        sessions: Arc::new(MemoryLoginSessionRepository::new(store.clone())),
+        outbox: Arc::new(MemoryOutboxRepository::new(store.clone())),
+        webhooks: Arc::new(MemoryWebhookRepository::new(store)),
+    }));
+}
+
+#[test]
+fn cached_repositories_should_conform() {
+    use super::cache::*;
+    use super::memory::*;
+
+    let store = MemoryStore::new();
+    let cache = UserCache::new(&crate::config::CacheConfig {
+        ttl: std::time::Duration::from_secs(60),
+        max_entries: 100,
+    });
+    futures::executor::block_on(run(Repositories {
+        users: Arc::new(CachingUserRepository::new(
+            Arc::new(MemoryUserRepository::new(store.clone())),
+            cache.clone(),
+        )),
+        logins: Arc::new(CachingUserLoginRepository::new(
+            Arc::new(MemoryUserLoginRepository::new(store.clone())),
+            cache.clone(),
+        )),
+        unit_of_work: Arc::new(CachingUnitOfWork::new(
+            Arc::new(MemoryUnitOfWork::new(store.clone())),
+            cache,
+        )),
+        audit_log: Arc::new(MemoryAuditLog::new(store.clone())),
+        sessions: Arc::new(MemoryLoginSessionRepository::new(store.clone())),
+        outbox: Arc::new(MemoryOutboxRepository::new(store.clone())),
+        webhooks: Arc::new(MemoryWebhookRepository::new(store)),
+    }));
+}
+
+#[test]
+#[cfg_attr(not(feature = "sqlite"), ignore)]
+fn sql_repositories_should_conform() {
+    use super::{
+        audit_log::AuditLog, login_session_repo::LoginSessionRepository,
+        outbox_repo::OutboxRepository, unit_of_work::UnitOfWork,
+        user_login_repo::UserLoginRepository, user_repo::UserRepository,
+        webhook_repo::WebhookRepository,
+    };
+
+    super::with_test_user(|db, _| {
+        run(Repositories {
+            users: Arc::new(UserRepository::new(db.clone())),
+            logins: Arc::new(UserLoginRepository::new(db.clone())),
+            unit_of_work: Arc::new(UnitOfWork::new(db.clone())),
+            audit_log: Arc::new(AuditLog::new(db.clone())),
+            sessions: Arc::new(LoginSessionRepository::new(db.clone())),
+            outbox: Arc::new(OutboxRepository::new(db.clone())),
+            webhooks: Arc::new(WebhookRepository::new(db)),
+        })
+    });
+}
@@ -0,0 +1,138 @@
+use crate::domain::interface::ILoginSessionRepository;
+use crate::domain::model;
+use crate::infra::{traced, DBBackend, DBConnector, DBConnectorError};
+use crate::schema::*;
+use async_trait::async_trait;
+use diesel::dsl::*;
+use diesel::prelude::*;
+
+#[derive(Queryable, Insertable, Clone)]
+pub struct LoginSessionRecord {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub succeeded: bool,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+impl LoginSessionRecord {
+    pub fn to_model(self) -> model::LoginSession {
+        model::LoginSession {
+            id: self.id,
+            user_id: self.user_id,
+            created_at: self.created_at,
+            ip: self.ip,
+            user_agent: self.user_agent,
+            succeeded: self.succeeded,
+            revoked_at: self.revoked_at,
+        }
+    }
+
+    pub fn from_model(session: model::LoginSession) -> Self {
+        LoginSessionRecord {
+            id: session.id,
+            user_id: session.user_id,
+            created_at: session.created_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            succeeded: session.succeeded,
+            revoked_at: session.revoked_at,
+        }
+    }
+}
+
+fn paginated<'a>(
+    query: model::LoginSessionQuery,
+) -> login_session_records::BoxedQuery<'a, DBBackend> {
+    let mut q = login_session_records::table
+        .filter(login_session_records::user_id.eq(query.user_id))
+        .into_boxed();
+
+    if let Some(before_id) = query.before_id {
+        q = q.filter(login_session_records::id.lt(before_id));
+    }
+
+    q.order(login_session_records::id.desc()).limit(query.limit)
+}
+
+pub struct LoginSessionRepository {
+    db: DBConnector,
+}
+
+impl LoginSessionRepository {
+    pub fn new(db: DBConnector) -> LoginSessionRepository {
+        LoginSessionRepository { db: db }
+    }
+}
+
+#[async_trait]
+impl ILoginSessionRepository for LoginSessionRepository {
+    async fn create(&self, session: model::LoginSession) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("LoginSessionRepository::create")
+            .execute(
+                insert_into(login_session_records::table)
+                    .values::<LoginSessionRecord>(LoginSessionRecord::from_model(session)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_by_id(
+        &self,
+        session_id: String,
+    ) -> Result<Option<model::LoginSession>, DBConnectorError> {
+        let session = self
+            .db
+            .caller("LoginSessionRepository::get_by_id")
+            .primary()
+            .first::<LoginSessionRecord, _>(
+                login_session_records::table.filter(login_session_records::id.eq(session_id)),
+            )
+            .await
+            .optional()?;
+
+        Ok(session.map(|s| s.to_model()))
+    }
+
+    async fn list(
+        &self,
+        query: model::LoginSessionQuery,
+    ) -> Result<Vec<model::LoginSession>, DBConnectorError> {
+        let records = self
+            .db
+            .caller("LoginSessionRepository::list")
+            .run_read_only(move |conn| {
+                traced(
+                    "LoginSessionRepository::list",
+                    paginated(query),
+                    |v| Some(v.len()),
+                    |query| query.load::<LoginSessionRecord>(conn),
+                )
+            })
+            .await?;
+
+        Ok(records.into_iter().map(|r| r.to_model()).collect())
+    }
+
+    async fn revoke(&self, session_id: String) -> Result<bool, DBConnectorError> {
+        let rows = self
+            .db
+            .caller("LoginSessionRepository::revoke")
+            .execute(
+                update(
+                    login_session_records::table
+                        .filter(login_session_records::id.eq(session_id))
+                        .filter(login_session_records::succeeded.eq(true))
+                        .filter(login_session_records::revoked_at.is_null()),
+                )
+                .set(login_session_records::revoked_at.eq(chrono::Utc::now().naive_utc())),
+            )
+            .await?;
+
+        Ok(rows > 0)
+    }
+}
@@ -0,0 +1,604 @@
+// Repositories keeping the rows in process, with the constraints of the SQL schema
+// For tests and local development without a database (REPOSITORY=memory)
+use super::audit_log::AuditLogRecord;
+use super::login_session_repo::LoginSessionRecord;
+use super::outbox_repo::{OutboxEventRecord, PENDING};
+use super::user_login_repo::{UserEmailVerificationRecord, UserLoginRecord};
+use super::user_repo::UserRecord;
+use super::webhook_repo::{WebhookDeliveryRecord, WebhookEndpointRecord};
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use diesel::result::{DatabaseErrorKind, Error};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+mod audit_log;
+mod login_session_repo;
+mod outbox_repo;
+mod unit_of_work;
+mod user_login_repo;
+mod user_repo;
+mod webhook_repo;
+
+pub use audit_log::*;
+pub use login_session_repo::*;
+pub use outbox_repo::*;
+pub use unit_of_work::*;
+pub use user_login_repo::*;
+pub use user_repo::*;
+pub use webhook_repo::*;
+
+// Fixtures for the tests on these repositories
+#[cfg(test)]
+pub fn test_user(name: &str) -> model::User {
+    model::User {
+        id: ulid::Ulid::new().to_string(),
+        name: name.to_owned(),
+        display_name: "test".to_owned(),
+        role: model::Role::User,
+        email: None,
+    }
+}
+
+#[cfg(test)]
+pub fn test_actor(user: &model::User) -> model::Actor {
+    model::Actor {
+        user_id: Some(user.id.clone()),
+        ip: None,
+        user_agent: None,
+    }
+}
+
+#[cfg(test)]
+pub fn test_event(user_id: &str) -> model::OutboxEvent {
+    model::OutboxEvent::new(model::DomainEvent::LoginEnabled {
+        user_id: user_id.to_owned(),
+    })
+}
+
+// the same error as a violated constraint in SQL, e.g. UniqueViolation
+fn violation(kind: DatabaseErrorKind, message: String) -> DBConnectorError {
+    DBConnectorError::from(Error::DatabaseError(kind, Box::new(message)))
+}
+
+fn duplicate(value: &str, key: &str) -> DBConnectorError {
+    violation(
+        DatabaseErrorKind::UniqueViolation,
+        format!("Duplicate entry '{}' for key '{}'", value, key),
+    )
+}
+
+// The rows of the SQL tables, keyed by their primary key
+// Every method checks the constraints before changing anything
+#[derive(Clone, Default)]
+struct Tables {
+    users: BTreeMap<String, UserRecord>,
+    logins: BTreeMap<String, UserLoginRecord>,
+    verifications: BTreeMap<String, UserEmailVerificationRecord>,
+    audit: BTreeMap<String, AuditLogRecord>,
+    sessions: BTreeMap<String, LoginSessionRecord>,
+    outbox: BTreeMap<String, OutboxEventRecord>,
+    webhook_endpoints: BTreeMap<String, WebhookEndpointRecord>,
+    webhook_deliveries: BTreeMap<String, WebhookDeliveryRecord>,
+}
+
+impl Tables {
+    // the unique indexes also cover soft deleted users
+    fn check_unique_user(&self, record: &UserRecord) -> Result<(), DBConnectorError> {
+        for other in self.users.values().filter(|u| u.id != record.id) {
+            if other.name == record.name {
+                return Err(duplicate(&record.name, "name"));
+            }
+            if let (Some(email), Some(other_email)) = (&record.email, &other.email) {
+                if email == other_email {
+                    return Err(duplicate(email, "email"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // foreign keys to user_records
+    fn check_user_exists(&self, user_id: &str) -> Result<(), DBConnectorError> {
+        if self.users.contains_key(user_id) {
+            Ok(())
+        } else {
+            Err(violation(
+                DatabaseErrorKind::ForeignKeyViolation,
+                format!("Cannot add a child row: no user '{}'", user_id),
+            ))
+        }
+    }
+
+    fn get_user(&self, user_id: &str) -> Option<model::User> {
+        self.users
+            .get(user_id)
+            .filter(|u| u.deleted_at.is_none())
+            .map(|u| u.clone().to_model())
+    }
+
+    // same filter as user_repo::filtered; the name prefix is case insensitive like MySQL's collation
+    fn filtered_users(&self, filter: &model::UserFilter) -> Vec<&UserRecord> {
+        let role = filter.role.as_ref().map(|r| r.as_string());
+        let prefix = filter.name_prefix.as_ref().map(|p| p.to_lowercase());
+
+        self.users
+            .values()
+            .filter(|u| u.deleted_at.is_none())
+            .filter(|u| match &filter.role {
+                None => true,
+                Some(model::Role::Unknown) => u.role.is_none() || u.role == role,
+                Some(_) => u.role == role,
+            })
+            .filter(|u| match &prefix {
+                None => true,
+                Some(prefix) => {
+                    u.name.to_lowercase().starts_with(prefix)
+                        || u.display_name.to_lowercase().starts_with(prefix)
+                }
+            })
+            .collect()
+    }
+
+    // same order and cursor as user_repo::paginated
+    fn list_users(&self, query: model::UserListQuery) -> Vec<model::User> {
+        use model::UserSort::*;
+
+        let mut users = self.filtered_users(&query.filter);
+        if let Some(cursor) = &query.cursor {
+            let at = (cursor.name.as_str(), cursor.id.as_str());
+            users.retain(|u| match query.sort {
+                IdAsc => u.id > cursor.id,
+                IdDesc => u.id < cursor.id,
+                NameAsc => (u.name.as_str(), u.id.as_str()) > at,
+                NameDesc => (u.name.as_str(), u.id.as_str()) < at,
+            });
+        }
+
+        match query.sort {
+            IdAsc => users.sort_by(|a, b| a.id.cmp(&b.id)),
+            IdDesc => users.sort_by(|a, b| b.id.cmp(&a.id)),
+            NameAsc => users.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id))),
+            NameDesc => users.sort_by(|a, b| (&b.name, &b.id).cmp(&(&a.name, &a.id))),
+        }
+
+        users
+            .into_iter()
+            .take(query.limit.max(0) as usize)
+            .map(|u| u.clone().to_model())
+            .collect()
+    }
+
+    fn insert_user(&mut self, record: UserRecord) -> Result<(), DBConnectorError> {
+        if self.users.contains_key(&record.id) {
+            return Err(duplicate(&record.id, "PRIMARY"));
+        }
+        self.check_unique_user(&record)?;
+
+        self.users.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    // like the SQL update, a missing or deleted user is not an error
+    fn update_user(&mut self, record: UserRecord) -> Result<(), DBConnectorError> {
+        if self.get_user(&record.id).is_none() {
+            return Ok(());
+        }
+        self.check_unique_user(&record)?;
+
+        if let Some(user) = self.users.get_mut(&record.id) {
+            user.name = record.name;
+            user.display_name = record.display_name;
+            user.role = record.role;
+            user.email = record.email;
+        }
+        Ok(())
+    }
+
+    fn delete_user(&mut self, user_id: &str) -> bool {
+        match self.users.get_mut(user_id) {
+            Some(user) if user.deleted_at.is_none() => {
+                user.deleted_at = Some(chrono::Utc::now().naive_utc());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // `on delete cascade`
+    fn purge_user(&mut self, user_id: &str) -> bool {
+        self.logins.remove(user_id);
+        self.verifications.remove(user_id);
+        self.sessions.retain(|_, s| s.user_id != user_id);
+        self.users.remove(user_id).is_some()
+    }
+
+    fn get_login(&self, user_id: &str) -> Option<model::Login> {
+        self.logins.get(user_id).map(|l| l.clone().to_model())
+    }
+
+    fn get_login_by_user_name(&self, user_name: &str) -> Option<(model::Login, model::User)> {
+        let user = self
+            .users
+            .values()
+            .find(|u| u.name == user_name && u.deleted_at.is_none())?;
+        let login = self.logins.get(&user.id)?;
+
+        Some((login.clone().to_model(), user.clone().to_model()))
+    }
+
+    fn insert_login(&mut self, record: UserLoginRecord) -> Result<(), DBConnectorError> {
+        if self.logins.contains_key(&record.user_id) {
+            return Err(duplicate(&record.user_id, "PRIMARY"));
+        }
+        self.check_user_exists(&record.user_id)?;
+
+        self.logins.insert(record.user_id.clone(), record);
+        Ok(())
+    }
+
+    // same as UPSERT_LOGIN
+    fn upsert_login(&mut self, record: UserLoginRecord) -> Result<(), DBConnectorError> {
+        match self.logins.get_mut(&record.user_id) {
+            Some(login) => {
+                login.password_hash = record.password_hash;
+                login.status = record.status;
+                login.version += 1;
+                Ok(())
+            }
+            None => self.insert_login(UserLoginRecord {
+                version: 0,
+                ..record
+            }),
+        }
+    }
+
+    fn update_login(&mut self, record: UserLoginRecord) -> Result<(), DBConnectorError> {
+        match self.logins.get_mut(&record.user_id) {
+            None => Err(DBConnectorError::NotFound),
+            Some(login) if login.version != record.version => {
+                Err(DBConnectorError::VersionConflict)
+            }
+            Some(login) => {
+                login.password_hash = record.password_hash;
+                login.status = record.status;
+                login.version += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn insert_verification(
+        &mut self,
+        record: UserEmailVerificationRecord,
+    ) -> Result<(), DBConnectorError> {
+        if self.verifications.contains_key(&record.user_id) {
+            return Err(duplicate(&record.user_id, "PRIMARY"));
+        }
+        if self
+            .verifications
+            .values()
+            .any(|v| v.token_hash == record.token_hash)
+        {
+            return Err(duplicate(&record.token_hash, "token_hash"));
+        }
+        self.check_user_exists(&record.user_id)?;
+
+        self.verifications.insert(record.user_id.clone(), record);
+        Ok(())
+    }
+
+    fn get_verification(&self, token_hash: &str) -> Option<model::EmailVerification> {
+        self.verifications
+            .values()
+            .find(|v| v.token_hash == token_hash)
+            .map(|v| v.clone().to_model())
+    }
+
+    // like the SQL one, NotFound unless the verification and a pending login of a user are left
+    fn complete_verification(&mut self, user_id: &str) -> Result<(), DBConnectorError> {
+        let pending = serde_json::to_string(&model::LoginUserStatus::PendingVerification).ok();
+        if !self.verifications.contains_key(user_id) || self.get_user(user_id).is_none() {
+            return Err(DBConnectorError::NotFound);
+        }
+        match self.logins.get_mut(user_id) {
+            Some(login) if login.status == pending => {
+                login.status = serde_json::to_string(&model::LoginUserStatus::Enabled).ok();
+                login.version += 1;
+            }
+            _ => return Err(DBConnectorError::NotFound),
+        }
+        self.verifications.remove(user_id);
+
+        Ok(())
+    }
+
+    // replaces the verification of the pending login of the user with this email
+    fn renew_verification(
+        &mut self,
+        email: &str,
+        record: impl FnOnce(String) -> UserEmailVerificationRecord,
+    ) -> Result<bool, DBConnectorError> {
+        let pending = serde_json::to_string(&model::LoginUserStatus::PendingVerification).ok();
+        let logins = &self.logins;
+        let user_id = match self.users.values().find(|u| {
+            u.email.as_ref().map(String::as_str) == Some(email)
+                && u.deleted_at.is_none()
+                && logins.get(&u.id).map_or(false, |l| l.status == pending)
+        }) {
+            Some(user) => user.id.clone(),
+            None => return Ok(false),
+        };
+
+        let record = record(user_id);
+        if self
+            .verifications
+            .values()
+            .any(|v| v.user_id != record.user_id && v.token_hash == record.token_hash)
+        {
+            return Err(duplicate(&record.token_hash, "token_hash"));
+        }
+
+        self.verifications.insert(record.user_id.clone(), record);
+        Ok(true)
+    }
+
+    fn insert_audit(&mut self, record: AuditLogRecord) -> Result<(), DBConnectorError> {
+        if self.audit.contains_key(&record.id) {
+            return Err(duplicate(&record.id, "PRIMARY"));
+        }
+
+        self.audit.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    fn insert_session(&mut self, record: LoginSessionRecord) -> Result<(), DBConnectorError> {
+        if self.sessions.contains_key(&record.id) {
+            return Err(duplicate(&record.id, "PRIMARY"));
+        }
+        self.check_user_exists(&record.user_id)?;
+
+        self.sessions.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    fn get_session(&self, session_id: &str) -> Option<model::LoginSession> {
+        self.sessions.get(session_id).map(|s| s.clone().to_model())
+    }
+
+    // same filter and order as login_session_repo::paginated
+    fn list_sessions(&self, query: model::LoginSessionQuery) -> Vec<model::LoginSession> {
+        let newest_first: Box<dyn Iterator<Item = &LoginSessionRecord>> = match query.before_id {
+            Some(before_id) => Box::new(self.sessions.range(..before_id).rev().map(|(_, s)| s)),
+            None => Box::new(self.sessions.values().rev()),
+        };
+
+        newest_first
+            .filter(|s| s.user_id == query.user_id)
+            .take(query.limit.max(0) as usize)
+            .map(|s| s.clone().to_model())
+            .collect()
+    }
+
+    fn revoke_session(&mut self, session_id: &str) -> bool {
+        match self.sessions.get_mut(session_id) {
+            Some(session) if session.succeeded && session.revoked_at.is_none() => {
+                session.revoked_at = Some(chrono::Utc::now().naive_utc());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn insert_outbox(&mut self, record: OutboxEventRecord) -> Result<(), DBConnectorError> {
+        if self.outbox.contains_key(&record.id) {
+            return Err(duplicate(&record.id, "PRIMARY"));
+        }
+
+        self.outbox.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    // same filter and order as OutboxRepository::list_due
+    fn list_due_outbox(&self, now: chrono::NaiveDateTime, limit: i64) -> Vec<model::OutboxEvent> {
+        self.outbox
+            .values()
+            .filter(|e| e.status == PENDING && e.next_attempt_at <= now)
+            .take(limit.max(0) as usize)
+            // the records are written from models, so they can always be read
+            .filter_map(|e| e.clone().to_model().ok())
+            .collect()
+    }
+
+    fn update_outbox(&mut self, event_id: &str, f: impl FnOnce(&mut OutboxEventRecord)) {
+        if let Some(event) = self.outbox.get_mut(event_id) {
+            f(event);
+        }
+    }
+
+    fn insert_webhook_endpoint(
+        &mut self,
+        record: WebhookEndpointRecord,
+    ) -> Result<(), DBConnectorError> {
+        if self.webhook_endpoints.contains_key(&record.id) {
+            return Err(duplicate(&record.id, "PRIMARY"));
+        }
+
+        self.webhook_endpoints.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    fn list_webhook_endpoints(&self) -> Vec<model::WebhookEndpoint> {
+        self.webhook_endpoints
+            .values()
+            .map(|e| e.clone().to_model())
+            .collect()
+    }
+
+    fn get_webhook_endpoint(&self, endpoint_id: &str) -> Option<model::WebhookEndpoint> {
+        self.webhook_endpoints
+            .get(endpoint_id)
+            .map(|e| e.clone().to_model())
+    }
+
+    // with its deliveries, like the foreign key
+    fn delete_webhook_endpoint(&mut self, endpoint_id: &str) -> bool {
+        self.webhook_deliveries
+            .retain(|_, d| d.endpoint_id != endpoint_id);
+        self.webhook_endpoints.remove(endpoint_id).is_some()
+    }
+
+    // skips the deliveries which are already queued, see WebhookRepository::enqueue
+    fn enqueue_webhook_delivery(
+        &mut self,
+        record: WebhookDeliveryRecord,
+    ) -> Result<(), DBConnectorError> {
+        if self
+            .webhook_deliveries
+            .values()
+            .any(|d| d.endpoint_id == record.endpoint_id && d.event_id == record.event_id)
+        {
+            return Ok(());
+        }
+        if self.webhook_deliveries.contains_key(&record.id) {
+            return Err(duplicate(&record.id, "PRIMARY"));
+        }
+        if !self.webhook_endpoints.contains_key(&record.endpoint_id) {
+            return Err(violation(
+                DatabaseErrorKind::ForeignKeyViolation,
+                format!(
+                    "Cannot add a child row: no endpoint '{}'",
+                    record.endpoint_id
+                ),
+            ));
+        }
+
+        self.webhook_deliveries.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    // same filter and order as WebhookRepository::list_due
+    fn list_due_webhook_deliveries(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Vec<(model::WebhookDelivery, model::WebhookEndpoint)> {
+        let pending = model::WebhookDeliveryStatus::Pending.as_string();
+
+        self.webhook_deliveries
+            .values()
+            .filter(|d| d.status == pending && d.next_attempt_at <= now)
+            .filter_map(|d| {
+                let endpoint = self.webhook_endpoints.get(&d.endpoint_id)?;
+                Some((d.clone().to_model(), endpoint.clone().to_model()))
+            })
+            .take(limit.max(0) as usize)
+            .collect()
+    }
+
+    fn claim_due_webhook_deliveries(
+        &mut self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Vec<(model::WebhookDelivery, model::WebhookEndpoint)> {
+        let due = self.list_due_webhook_deliveries(now, limit);
+        for (delivery, _) in &due {
+            if let Some(record) = self.webhook_deliveries.get_mut(&delivery.id) {
+                record.next_attempt_at = lease_until;
+            }
+        }
+        due
+    }
+
+    // same filter and order as webhook_repo::paginated
+    fn list_webhook_deliveries(
+        &self,
+        query: model::WebhookDeliveryQuery,
+    ) -> Vec<model::WebhookDelivery> {
+        let status = query.status.map(|s| s.as_string());
+        let newest_first: Box<dyn Iterator<Item = &WebhookDeliveryRecord>> = match query.before_id {
+            Some(before_id) => Box::new(
+                self.webhook_deliveries
+                    .range(..before_id)
+                    .rev()
+                    .map(|(_, d)| d),
+            ),
+            None => Box::new(self.webhook_deliveries.values().rev()),
+        };
+
+        newest_first
+            .filter(|d| d.endpoint_id == query.endpoint_id)
+            .filter(|d| status.as_ref().map_or(true, |s| &d.status == s))
+            .take(query.limit.max(0) as usize)
+            .map(|d| d.clone().to_model())
+            .collect()
+    }
+
+    fn get_webhook_delivery(&self, delivery_id: &str) -> Option<model::WebhookDelivery> {
+        self.webhook_deliveries
+            .get(delivery_id)
+            .map(|d| d.clone().to_model())
+    }
+
+    fn update_webhook_delivery(&mut self, record: WebhookDeliveryRecord) {
+        if let Some(delivery) = self.webhook_deliveries.get_mut(&record.id) {
+            delivery.status = record.status;
+            delivery.attempts = record.attempts;
+            delivery.next_attempt_at = record.next_attempt_at;
+            delivery.last_status_code = record.last_status_code;
+            delivery.last_error = record.last_error;
+            delivery.updated_at = record.updated_at;
+        }
+    }
+
+    // same filter and order as audit_log::paginated
+    fn list_audit(&self, query: model::AuditQuery) -> Vec<model::AuditEntry> {
+        let filter = query.filter;
+        let action = filter.action.map(|a| a.as_string());
+        let outcome = filter.outcome.map(|o| o.as_string());
+        let newest_first: Box<dyn Iterator<Item = &AuditLogRecord>> = match query.before_id {
+            Some(before_id) => Box::new(self.audit.range(..before_id).rev().map(|(_, e)| e)),
+            None => Box::new(self.audit.values().rev()),
+        };
+
+        newest_first
+            .filter(|e| filter.actor_id.is_none() || e.actor_id == filter.actor_id)
+            .filter(|e| filter.target_id.is_none() || e.target_id == filter.target_id)
+            .filter(|e| action.as_ref().map_or(true, |a| &e.action == a))
+            .filter(|e| outcome.as_ref().map_or(true, |o| &e.outcome == o))
+            .filter(|e| filter.since.map_or(true, |since| e.occurred_at >= since))
+            .filter(|e| filter.until.map_or(true, |until| e.occurred_at < until))
+            .take(query.limit.max(0) as usize)
+            .map(|e| e.clone().to_model())
+            .collect()
+    }
+}
+
+// Shared by every repository (and worker) made from it
+#[derive(Clone, Default)]
+pub struct MemoryStore(Arc<Mutex<Tables>>);
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&Tables) -> R) -> R {
+        f(&self.0.lock().unwrap())
+    }
+
+    // f works on a copy, which replaces the tables only when it succeeds (like a transaction)
+    fn write<R>(
+        &self,
+        f: impl FnOnce(&mut Tables) -> Result<R, DBConnectorError>,
+    ) -> Result<R, DBConnectorError> {
+        let mut tables = self.0.lock().unwrap();
+        let mut copy = tables.clone();
+        let result = f(&mut copy)?;
+        *tables = copy;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,31 @@
+use super::MemoryStore;
+use crate::domain::interface::IAuditLog;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::audit_log::AuditLogRecord;
+use async_trait::async_trait;
+
+pub struct MemoryAuditLog {
+    store: MemoryStore,
+}
+
+impl MemoryAuditLog {
+    pub fn new(store: MemoryStore) -> MemoryAuditLog {
+        MemoryAuditLog { store: store }
+    }
+}
+
+#[async_trait]
+impl IAuditLog for MemoryAuditLog {
+    async fn append(&self, entry: model::AuditEntry) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.insert_audit(AuditLogRecord::from_model(entry)))
+    }
+
+    async fn list(
+        &self,
+        query: model::AuditQuery,
+    ) -> Result<Vec<model::AuditEntry>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.list_audit(query)))
+    }
+}
@@ -0,0 +1,43 @@
+use super::MemoryStore;
+use crate::domain::interface::ILoginSessionRepository;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::login_session_repo::LoginSessionRecord;
+use async_trait::async_trait;
+
+pub struct MemoryLoginSessionRepository {
+    store: MemoryStore,
+}
+
+impl MemoryLoginSessionRepository {
+    pub fn new(store: MemoryStore) -> MemoryLoginSessionRepository {
+        MemoryLoginSessionRepository { store: store }
+    }
+}
+
+#[async_trait]
+impl ILoginSessionRepository for MemoryLoginSessionRepository {
+    async fn create(&self, session: model::LoginSession) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.insert_session(LoginSessionRecord::from_model(session)))
+    }
+
+    async fn get_by_id(
+        &self,
+        session_id: String,
+    ) -> Result<Option<model::LoginSession>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.get_session(&session_id)))
+    }
+
+    async fn list(
+        &self,
+        query: model::LoginSessionQuery,
+    ) -> Result<Vec<model::LoginSession>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.list_sessions(query)))
+    }
+
+    async fn revoke(&self, session_id: String) -> Result<bool, DBConnectorError> {
+        self.store
+            .write(|tables| Ok(tables.revoke_session(&session_id)))
+    }
+}
@@ -0,0 +1,71 @@
+use super::MemoryStore;
+use crate::domain::interface::IOutboxRepository;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::outbox_repo::{DEAD, DISPATCHED, PENDING};
+use async_trait::async_trait;
+
+pub struct MemoryOutboxRepository {
+    store: MemoryStore,
+}
+
+impl MemoryOutboxRepository {
+    pub fn new(store: MemoryStore) -> MemoryOutboxRepository {
+        MemoryOutboxRepository { store: store }
+    }
+}
+
+#[async_trait]
+impl IOutboxRepository for MemoryOutboxRepository {
+    async fn list_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.list_due_outbox(now, limit)))
+    }
+
+    async fn claim_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError> {
+        self.store.write(|tables| {
+            let due = tables.list_due_outbox(now, limit);
+            for event in &due {
+                tables.update_outbox(&event.id, |e| e.next_attempt_at = lease_until);
+            }
+            Ok(due)
+        })
+    }
+
+    async fn mark_dispatched(&self, event_id: String) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            tables.update_outbox(&event_id, |event| event.status = DISPATCHED.to_owned());
+            Ok(())
+        })
+    }
+
+    async fn mark_failed(
+        &self,
+        event_id: String,
+        error: String,
+        next_attempt_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            tables.update_outbox(&event_id, |event| {
+                event.status = if next_attempt_at.is_some() {
+                    PENDING.to_owned()
+                } else {
+                    DEAD.to_owned()
+                };
+                event.attempts += 1;
+                event.next_attempt_at =
+                    next_attempt_at.unwrap_or_else(|| chrono::Utc::now().naive_utc());
+                event.last_error = Some(error);
+            });
+            Ok(())
+        })
+    }
+}
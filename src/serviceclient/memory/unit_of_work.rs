@@ -0,0 +1,103 @@
+use super::{MemoryStore, Tables};
+use crate::domain::interface;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::outbox_repo::OutboxEventRecord;
+use crate::serviceclient::user_login_repo::UserLoginRecord;
+use crate::serviceclient::user_repo::UserRecord;
+use async_trait::async_trait;
+use std::cell::RefCell;
+
+// The repositories of a unit of work, over the copy of the tables made by MemoryStore::write
+struct Transaction {
+    tables: RefCell<Tables>,
+}
+
+impl interface::ITransaction for Transaction {
+    fn users(&self) -> &dyn interface::IUserRepositoryTx {
+        self
+    }
+
+    fn logins(&self) -> &dyn interface::IUserLoginRepositoryTx {
+        self
+    }
+
+    fn outbox(&self) -> &dyn interface::IOutboxRepositoryTx {
+        self
+    }
+}
+
+impl interface::IUserRepositoryTx for Transaction {
+    fn get_by_id(&self, user_id: &str) -> Result<Option<model::User>, DBConnectorError> {
+        Ok(self.tables.borrow().get_user(user_id))
+    }
+
+    fn save(&self, user: model::User) -> Result<(), DBConnectorError> {
+        self.tables
+            .borrow_mut()
+            .insert_user(UserRecord::from_model(user))
+    }
+
+    fn update(&self, user: model::User) -> Result<(), DBConnectorError> {
+        self.tables
+            .borrow_mut()
+            .update_user(UserRecord::from_model(user))
+    }
+}
+
+impl interface::IUserLoginRepositoryTx for Transaction {
+    fn get_by_user_id(&self, user_id: &str) -> Result<Option<model::Login>, DBConnectorError> {
+        Ok(self.tables.borrow().get_login(user_id))
+    }
+
+    fn save(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.tables
+            .borrow_mut()
+            .upsert_login(UserLoginRecord::from_model(login))
+    }
+
+    fn create(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.tables
+            .borrow_mut()
+            .insert_login(UserLoginRecord::from_model(login))
+    }
+
+    fn update(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.tables
+            .borrow_mut()
+            .update_login(UserLoginRecord::from_model(login))
+    }
+}
+
+impl interface::IOutboxRepositoryTx for Transaction {
+    fn append(&self, event: model::OutboxEvent) -> Result<(), DBConnectorError> {
+        self.tables
+            .borrow_mut()
+            .insert_outbox(OutboxEventRecord::from_model(event))
+    }
+}
+
+pub struct MemoryUnitOfWork {
+    store: MemoryStore,
+}
+
+impl MemoryUnitOfWork {
+    pub fn new(store: MemoryStore) -> MemoryUnitOfWork {
+        MemoryUnitOfWork { store: store }
+    }
+}
+
+#[async_trait]
+impl interface::IUnitOfWork for MemoryUnitOfWork {
+    async fn run(&self, work: interface::Work) -> Result<(), DBConnectorError> {
+        self.store.write(move |tables| {
+            let tx = Transaction {
+                tables: RefCell::new(std::mem::replace(tables, Tables::default())),
+            };
+            let result = work(&tx);
+            *tables = tx.tables.into_inner();
+
+            result
+        })
+    }
+}
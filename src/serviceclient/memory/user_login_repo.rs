@@ -0,0 +1,110 @@
+use super::MemoryStore;
+use crate::domain::interface::IUserLoginRepository;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::outbox_repo::OutboxEventRecord;
+use crate::serviceclient::user_login_repo::{UserEmailVerificationRecord, UserLoginRecord};
+use crate::serviceclient::user_repo::UserRecord;
+use async_trait::async_trait;
+
+pub struct MemoryUserLoginRepository {
+    store: MemoryStore,
+}
+
+impl MemoryUserLoginRepository {
+    pub fn new(store: MemoryStore) -> MemoryUserLoginRepository {
+        MemoryUserLoginRepository { store: store }
+    }
+}
+
+#[async_trait]
+impl IUserLoginRepository for MemoryUserLoginRepository {
+    async fn get_by_user_name(
+        &self,
+        user_name: String,
+    ) -> Result<Option<(model::Login, model::User)>, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.get_login_by_user_name(&user_name)))
+    }
+
+    async fn get_by_user_id(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::Login>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.get_login(&user_id)))
+    }
+
+    async fn save(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.upsert_login(UserLoginRecord::from_model(login)))
+    }
+
+    async fn create(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.insert_login(UserLoginRecord::from_model(login)))
+    }
+
+    async fn update(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.update_login(UserLoginRecord::from_model(login)))
+    }
+
+    async fn create_pending(
+        &self,
+        user: model::User,
+        login: model::Login,
+        verification: model::EmailVerification,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            tables.insert_user(UserRecord::from_model(user))?;
+            tables.insert_login(UserLoginRecord::from_model(login))?;
+            tables.insert_verification(UserEmailVerificationRecord::from_model(verification))?;
+            for event in events {
+                tables.insert_outbox(OutboxEventRecord::from_model(event))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn get_verification(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<model::EmailVerification>, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.get_verification(&token_hash)))
+    }
+
+    async fn complete_verification(
+        &self,
+        user_id: String,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            tables.complete_verification(&user_id)?;
+            for event in events {
+                tables.insert_outbox(OutboxEventRecord::from_model(event))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn renew_verification(
+        &self,
+        email: String,
+        token_hash: String,
+        expires_at: chrono::NaiveDateTime,
+    ) -> Result<bool, DBConnectorError> {
+        self.store.write(|tables| {
+            tables.renew_verification(&email, |user_id| UserEmailVerificationRecord {
+                user_id: user_id,
+                token_hash: token_hash,
+                expires_at: expires_at,
+            })
+        })
+    }
+}
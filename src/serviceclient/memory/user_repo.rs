@@ -0,0 +1,62 @@
+use super::MemoryStore;
+use crate::domain::interface::IUserRepository;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::user_repo::UserRecord;
+use async_trait::async_trait;
+
+pub struct MemoryUserRepository {
+    store: MemoryStore,
+}
+
+impl MemoryUserRepository {
+    pub fn new(store: MemoryStore) -> MemoryUserRepository {
+        MemoryUserRepository { store: store }
+    }
+}
+
+#[async_trait]
+impl IUserRepository for MemoryUserRepository {
+    async fn get_by_id(&self, user_id: String) -> Result<Option<model::User>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.get_user(&user_id)))
+    }
+
+    // there is no replica to lag behind
+    async fn get_by_id_from_primary(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::User>, DBConnectorError> {
+        self.get_by_id(user_id).await
+    }
+
+    async fn list(
+        &self,
+        query: model::UserListQuery,
+    ) -> Result<Vec<model::User>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.list_users(query)))
+    }
+
+    async fn count(&self, filter: model::UserFilter) -> Result<i64, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.filtered_users(&filter).len() as i64))
+    }
+
+    async fn save(&self, user: model::User) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.insert_user(UserRecord::from_model(user)))
+    }
+
+    async fn update(&self, user: model::User) -> Result<(), DBConnectorError> {
+        self.store
+            .write(|tables| tables.update_user(UserRecord::from_model(user)))
+    }
+
+    async fn delete(&self, user_id: String) -> Result<bool, DBConnectorError> {
+        self.store.write(|tables| Ok(tables.delete_user(&user_id)))
+    }
+
+    async fn purge(&self, user_id: String) -> Result<bool, DBConnectorError> {
+        self.store.write(|tables| Ok(tables.purge_user(&user_id)))
+    }
+}
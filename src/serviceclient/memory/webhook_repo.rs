@@ -0,0 +1,106 @@
+use super::MemoryStore;
+use crate::domain::interface::IWebhookRepository;
+use crate::domain::model;
+use crate::infra::DBConnectorError;
+use crate::serviceclient::webhook_repo::{WebhookDeliveryRecord, WebhookEndpointRecord};
+use async_trait::async_trait;
+
+pub struct MemoryWebhookRepository {
+    store: MemoryStore,
+}
+
+impl MemoryWebhookRepository {
+    pub fn new(store: MemoryStore) -> MemoryWebhookRepository {
+        MemoryWebhookRepository { store: store }
+    }
+}
+
+#[async_trait]
+impl IWebhookRepository for MemoryWebhookRepository {
+    async fn create_endpoint(
+        &self,
+        endpoint: model::WebhookEndpoint,
+    ) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            tables.insert_webhook_endpoint(WebhookEndpointRecord::from_model(endpoint))
+        })
+    }
+
+    async fn list_endpoints(&self) -> Result<Vec<model::WebhookEndpoint>, DBConnectorError> {
+        Ok(self.store.read(|tables| tables.list_webhook_endpoints()))
+    }
+
+    async fn get_endpoint(
+        &self,
+        endpoint_id: String,
+    ) -> Result<Option<model::WebhookEndpoint>, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.get_webhook_endpoint(&endpoint_id)))
+    }
+
+    async fn delete_endpoint(&self, endpoint_id: String) -> Result<bool, DBConnectorError> {
+        self.store
+            .write(|tables| Ok(tables.delete_webhook_endpoint(&endpoint_id)))
+    }
+
+    async fn enqueue(
+        &self,
+        deliveries: Vec<model::WebhookDelivery>,
+    ) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            for delivery in deliveries {
+                tables.enqueue_webhook_delivery(WebhookDeliveryRecord::from_model(delivery))?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn list_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(model::WebhookDelivery, model::WebhookEndpoint)>, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.list_due_webhook_deliveries(now, limit)))
+    }
+
+    async fn claim_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(model::WebhookDelivery, model::WebhookEndpoint)>, DBConnectorError> {
+        self.store
+            .write(|tables| Ok(tables.claim_due_webhook_deliveries(now, lease_until, limit)))
+    }
+
+    async fn list_deliveries(
+        &self,
+        query: model::WebhookDeliveryQuery,
+    ) -> Result<Vec<model::WebhookDelivery>, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.list_webhook_deliveries(query)))
+    }
+
+    async fn get_delivery(
+        &self,
+        delivery_id: String,
+    ) -> Result<Option<model::WebhookDelivery>, DBConnectorError> {
+        Ok(self
+            .store
+            .read(|tables| tables.get_webhook_delivery(&delivery_id)))
+    }
+
+    async fn update_delivery(
+        &self,
+        delivery: model::WebhookDelivery,
+    ) -> Result<(), DBConnectorError> {
+        self.store.write(|tables| {
+            tables.update_webhook_delivery(WebhookDeliveryRecord::from_model(delivery));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,247 @@
+use crate::domain::interface::{IOutboxRepository, IOutboxRepositoryTx};
+use crate::domain::model;
+use crate::infra::{traced, DBConnection, DBConnector, DBConnectorError};
+use crate::schema::*;
+use async_trait::async_trait;
+use diesel::dsl::*;
+use diesel::prelude::*;
+
+// outbox_events.status
+pub const PENDING: &str = "pending";
+pub const DISPATCHED: &str = "dispatched";
+pub const DEAD: &str = "dead";
+
+#[derive(Queryable, Insertable, Clone)]
+#[table_name = "outbox_events"]
+pub struct OutboxEventRecord {
+    pub id: String,
+    pub occurred_at: chrono::NaiveDateTime,
+    pub event_type: String,
+    // the DomainEvent as JSON
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub last_error: Option<String>,
+}
+
+impl OutboxEventRecord {
+    // Fails for an event this version cannot read
+    pub fn to_model(self) -> Result<model::OutboxEvent, serde_json::Error> {
+        Ok(model::OutboxEvent {
+            event: serde_json::from_str(&self.payload)?,
+            id: self.id,
+            occurred_at: self.occurred_at,
+            attempts: self.attempts,
+        })
+    }
+
+    pub fn from_model(event: model::OutboxEvent) -> Self {
+        OutboxEventRecord {
+            id: event.id,
+            occurred_at: event.occurred_at,
+            event_type: event.event.event_type().to_owned(),
+            payload: serde_json::to_string(&event.event).unwrap(),
+            status: PENDING.to_owned(),
+            attempts: event.attempts,
+            next_attempt_at: event.occurred_at,
+            last_error: None,
+        }
+    }
+}
+
+pub struct OutboxRepositoryTx<'a> {
+    conn: &'a DBConnection,
+}
+
+impl<'a> OutboxRepositoryTx<'a> {
+    pub fn new(conn: &'a DBConnection) -> OutboxRepositoryTx<'a> {
+        OutboxRepositoryTx { conn: conn }
+    }
+}
+
+impl<'a> IOutboxRepositoryTx for OutboxRepositoryTx<'a> {
+    fn append(&self, event: model::OutboxEvent) -> Result<(), DBConnectorError> {
+        traced(
+            "OutboxRepositoryTx::append",
+            insert_into(outbox_events::table).values(&OutboxEventRecord::from_model(event)),
+            |n| Some(*n),
+            |query| query.execute(self.conn),
+        )?;
+
+        Ok(())
+    }
+}
+
+pub struct OutboxRepository {
+    db: DBConnector,
+}
+
+impl OutboxRepository {
+    pub fn new(db: DBConnector) -> OutboxRepository {
+        OutboxRepository { db: db }
+    }
+
+    async fn readable(
+        &self,
+        records: Vec<OutboxEventRecord>,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError> {
+        let mut events = Vec::new();
+        for record in records {
+            let id = record.id.clone();
+            match record.to_model() {
+                Ok(event) => events.push(event),
+                // dead, rather than taking a place in every batch
+                Err(err) => {
+                    warn!("Giving up on the unreadable outbox event {}: {}", id, err);
+                    self.mark_failed(id, format!("unreadable payload: {}", err), None)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl IOutboxRepository for OutboxRepository {
+    async fn list_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError> {
+        // from the primary: a lagging replica would hand out events which were already dispatched
+        let records = self
+            .db
+            .caller("OutboxRepository::list_due")
+            .primary()
+            .load::<OutboxEventRecord, _>(
+                outbox_events::table
+                    .filter(outbox_events::status.eq(PENDING))
+                    .filter(outbox_events::next_attempt_at.le(now))
+                    .order(outbox_events::id.asc())
+                    .limit(limit),
+            )
+            .await?;
+
+        self.readable(records).await
+    }
+
+    async fn claim_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<model::OutboxEvent>, DBConnectorError> {
+        let records = self
+            .db
+            .caller("OutboxRepository::claim_due")
+            .run(move |conn| {
+                let name = "OutboxRepository::claim_due";
+                let due = traced(
+                    name,
+                    outbox_events::table
+                        .filter(outbox_events::status.eq(PENDING))
+                        .filter(outbox_events::next_attempt_at.le(now))
+                        .order(outbox_events::id.asc())
+                        .limit(limit),
+                    |v| Some(v.len()),
+                    |query| query.load::<OutboxEventRecord>(conn),
+                )?;
+
+                // guarded by the time read, which a concurrent claim has changed
+                let mut claimed = Vec::new();
+                for record in due {
+                    let updated = traced(
+                        name,
+                        update(
+                            outbox_events::table
+                                .filter(outbox_events::id.eq(&record.id))
+                                .filter(outbox_events::status.eq(PENDING))
+                                .filter(outbox_events::next_attempt_at.eq(record.next_attempt_at)),
+                        )
+                        .set(outbox_events::next_attempt_at.eq(lease_until)),
+                        |n| Some(*n),
+                        |query| query.execute(conn),
+                    )?;
+                    if updated == 1 {
+                        claimed.push(record);
+                    }
+                }
+
+                Ok(claimed)
+            })
+            .await?;
+
+        self.readable(records).await
+    }
+
+    async fn mark_dispatched(&self, event_id: String) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("OutboxRepository::mark_dispatched")
+            .execute(
+                update(outbox_events::table.filter(outbox_events::id.eq(event_id)))
+                    .set(outbox_events::status.eq(DISPATCHED)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        event_id: String,
+        error: String,
+        next_attempt_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<(), DBConnectorError> {
+        let status = if next_attempt_at.is_some() {
+            PENDING
+        } else {
+            DEAD
+        };
+        let next_attempt_at = next_attempt_at.unwrap_or_else(|| chrono::Utc::now().naive_utc());
+
+        self.db
+            .caller("OutboxRepository::mark_failed")
+            .execute(
+                update(outbox_events::table.filter(outbox_events::id.eq(event_id))).set((
+                    outbox_events::status.eq(status),
+                    outbox_events::attempts.eq(outbox_events::attempts + 1),
+                    outbox_events::next_attempt_at.eq(next_attempt_at),
+                    outbox_events::last_error.eq(error),
+                )),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[test]
+#[cfg_attr(not(feature = "sqlite"), ignore)]
+fn unreadable_events_should_be_dead() {
+    super::with_test_user(|db, user_id| async move {
+        let repository = OutboxRepository::new(db.clone());
+        let mut record = OutboxEventRecord::from_model(model::OutboxEvent::new(
+            model::DomainEvent::LoginEnabled { user_id: user_id },
+        ));
+        // e.g. written by a newer version
+        record.payload = "{\"type\":\"user_renamed\"}".to_owned();
+        let id = record.id.clone();
+        db.execute(insert_into(outbox_events::table).values::<OutboxEventRecord>(record))
+            .await
+            .unwrap();
+
+        let now = chrono::Utc::now().naive_utc();
+        let due = repository.list_due(now, 1000).await.unwrap();
+        assert!(due.iter().all(|e| e.id != id));
+
+        let dead = db
+            .first::<OutboxEventRecord, _>(outbox_events::table.filter(outbox_events::id.eq(id)))
+            .await
+            .unwrap();
+        assert_eq!(DEAD, dead.status);
+        assert!(dead.last_error.unwrap().starts_with("unreadable payload"));
+    });
+}
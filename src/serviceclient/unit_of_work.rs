@@ -0,0 +1,103 @@
+use super::outbox_repo::OutboxRepositoryTx;
+use super::user_login_repo::UserLoginRepositoryTx;
+use super::user_repo::UserRepositoryTx;
+use crate::domain::interface;
+use crate::infra::{DBConnection, DBConnector, DBConnectorError};
+use async_trait::async_trait;
+
+struct Transaction<'a> {
+    users: UserRepositoryTx<'a>,
+    logins: UserLoginRepositoryTx<'a>,
+    outbox: OutboxRepositoryTx<'a>,
+}
+
+impl<'a> Transaction<'a> {
+    fn new(conn: &'a DBConnection) -> Transaction<'a> {
+        Transaction {
+            users: UserRepositoryTx::new(conn),
+            logins: UserLoginRepositoryTx::new(conn),
+            outbox: OutboxRepositoryTx::new(conn),
+        }
+    }
+}
+
+impl<'a> interface::ITransaction for Transaction<'a> {
+    fn users(&self) -> &dyn interface::IUserRepositoryTx {
+        &self.users
+    }
+
+    fn logins(&self) -> &dyn interface::IUserLoginRepositoryTx {
+        &self.logins
+    }
+
+    fn outbox(&self) -> &dyn interface::IOutboxRepositoryTx {
+        &self.outbox
+    }
+}
+
+pub struct UnitOfWork {
+    db: DBConnector,
+}
+
+impl UnitOfWork {
+    pub fn new(db: DBConnector) -> UnitOfWork {
+        UnitOfWork { db: db }
+    }
+}
+
+#[async_trait]
+impl interface::IUnitOfWork for UnitOfWork {
+    async fn run(&self, work: interface::Work) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("UnitOfWork::run")
+            .transaction(move |conn| work(&Transaction::new(conn)))
+            .await
+    }
+}
+
+#[test]
+#[cfg_attr(not(feature = "sqlite"), ignore)]
+fn work_should_be_rolled_back_on_error() {
+    use crate::domain::interface::{
+        ITransaction, IUnitOfWork, IUserLoginRepositoryTx, IUserRepositoryTx,
+    };
+    use crate::domain::model;
+
+    super::with_test_user(|db, user_id| async move {
+        let unit_of_work = UnitOfWork::new(db.clone());
+        let login = |user_id: &str| model::Login {
+            user_id: user_id.to_owned(),
+            password_hash: "hash".to_owned(),
+            status: model::LoginUserStatus::Enabled,
+            version: 0,
+        };
+        let renamed = format!("{}-renamed", user_id);
+
+        let id = user_id.clone();
+        let result = unit_of_work
+            .run(Box::new(move |tx: &dyn ITransaction| {
+                let mut user = tx.users().get_by_id(&id)?.unwrap();
+                user.name = renamed;
+                tx.users().update(user)?;
+                tx.logins().create(login(&id))?;
+                // fails with UniqueViolation
+                tx.logins().create(login(&id))
+            }))
+            .await;
+        match result {
+            Err(DBConnectorError::UniqueViolation(_)) => (),
+            _ => panic!("expected a unique violation"),
+        }
+
+        let id = user_id.clone();
+        db.transaction(move |conn| {
+            let tx = Transaction::new(conn);
+            assert_eq!(id, tx.users.get_by_id(&id)?.unwrap().name);
+            assert!(tx.logins.get_by_user_id(&id)?.is_none());
+
+            Ok(())
+        })
+        .await
+        .unwrap();
+    });
+}
@@ -1,21 +1,24 @@
+use super::outbox_repo::OutboxRepositoryTx;
 use crate::domain::interface;
+use crate::domain::interface::{IOutboxRepositoryTx, IUserLoginRepositoryTx};
 use crate::domain::model;
-use crate::infra::{DBConnector, DBConnectorError};
+use crate::infra::{traced, DBConnection, DBConnector, DBConnectorError, OptionalResult};
 use crate::schema::*;
 use async_trait::async_trait;
 use diesel::dsl::*;
 use diesel::prelude::*;
 use serde::*;
 
-#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[derive(Queryable, Insertable, Serialize, Deserialize, Clone)]
 pub struct UserLoginRecord {
     pub user_id: String,
     pub password_hash: String,
     pub status: Option<String>,
+    pub version: i32,
 }
 
 impl UserLoginRecord {
-    fn to_model(self) -> model::Login {
+    pub fn to_model(self) -> model::Login {
         model::Login {
             user_id: self.user_id,
             password_hash: self.password_hash,
@@ -23,14 +26,42 @@ impl UserLoginRecord {
                 .status
                 .and_then(|r| serde_json::from_str(&r).ok())
                 .unwrap_or(model::LoginUserStatus::Enabled),
+            version: self.version,
         }
     }
 
-    fn from_model(login: model::Login) -> Self {
+    pub fn from_model(login: model::Login) -> Self {
         UserLoginRecord {
             user_id: login.user_id,
             password_hash: login.password_hash,
             status: serde_json::to_string(&login.status).ok(),
+            version: login.version,
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Clone)]
+#[table_name = "user_email_verifications"]
+pub struct UserEmailVerificationRecord {
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+impl UserEmailVerificationRecord {
+    pub fn to_model(self) -> model::EmailVerification {
+        model::EmailVerification {
+            user_id: self.user_id,
+            token_hash: self.token_hash,
+            expires_at: self.expires_at,
+        }
+    }
+
+    pub fn from_model(verification: model::EmailVerification) -> Self {
+        UserEmailVerificationRecord {
+            user_id: verification.user_id,
+            token_hash: verification.token_hash,
+            expires_at: verification.expires_at,
         }
     }
 }
@@ -45,43 +76,348 @@ impl UserLoginRepository {
     }
 }
 
+// binds: user_id, password_hash, status
+#[cfg(feature = "mysql")]
+const UPSERT_LOGIN: &str =
+    "INSERT INTO user_login_records (user_id, password_hash, status, version) \
+     VALUES (?, ?, ?, 0) \
+     ON DUPLICATE KEY UPDATE \
+     password_hash = VALUES(password_hash), \
+     status = VALUES(status), \
+     version = version + 1";
+
+#[cfg(feature = "postgres")]
+const UPSERT_LOGIN: &str =
+    "INSERT INTO user_login_records (user_id, password_hash, status, version) \
+     VALUES ($1, $2, $3, 0) \
+     ON CONFLICT (user_id) DO UPDATE SET \
+     password_hash = EXCLUDED.password_hash, \
+     status = EXCLUDED.status, \
+     version = user_login_records.version + 1";
+
+// needs SQLite 3.24 or later
+#[cfg(feature = "sqlite")]
+const UPSERT_LOGIN: &str =
+    "INSERT INTO user_login_records (user_id, password_hash, status, version) \
+     VALUES (?, ?, ?, 0) \
+     ON CONFLICT (user_id) DO UPDATE SET \
+     password_hash = excluded.password_hash, \
+     status = excluded.status, \
+     version = version + 1";
+
+pub struct UserLoginRepositoryTx<'a> {
+    conn: &'a DBConnection,
+}
+
+impl<'a> UserLoginRepositoryTx<'a> {
+    pub fn new(conn: &'a DBConnection) -> UserLoginRepositoryTx<'a> {
+        UserLoginRepositoryTx { conn: conn }
+    }
+}
+
+impl<'a> interface::IUserLoginRepositoryTx for UserLoginRepositoryTx<'a> {
+    fn get_by_user_id(&self, user_id: &str) -> Result<Option<model::Login>, DBConnectorError> {
+        let record = traced(
+            "UserLoginRepositoryTx::get_by_user_id",
+            user_login_records::table.find(user_id),
+            |_| Some(1),
+            |query| query.first::<UserLoginRecord>(self.conn),
+        )
+        .optional()?;
+
+        Ok(record.map(|r| r.to_model()))
+    }
+
+    fn save(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        use diesel::sql_types::{Nullable, Varchar};
+        let record = UserLoginRecord::from_model(login);
+
+        traced(
+            "UserLoginRepositoryTx::save",
+            diesel::sql_query(UPSERT_LOGIN)
+                .bind::<Varchar, _>(record.user_id)
+                .bind::<Varchar, _>(record.password_hash)
+                .bind::<Nullable<Varchar>, _>(record.status),
+            |n| Some(*n),
+            |query| query.execute(self.conn),
+        )?;
+
+        Ok(())
+    }
+
+    fn create(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        traced(
+            "UserLoginRepositoryTx::create",
+            insert_into(user_login_records::table).values(&UserLoginRecord::from_model(login)),
+            |n| Some(*n),
+            |query| query.execute(self.conn),
+        )?;
+
+        Ok(())
+    }
+
+    fn update(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        let record = UserLoginRecord::from_model(login);
+
+        let rows = traced(
+            "UserLoginRepositoryTx::update",
+            update(
+                user_login_records::table
+                    .filter(user_login_records::user_id.eq(&record.user_id))
+                    .filter(user_login_records::version.eq(record.version)),
+            )
+            .set((
+                user_login_records::password_hash.eq(&record.password_hash),
+                user_login_records::status.eq(&record.status),
+                user_login_records::version.eq(user_login_records::version + 1),
+            )),
+            |n| Some(*n),
+            |query| query.execute(self.conn),
+        )?;
+
+        if rows > 0 {
+            Ok(())
+        } else if traced(
+            "UserLoginRepositoryTx::update",
+            select(exists(user_login_records::table.find(&record.user_id))),
+            |_| Some(1),
+            |query| query.get_result::<bool>(self.conn),
+        )? {
+            Err(DBConnectorError::VersionConflict)
+        } else {
+            Err(DBConnectorError::NotFound)
+        }
+    }
+}
+
 #[async_trait]
 impl interface::IUserLoginRepository for UserLoginRepository {
-    async fn save(&self, user: model::Login) -> Result<(), DBConnectorError> {
+    async fn save(&self, login: model::Login) -> Result<(), DBConnectorError> {
         self.db
-            .execute(
-                insert_into(user_login_records::table)
-                    .values::<UserLoginRecord>(UserLoginRecord::from_model(user)),
-            )
-            .await?;
+            .caller("UserLoginRepository::save")
+            .transaction(move |conn| UserLoginRepositoryTx::new(conn).save(login))
+            .await
+    }
 
-        Ok(())
+    async fn create(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("UserLoginRepository::create")
+            .transaction(move |conn| UserLoginRepositoryTx::new(conn).create(login))
+            .await
+    }
+
+    async fn update(&self, login: model::Login) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("UserLoginRepository::update")
+            .transaction(move |conn| UserLoginRepositoryTx::new(conn).update(login))
+            .await
     }
 
     async fn get_by_user_name(
         &self,
         user_name: String,
-    ) -> Result<(model::Login, model::User), DBConnectorError> {
-        let (user, login) = self
+    ) -> Result<Option<(model::Login, model::User)>, DBConnectorError> {
+        // logins are always checked against the primary, e.g. right after a password change
+        let record = self
             .db
+            .caller("UserLoginRepository::get_by_user_name")
+            .primary()
             .first::<(super::user_repo::UserRecord, UserLoginRecord), _>(
                 user_records::table
                     .inner_join(user_login_records::table)
-                    .filter(user_records::name.eq(user_name)),
+                    .filter(user_records::name.eq(user_name))
+                    .filter(user_records::deleted_at.is_null()),
             )
-            .await?;
+            .await
+            .optional()?;
 
-        Ok((login.to_model(), user.to_model()))
+        Ok(record.map(|(user, login)| (login.to_model(), user.to_model())))
     }
 
-    async fn get_by_user_id(&self, user_id: String) -> Result<model::Login, DBConnectorError> {
+    async fn get_by_user_id(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::Login>, DBConnectorError> {
         let record = self
             .db
+            .caller("UserLoginRepository::get_by_user_id")
+            .primary()
             .first::<UserLoginRecord, _>(
                 user_login_records::table.filter(user_login_records::user_id.eq(user_id)),
             )
-            .await?;
+            .await
+            .optional()?;
+
+        Ok(record.map(|r| r.to_model()))
+    }
+
+    async fn create_pending(
+        &self,
+        user: model::User,
+        login: model::Login,
+        verification: model::EmailVerification,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError> {
+        let user = super::user_repo::UserRecord::from_model(user);
+        let login = UserLoginRecord::from_model(login);
+        let verification = UserEmailVerificationRecord::from_model(verification);
+
+        self.db
+            .caller("UserLoginRepository::create_pending")
+            .transaction(move |conn| {
+                let name = "UserLoginRepository::create_pending";
+                traced(
+                    name,
+                    insert_into(user_records::table).values(&user),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
+                traced(
+                    name,
+                    insert_into(user_login_records::table).values(&login),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
+                traced(
+                    name,
+                    insert_into(user_email_verifications::table).values(&verification),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
+
+                let outbox = OutboxRepositoryTx::new(conn);
+                for event in events {
+                    outbox.append(event)?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_verification(
+        &self,
+        token_hash: String,
+    ) -> Result<Option<model::EmailVerification>, DBConnectorError> {
+        // the verification link may be opened right after signing up
+        let record = self
+            .db
+            .caller("UserLoginRepository::get_verification")
+            .primary()
+            .first::<UserEmailVerificationRecord, _>(
+                user_email_verifications::table
+                    .filter(user_email_verifications::token_hash.eq(token_hash)),
+            )
+            .await
+            .optional()?;
+
+        Ok(record.map(|r| r.to_model()))
+    }
+
+    async fn complete_verification(
+        &self,
+        user_id: String,
+        events: Vec<model::OutboxEvent>,
+    ) -> Result<(), DBConnectorError> {
+        let enabled = serde_json::to_string(&model::LoginUserStatus::Enabled).ok();
+        let pending = serde_json::to_string(&model::LoginUserStatus::PendingVerification).ok();
+
+        self.db
+            .caller("UserLoginRepository::complete_verification")
+            .transaction(move |conn| {
+                let name = "UserLoginRepository::complete_verification";
+                // a concurrent verification waits for this row, then finds it gone
+                let consumed = traced(
+                    name,
+                    delete(user_email_verifications::table.find(&user_id)),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
+                if consumed == 0 {
+                    return Err(DBConnectorError::NotFound);
+                }
+                let updated = traced(
+                    name,
+                    update(
+                        user_login_records::table
+                            .filter(user_login_records::user_id.eq(&user_id))
+                            .filter(user_login_records::status.eq(&pending))
+                            .filter(exists(
+                                user_records::table
+                                    .filter(user_records::id.eq(&user_id))
+                                    .filter(user_records::deleted_at.is_null()),
+                            )),
+                    )
+                    .set((
+                        user_login_records::status.eq(&enabled),
+                        user_login_records::version.eq(user_login_records::version + 1),
+                    )),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
+                if updated == 0 {
+                    return Err(DBConnectorError::NotFound);
+                }
+
+                let outbox = OutboxRepositoryTx::new(conn);
+                for event in events {
+                    outbox.append(event)?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn renew_verification(
+        &self,
+        email: String,
+        token_hash: String,
+        expires_at: chrono::NaiveDateTime,
+    ) -> Result<bool, DBConnectorError> {
+        let pending = serde_json::to_string(&model::LoginUserStatus::PendingVerification).ok();
+
+        self.db
+            .caller("UserLoginRepository::renew_verification")
+            .transaction(move |conn| {
+                let name = "UserLoginRepository::renew_verification";
+                let user_ids = traced(
+                    name,
+                    user_records::table
+                        .inner_join(user_login_records::table)
+                        .filter(user_records::email.eq(&email))
+                        .filter(user_records::deleted_at.is_null())
+                        .filter(user_login_records::status.eq(&pending))
+                        .select(user_records::id),
+                    |v| Some(v.len()),
+                    |query| query.load::<String>(conn),
+                )?;
+                let user_id = match user_ids.into_iter().next() {
+                    Some(user_id) => user_id,
+                    None => return Ok(false),
+                };
+
+                traced(
+                    name,
+                    delete(user_email_verifications::table.find(&user_id)),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
+                traced(
+                    name,
+                    insert_into(user_email_verifications::table).values(
+                        &UserEmailVerificationRecord {
+                            user_id: user_id,
+                            token_hash: token_hash,
+                            expires_at: expires_at,
+                        },
+                    ),
+                    |n| Some(*n),
+                    |query| query.execute(conn),
+                )?;
 
-        Ok(record.to_model())
+                Ok(true)
+            })
+            .await
     }
 }
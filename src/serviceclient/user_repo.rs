@@ -1,18 +1,24 @@
-use crate::domain::interface::IUserRepository;
+use crate::domain::interface::{IUserRepository, IUserRepositoryTx};
 use crate::domain::model;
-use crate::infra::{DBConnector, DBConnectorError};
+use crate::infra::{
+    traced, DBBackend, DBConnection, DBConnector, DBConnectorError, OptionalResult,
+};
 use crate::schema::*;
 use async_trait::async_trait;
 use diesel::dsl::*;
 use diesel::prelude::*;
 use serde::*;
+use std::time::Duration;
 
-#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[derive(Queryable, Insertable, Serialize, Deserialize, Clone)]
 pub struct UserRecord {
     pub id: String,
     pub name: String,
     pub display_name: String,
     pub role: Option<String>,
+    // soft delete; rows with deleted_at are invisible to everything but purge
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub email: Option<String>,
 }
 
 impl UserRecord {
@@ -25,6 +31,7 @@ impl UserRecord {
                 .role
                 .map(|r| model::Role::new_from_str(&r))
                 .unwrap_or(model::Role::Unknown),
+            email: self.email,
         }
     }
 
@@ -34,10 +41,88 @@ impl UserRecord {
             name: user.name,
             display_name: user.display_name,
             role: Some(user.role.as_string()),
+            deleted_at: None,
+            email: user.email,
         }
     }
 }
 
+// counting scans every filtered row, which must not hold an executor thread for long
+const COUNT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// for `LIKE ... ESCAPE '\'` (SQLite has no default escape character)
+fn escape_like(raw: &str) -> String {
+    raw.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn filtered<'a>(filter: model::UserFilter) -> user_records::BoxedQuery<'a, DBBackend> {
+    let mut query = user_records::table
+        .filter(user_records::deleted_at.is_null())
+        .into_boxed();
+
+    if let Some(role) = filter.role {
+        query = if role == model::Role::Unknown {
+            query.filter(
+                user_records::role
+                    .is_null()
+                    .or(user_records::role.eq(role.as_string())),
+            )
+        } else {
+            query.filter(user_records::role.eq(role.as_string()))
+        };
+    }
+
+    if let Some(prefix) = filter.name_prefix {
+        let pattern = format!("{}%", escape_like(&prefix));
+        query = query.filter(
+            user_records::name
+                .like(pattern.clone())
+                .escape('\\')
+                .or(user_records::display_name.like(pattern).escape('\\')),
+        );
+    }
+
+    query
+}
+
+fn paginated<'a>(query: model::UserListQuery) -> user_records::BoxedQuery<'a, DBBackend> {
+    use model::UserSort::*;
+
+    let mut q = filtered(query.filter);
+
+    if let Some(cursor) = query.cursor {
+        q = match query.sort {
+            IdAsc => q.filter(user_records::id.gt(cursor.id)),
+            IdDesc => q.filter(user_records::id.lt(cursor.id)),
+            NameAsc => q.filter(
+                user_records::name
+                    .gt(cursor.name.clone())
+                    .or(user_records::name
+                        .eq(cursor.name)
+                        .and(user_records::id.gt(cursor.id))),
+            ),
+            NameDesc => q.filter(
+                user_records::name
+                    .lt(cursor.name.clone())
+                    .or(user_records::name
+                        .eq(cursor.name)
+                        .and(user_records::id.lt(cursor.id))),
+            ),
+        };
+    }
+
+    q = match query.sort {
+        IdAsc => q.order(user_records::id.asc()),
+        IdDesc => q.order(user_records::id.desc()),
+        NameAsc => q.order((user_records::name.asc(), user_records::id.asc())),
+        NameDesc => q.order((user_records::name.desc(), user_records::id.desc())),
+    };
+
+    q.limit(query.limit)
+}
+
 pub struct UserRepository {
     db: DBConnector,
 }
@@ -48,15 +133,120 @@ impl UserRepository {
     }
 }
 
+pub struct UserRepositoryTx<'a> {
+    conn: &'a DBConnection,
+}
+
+impl<'a> UserRepositoryTx<'a> {
+    pub fn new(conn: &'a DBConnection) -> UserRepositoryTx<'a> {
+        UserRepositoryTx { conn: conn }
+    }
+}
+
+impl<'a> IUserRepositoryTx for UserRepositoryTx<'a> {
+    fn get_by_id(&self, user_id: &str) -> Result<Option<model::User>, DBConnectorError> {
+        let user = traced(
+            "UserRepositoryTx::get_by_id",
+            user_records::table
+                .filter(user_records::id.eq(user_id))
+                .filter(user_records::deleted_at.is_null()),
+            |_| Some(1),
+            |query| query.first::<UserRecord>(self.conn),
+        )
+        .optional()?;
+
+        Ok(user.map(|u| u.to_model()))
+    }
+
+    fn save(&self, user: model::User) -> Result<(), DBConnectorError> {
+        traced(
+            "UserRepositoryTx::save",
+            insert_into(user_records::table).values(&UserRecord::from_model(user)),
+            |n| Some(*n),
+            |query| query.execute(self.conn),
+        )?;
+
+        Ok(())
+    }
+
+    fn update(&self, user: model::User) -> Result<(), DBConnectorError> {
+        let record = UserRecord::from_model(user);
+        traced(
+            "UserRepositoryTx::update",
+            update(
+                user_records::table
+                    .filter(user_records::id.eq(&record.id))
+                    .filter(user_records::deleted_at.is_null()),
+            )
+            .set((
+                user_records::name.eq(&record.name),
+                user_records::display_name.eq(&record.display_name),
+                user_records::role.eq(&record.role),
+                user_records::email.eq(&record.email),
+            )),
+            |n| Some(*n),
+            |query| query.execute(self.conn),
+        )?;
+
+        Ok(())
+    }
+}
+
+async fn find_by_id(
+    db: DBConnector,
+    user_id: String,
+) -> Result<Option<model::User>, DBConnectorError> {
+    let user = db
+        .first::<UserRecord, _>(
+            user_records::table
+                .filter(user_records::id.eq(user_id))
+                .filter(user_records::deleted_at.is_null()),
+        )
+        .await
+        .optional()?;
+
+    Ok(user.map(|u| u.to_model()))
+}
+
 #[async_trait]
 impl IUserRepository for UserRepository {
-    async fn list(&self) -> Result<Vec<model::User>, DBConnectorError> {
-        let us = self.db.load::<UserRecord, _>(user_records::table).await?;
+    async fn list(
+        &self,
+        query: model::UserListQuery,
+    ) -> Result<Vec<model::User>, DBConnectorError> {
+        let us = self
+            .db
+            .caller("UserRepository::list")
+            .run_read_only(move |conn| {
+                traced(
+                    "UserRepository::list",
+                    paginated(query),
+                    |v| Some(v.len()),
+                    |query| query.load::<UserRecord>(conn),
+                )
+            })
+            .await?;
         Ok(us.into_iter().map(|r| r.to_model()).collect())
     }
 
+    async fn count(&self, filter: model::UserFilter) -> Result<i64, DBConnectorError> {
+        self.db
+            .caller("UserRepository::count")
+            .timeout(COUNT_TIMEOUT)
+            .run_read_only(move |conn| {
+                traced(
+                    "UserRepository::count",
+                    filtered(filter).count(),
+                    |_| Some(1),
+                    |query| query.get_result::<i64>(conn),
+                )
+            })
+            .await
+    }
+
     async fn save(&self, user: model::User) -> Result<(), DBConnectorError> {
         self.db
+            .caller("UserRepository::save")
             .execute(
                 insert_into(user_records::table).values::<UserRecord>(UserRecord::from_model(user)),
             )
@@ -65,12 +255,128 @@ impl IUserRepository for UserRepository {
         Ok(())
     }
 
-    async fn get_by_id(&self, user_id: String) -> Result<model::User, DBConnectorError> {
-        let user = self
+    async fn get_by_id(&self, user_id: String) -> Result<Option<model::User>, DBConnectorError> {
+        find_by_id(self.db.caller("UserRepository::get_by_id"), user_id).await
+    }
+
+    async fn get_by_id_from_primary(
+        &self,
+        user_id: String,
+    ) -> Result<Option<model::User>, DBConnectorError> {
+        find_by_id(
+            self.db
+                .caller("UserRepository::get_by_id_from_primary")
+                .primary(),
+            user_id,
+        )
+        .await
+    }
+
+    async fn update(&self, user: model::User) -> Result<(), DBConnectorError> {
+        let record = UserRecord::from_model(user);
+        self.db
+            .caller("UserRepository::update")
+            .execute(
+                update(
+                    user_records::table
+                        .filter(user_records::id.eq(record.id))
+                        .filter(user_records::deleted_at.is_null()),
+                )
+                .set((
+                    user_records::name.eq(record.name),
+                    user_records::display_name.eq(record.display_name),
+                    user_records::role.eq(record.role),
+                    user_records::email.eq(record.email),
+                )),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: String) -> Result<bool, DBConnectorError> {
+        let rows = self
             .db
-            .first::<UserRecord, _>(user_records::table.filter(user_records::id.eq(user_id)))
+            .caller("UserRepository::delete")
+            .execute(
+                update(
+                    user_records::table
+                        .filter(user_records::id.eq(user_id))
+                        .filter(user_records::deleted_at.is_null()),
+                )
+                .set(user_records::deleted_at.eq(chrono::Utc::now().naive_utc())),
+            )
             .await?;
 
-        Ok(user.to_model())
+        Ok(rows > 0)
+    }
+
+    // user_login_records are removed by `on delete cascade`
+    async fn purge(&self, user_id: String) -> Result<bool, DBConnectorError> {
+        let rows = self
+            .db
+            .caller("UserRepository::purge")
+            .execute(delete(
+                user_records::table.filter(user_records::id.eq(user_id)),
+            ))
+            .await?;
+
+        Ok(rows > 0)
+    }
+}
+
+#[test]
+#[cfg_attr(not(feature = "sqlite"), ignore)]
+fn execute_should_send_bind_values() {
+    super::with_test_user(|db, user_id| async move {
+        let repository = UserRepository::new(db.clone());
+        let id = ulid::Ulid::new().to_string();
+        // would break the statement if it were inlined into the SQL
+        let name = format!("O'Brien \\ {}", id);
+
+        repository
+            .save(model::User {
+                id: id.clone(),
+                name: name.clone(),
+                display_name: "test".to_owned(),
+                role: model::Role::User,
+                email: None,
+            })
+            .await
+            .unwrap();
+
+        let user = repository.get_by_id(id.clone()).await.unwrap().unwrap();
+        assert_eq!(name, user.name);
+        assert!(repository.delete(id).await.unwrap());
+        assert!(repository.purge(user_id).await.unwrap());
+    });
+}
+
+#[test]
+#[cfg_attr(not(feature = "sqlite"), ignore)]
+fn sql_load_should_read_rows_by_name() {
+    use diesel::sql_types::Varchar;
+
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Varchar"]
+        name: String,
     }
+
+    #[cfg(not(feature = "postgres"))]
+    const QUERY: &str = "SELECT name FROM user_records WHERE id = ?";
+    #[cfg(feature = "postgres")]
+    const QUERY: &str = "SELECT name FROM user_records WHERE id = $1";
+
+    super::with_test_user(|db, user_id| async move {
+        let rows = db
+            .sql_load::<Row, _>(diesel::sql_query(QUERY).bind::<Varchar, _>(user_id.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![user_id],
+            rows.into_iter().map(|r| r.name).collect::<Vec<_>>()
+        );
+    });
 }
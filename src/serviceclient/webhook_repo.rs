@@ -0,0 +1,368 @@
+use crate::domain::interface::IWebhookRepository;
+use crate::domain::model;
+use crate::infra::{traced, DBBackend, DBConnector, DBConnectorError};
+use crate::schema::*;
+use async_trait::async_trait;
+use diesel::dsl::*;
+use diesel::prelude::*;
+
+#[derive(Queryable, Insertable, Clone)]
+#[table_name = "webhook_endpoints"]
+pub struct WebhookEndpointRecord {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    // comma separated
+    pub event_types: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl WebhookEndpointRecord {
+    pub fn to_model(self) -> model::WebhookEndpoint {
+        model::WebhookEndpoint {
+            id: self.id,
+            url: self.url,
+            secret: self.secret,
+            event_types: self
+                .event_types
+                .split(',')
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_owned())
+                .collect(),
+            created_at: self.created_at,
+        }
+    }
+
+    pub fn from_model(endpoint: model::WebhookEndpoint) -> Self {
+        WebhookEndpointRecord {
+            id: endpoint.id,
+            url: endpoint.url,
+            secret: endpoint.secret,
+            event_types: endpoint.event_types.join(","),
+            created_at: endpoint.created_at,
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Clone)]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDeliveryRecord {
+    pub id: String,
+    pub endpoint_id: String,
+    pub event_id: String,
+    pub event_type: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl WebhookDeliveryRecord {
+    pub fn to_model(self) -> model::WebhookDelivery {
+        model::WebhookDelivery {
+            id: self.id,
+            endpoint_id: self.endpoint_id,
+            event_id: self.event_id,
+            event_type: self.event_type,
+            payload: self.payload,
+            // a status written by a newer version is left alone
+            status: model::WebhookDeliveryStatus::new_from_str(&self.status)
+                .unwrap_or(model::WebhookDeliveryStatus::Dead),
+            attempts: self.attempts,
+            next_attempt_at: self.next_attempt_at,
+            last_status_code: self.last_status_code,
+            last_error: self.last_error,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+
+    pub fn from_model(delivery: model::WebhookDelivery) -> Self {
+        WebhookDeliveryRecord {
+            id: delivery.id,
+            endpoint_id: delivery.endpoint_id,
+            event_id: delivery.event_id,
+            event_type: delivery.event_type,
+            payload: delivery.payload,
+            status: delivery.status.as_string(),
+            attempts: delivery.attempts,
+            next_attempt_at: delivery.next_attempt_at,
+            last_status_code: delivery.last_status_code,
+            last_error: delivery.last_error,
+            created_at: delivery.created_at,
+            updated_at: delivery.updated_at,
+        }
+    }
+}
+
+fn paginated<'a>(
+    query: model::WebhookDeliveryQuery,
+) -> webhook_deliveries::BoxedQuery<'a, DBBackend> {
+    let mut q = webhook_deliveries::table
+        .filter(webhook_deliveries::endpoint_id.eq(query.endpoint_id))
+        .into_boxed();
+
+    if let Some(status) = query.status {
+        q = q.filter(webhook_deliveries::status.eq(status.as_string()));
+    }
+    if let Some(before_id) = query.before_id {
+        q = q.filter(webhook_deliveries::id.lt(before_id));
+    }
+
+    q.order(webhook_deliveries::id.desc()).limit(query.limit)
+}
+
+pub struct WebhookRepository {
+    db: DBConnector,
+}
+
+impl WebhookRepository {
+    pub fn new(db: DBConnector) -> WebhookRepository {
+        WebhookRepository { db: db }
+    }
+}
+
+#[async_trait]
+impl IWebhookRepository for WebhookRepository {
+    async fn create_endpoint(
+        &self,
+        endpoint: model::WebhookEndpoint,
+    ) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("WebhookRepository::create_endpoint")
+            .execute(
+                insert_into(webhook_endpoints::table)
+                    .values::<WebhookEndpointRecord>(WebhookEndpointRecord::from_model(endpoint)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list_endpoints(&self) -> Result<Vec<model::WebhookEndpoint>, DBConnectorError> {
+        let records = self
+            .db
+            .caller("WebhookRepository::list_endpoints")
+            .load::<WebhookEndpointRecord, _>(
+                webhook_endpoints::table.order(webhook_endpoints::id.asc()),
+            )
+            .await?;
+
+        Ok(records.into_iter().map(|r| r.to_model()).collect())
+    }
+
+    async fn get_endpoint(
+        &self,
+        endpoint_id: String,
+    ) -> Result<Option<model::WebhookEndpoint>, DBConnectorError> {
+        let endpoint = self
+            .db
+            .caller("WebhookRepository::get_endpoint")
+            .first::<WebhookEndpointRecord, _>(
+                webhook_endpoints::table.filter(webhook_endpoints::id.eq(endpoint_id)),
+            )
+            .await
+            .optional()?;
+
+        Ok(endpoint.map(|e| e.to_model()))
+    }
+
+    async fn delete_endpoint(&self, endpoint_id: String) -> Result<bool, DBConnectorError> {
+        let rows = self
+            .db
+            .caller("WebhookRepository::delete_endpoint")
+            .execute(delete(
+                webhook_endpoints::table.filter(webhook_endpoints::id.eq(endpoint_id)),
+            ))
+            .await?;
+
+        Ok(rows > 0)
+    }
+
+    async fn enqueue(
+        &self,
+        deliveries: Vec<model::WebhookDelivery>,
+    ) -> Result<(), DBConnectorError> {
+        self.db
+            .caller("WebhookRepository::enqueue")
+            .transaction(move |conn| {
+                for delivery in deliveries {
+                    let queued = traced(
+                        "WebhookRepository::enqueue",
+                        select(exists(
+                            webhook_deliveries::table
+                                .filter(webhook_deliveries::endpoint_id.eq(&delivery.endpoint_id))
+                                .filter(webhook_deliveries::event_id.eq(&delivery.event_id)),
+                        )),
+                        |_| Some(1),
+                        |query| query.get_result::<bool>(conn),
+                    )?;
+
+                    if !queued {
+                        traced(
+                            "WebhookRepository::enqueue",
+                            insert_into(webhook_deliveries::table)
+                                .values(&WebhookDeliveryRecord::from_model(delivery)),
+                            |n| Some(*n),
+                            |query| query.execute(conn),
+                        )?;
+                    }
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn list_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(model::WebhookDelivery, model::WebhookEndpoint)>, DBConnectorError> {
+        // from the primary: a lagging replica would hand out deliveries which already succeeded
+        let records = self
+            .db
+            .caller("WebhookRepository::list_due")
+            .primary()
+            .load::<(WebhookDeliveryRecord, WebhookEndpointRecord), _>(
+                webhook_deliveries::table
+                    .inner_join(webhook_endpoints::table)
+                    .filter(
+                        webhook_deliveries::status
+                            .eq(model::WebhookDeliveryStatus::Pending.as_string()),
+                    )
+                    .filter(webhook_deliveries::next_attempt_at.le(now))
+                    .order(webhook_deliveries::id.asc())
+                    .limit(limit),
+            )
+            .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|(delivery, endpoint)| (delivery.to_model(), endpoint.to_model()))
+            .collect())
+    }
+
+    async fn claim_due(
+        &self,
+        now: chrono::NaiveDateTime,
+        lease_until: chrono::NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(model::WebhookDelivery, model::WebhookEndpoint)>, DBConnectorError> {
+        let pending = model::WebhookDeliveryStatus::Pending.as_string();
+        let records = self
+            .db
+            .caller("WebhookRepository::claim_due")
+            .run(move |conn| {
+                let name = "WebhookRepository::claim_due";
+                let due = traced(
+                    name,
+                    webhook_deliveries::table
+                        .inner_join(webhook_endpoints::table)
+                        .filter(webhook_deliveries::status.eq(&pending))
+                        .filter(webhook_deliveries::next_attempt_at.le(now))
+                        .order(webhook_deliveries::id.asc())
+                        .limit(limit),
+                    |v| Some(v.len()),
+                    |query| query.load::<(WebhookDeliveryRecord, WebhookEndpointRecord)>(conn),
+                )?;
+
+                // guarded by the time read, which a concurrent claim has changed
+                let mut claimed = Vec::new();
+                for (delivery, endpoint) in due {
+                    let updated = traced(
+                        name,
+                        update(
+                            webhook_deliveries::table
+                                .filter(webhook_deliveries::id.eq(&delivery.id))
+                                .filter(webhook_deliveries::status.eq(&pending))
+                                .filter(
+                                    webhook_deliveries::next_attempt_at
+                                        .eq(delivery.next_attempt_at),
+                                ),
+                        )
+                        .set(webhook_deliveries::next_attempt_at.eq(lease_until)),
+                        |n| Some(*n),
+                        |query| query.execute(conn),
+                    )?;
+                    if updated == 1 {
+                        claimed.push((delivery, endpoint));
+                    }
+                }
+
+                Ok(claimed)
+            })
+            .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|(delivery, endpoint)| (delivery.to_model(), endpoint.to_model()))
+            .collect())
+    }
+
+    async fn list_deliveries(
+        &self,
+        query: model::WebhookDeliveryQuery,
+    ) -> Result<Vec<model::WebhookDelivery>, DBConnectorError> {
+        let records = self
+            .db
+            .caller("WebhookRepository::list_deliveries")
+            .run_read_only(move |conn| {
+                traced(
+                    "WebhookRepository::list_deliveries",
+                    paginated(query),
+                    |v| Some(v.len()),
+                    |query| query.load::<WebhookDeliveryRecord>(conn),
+                )
+            })
+            .await?;
+
+        Ok(records.into_iter().map(|r| r.to_model()).collect())
+    }
+
+    async fn get_delivery(
+        &self,
+        delivery_id: String,
+    ) -> Result<Option<model::WebhookDelivery>, DBConnectorError> {
+        let delivery = self
+            .db
+            .caller("WebhookRepository::get_delivery")
+            .primary()
+            .first::<WebhookDeliveryRecord, _>(
+                webhook_deliveries::table.filter(webhook_deliveries::id.eq(delivery_id)),
+            )
+            .await
+            .optional()?;
+
+        Ok(delivery.map(|d| d.to_model()))
+    }
+
+    async fn update_delivery(
+        &self,
+        delivery: model::WebhookDelivery,
+    ) -> Result<(), DBConnectorError> {
+        let record = WebhookDeliveryRecord::from_model(delivery);
+
+        self.db
+            .caller("WebhookRepository::update_delivery")
+            .execute(
+                update(webhook_deliveries::table.filter(webhook_deliveries::id.eq(record.id))).set(
+                    (
+                        webhook_deliveries::status.eq(record.status),
+                        webhook_deliveries::attempts.eq(record.attempts),
+                        webhook_deliveries::next_attempt_at.eq(record.next_attempt_at),
+                        webhook_deliveries::last_status_code.eq(record.last_status_code),
+                        webhook_deliveries::last_error.eq(record.last_error),
+                        webhook_deliveries::updated_at.eq(record.updated_at),
+                    ),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,14 +1,24 @@
 use crate::async_await;
 use crate::domain::model;
 use crate::error::ServiceError;
+use crate::infra;
 use crate::initializer;
+use crate::serviceclient;
 use actix_http::Response;
-use actix_web::{error, web, HttpResponse};
+use actix_web::{web, HttpResponse};
 use futures01::stream::Stream;
 
+#[cfg(test)]
+mod e2e;
+mod tracing;
+
+pub use self::tracing::Tracing;
+
 #[derive(Clone)]
 pub struct WebContext {
     pub app: initializer::AppContext,
+    // only for the private listener
+    pub internal_secret: Option<String>,
 }
 
 impl WebContext {
@@ -19,18 +29,33 @@ impl WebContext {
             .map(|v| v.to_owned())
     }
 
+    fn authorize_internal(&self, req: &web::HttpRequest) -> Result<(), ServiceError> {
+        let secret = match &self.internal_secret {
+            Some(secret) => secret,
+            None => return Ok(()),
+        };
+
+        let given = req
+            .headers()
+            .get("X-Internal-Secret")
+            .map(|v| v.as_bytes())
+            .unwrap_or(b"");
+        ring::constant_time::verify_slices_are_equal(given, secret.as_bytes())
+            .map_err(|_| ServiceError::Unauthorized(failure::err_msg("Invalid internal secret")))
+    }
+
     async fn authorize(
         &self,
         req: web::HttpRequest,
         validate_user_role: Option<model::Role>,
-    ) -> Result<model::User, error::Error> {
+    ) -> Result<model::User, ServiceError> {
         let token = WebContext::auth_token(req)
-            .ok_or(ServiceError::Unauthorized(failure::err_msg("Empty token")).to_http_error())?;
+            .ok_or_else(|| ServiceError::Unauthorized(failure::err_msg("Empty token")))?;
         let stoken = token.split("Bearer ").collect::<Vec<&str>>();
         if stoken.len() != 2 {
-            return Err(
-                ServiceError::Unauthorized(failure::err_msg("Invalid token")).to_http_error(),
-            );
+            return Err(ServiceError::Unauthorized(failure::err_msg(
+                "Invalid token",
+            )));
         }
 
         let user = self
@@ -38,15 +63,13 @@ impl WebContext {
             .services
             .login_service
             .authorize(stoken[1].to_owned())
-            .await
-            .map_err(|e| e.to_http_error())?;
+            .await?;
 
         if let Some(check_role) = validate_user_role {
             if user.role < check_role {
-                return Err(
-                    ServiceError::Unauthorized(failure::err_msg("Role is not enough"))
-                        .to_http_error(),
-                );
+                return Err(ServiceError::Unauthorized(failure::err_msg(
+                    "Role is not enough",
+                )));
             }
         }
 
@@ -54,30 +77,130 @@ impl WebContext {
     }
 }
 
+async fn parse_body<T: serde::de::DeserializeOwned>(
+    payload: web::Payload,
+) -> Result<T, ServiceError> {
+    let body = futures::compat::Compat01As03::new(payload.concat2())
+        .await
+        .map_err(|err| {
+            warn!("Unreadable body: {}", err);
+            ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(failure::err_msg(
+                "the body could not be read",
+            ))))
+        })?;
+
+    serde_json::from_slice::<T>(body.as_ref()).map_err(ServiceError::ParseError)
+}
+
+// longer user agents are cut, to bound the size of audit entries
+const MAX_USER_AGENT_LEN: usize = 512;
+
+// Who the audit entries of the request are for; `user` is None before login
+fn actor(req: &web::HttpRequest, user: Option<&model::User>) -> model::Actor {
+    model::Actor {
+        user_id: user.map(|u| u.id.clone()),
+        ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        user_agent: req
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(MAX_USER_AGENT_LEN).collect()),
+    }
+}
+
+// Malformed paths and query strings are answered with problem+json like the other errors,
+// instead of the extractors' plain text
+fn invalid_extraction(err: impl std::fmt::Display) -> actix_web::Error {
+    warn!("Malformed path or query string: {}", err);
+    ServiceError::InvalidRequest(Box::new(ServiceError::GeneralError(failure::err_msg(
+        "malformed path or query string",
+    ))))
+    .into()
+}
+
+// web::resource with the error handlers of the extractors
+fn resource(path: &str) -> actix_web::Resource {
+    web::resource(path)
+        .data(web::QueryConfig::default().error_handler(|err, _| invalid_extraction(err)))
+        .data(web::PathConfig::default().error_handler(|err, _| invalid_extraction(err)))
+}
+
 pub fn handlers(cfg: &mut web::ServiceConfig) {
     cfg.service(
-        web::resource("/admin/users")
-            .route(web::get().to_async(async_await::wrap2(api_list_users)))
+        resource("/admin/users")
+            .route(web::get().to_async(async_await::wrap3(api_list_users)))
             .route(web::post().to_async(async_await::wrap3(api_create_user))),
     )
     .service(
-        web::resource("/auth/login")
-            .route(web::post().to_async(async_await::wrap2(api_auth_login))),
+        resource("/admin/users/{user_id}")
+            .route(web::get().to_async(async_await::wrap3(api_get_user)))
+            .route(web::patch().to_async(async_await::wrap4(api_update_user)))
+            .route(web::delete().to_async(async_await::wrap4(api_delete_user))),
     )
     .service(
-        web::resource("/private/login/{user_id}")
-            .route(web::put().to_async(async_await::wrap2(private_api_enable_user_with_password))),
+        resource("/admin/users/{user_id}/sessions")
+            .route(web::get().to_async(async_await::wrap4(api_list_user_sessions))),
     )
     .service(
-        web::resource("/perf/non_blocking")
-            .route(web::get().to_async(async_await::wrap2(api_non_blocking))),
-    );
+        resource("/admin/users/{user_id}/sessions/{session_id}")
+            .route(web::delete().to_async(async_await::wrap3(api_revoke_user_session))),
+    )
+    .service(
+        resource("/admin/audit").route(web::get().to_async(async_await::wrap3(api_list_audit))),
+    )
+    .service(
+        resource("/admin/audit/export")
+            .route(web::get().to_async(async_await::wrap3(api_export_audit))),
+    )
+    .service(
+        resource("/admin/webhooks")
+            .route(web::get().to_async(async_await::wrap2(api_list_webhooks)))
+            .route(web::post().to_async(async_await::wrap3(api_create_webhook))),
+    )
+    .service(
+        resource("/admin/webhooks/{webhook_id}")
+            .route(web::delete().to_async(async_await::wrap3(api_delete_webhook))),
+    )
+    .service(
+        resource("/admin/webhooks/{webhook_id}/deliveries")
+            .route(web::get().to_async(async_await::wrap4(api_list_webhook_deliveries))),
+    )
+    .service(
+        resource("/admin/webhooks/{webhook_id}/deliveries/{delivery_id}/retry")
+            .route(web::post().to_async(async_await::wrap3(api_retry_webhook_delivery))),
+    )
+    .service(resource("/me").route(web::get().to_async(async_await::wrap2(api_get_me))))
+    .service(
+        resource("/me/sessions")
+            .route(web::get().to_async(async_await::wrap3(api_list_my_sessions))),
+    )
+    .service(
+        resource("/me/sessions/{session_id}")
+            .route(web::delete().to_async(async_await::wrap3(api_revoke_my_session))),
+    )
+    .service(
+        resource("/auth/login").route(web::post().to_async(async_await::wrap3(api_auth_login))),
+    )
+    .service(
+        resource("/auth/signup").route(web::post().to_async(async_await::wrap2(api_auth_signup))),
+    )
+    .service(
+        resource("/auth/signup/resend")
+            .route(web::post().to_async(async_await::wrap2(api_auth_signup_resend))),
+    )
+    .service(
+        resource("/auth/verify")
+            .route(web::get().to_async(async_await::wrap2(api_auth_verify_link)))
+            .route(web::post().to_async(async_await::wrap2(api_auth_verify))),
+    )
+    .service(resource("/health").route(web::get().to_async(async_await::wrap2(api_health))));
 }
 
 async fn api_list_users(
+    query: web::Query<crate::domain::service::UserListInput>,
     context: web::Data<WebContext>,
     req: web::HttpRequest,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServiceError> {
     context
         .get_ref()
         .authorize(req, Some(model::Role::PowerUser))
@@ -87,9 +210,8 @@ async fn api_list_users(
         .app
         .services
         .user_service
-        .list()
-        .await
-        .map_err(|e| e.to_http_error())?;
+        .list(query.into_inner())
+        .await?;
 
     Ok(Response::Ok().json(res))
 }
@@ -98,91 +220,541 @@ async fn api_create_user(
     payload: web::Payload,
     context: web::Data<WebContext>,
     req: web::HttpRequest,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServiceError> {
+    let admin = context
+        .get_ref()
+        .authorize(req.clone(), Some(model::Role::Admin))
+        .await?;
+
+    let input = parse_body::<crate::domain::service::UserCreateInput>(payload).await?;
+
+    let user = context
+        .app
+        .services
+        .user_service
+        .create(actor(&req, Some(&admin)), input)
+        .await?;
+
+    Ok(Response::Created()
+        .header(
+            actix_web::http::header::LOCATION,
+            format!("/admin/users/{}", user.id),
+        )
+        .json(user))
+}
+
+async fn api_get_user(
+    path: web::Path<String>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
     context
         .get_ref()
-        .authorize(req, Some(model::Role::Admin))
+        .authorize(req, Some(model::Role::PowerUser))
         .await?;
 
-    let body = Box::new(
-        futures::compat::Compat01As03::new(payload.concat2())
-            .await
-            .map_err(error::ErrorBadRequest)?,
-    );
-    let input = serde_json::from_slice::<crate::domain::service::UserCreateInput>(body.as_ref())
-        .map_err(error::ErrorBadRequest)?;
+    let res = context
+        .app
+        .services
+        .user_service
+        .get(path.into_inner())
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_update_user(
+    path: web::Path<String>,
+    payload: web::Payload,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin = context
+        .get_ref()
+        .authorize(req.clone(), Some(model::Role::Admin))
+        .await?;
+
+    let input = parse_body::<crate::domain::service::UserUpdateInput>(payload).await?;
+
+    let res = context
+        .app
+        .services
+        .user_service
+        .update(actor(&req, Some(&admin)), path.into_inner(), input)
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+#[derive(serde::Deserialize)]
+struct DeleteUserQuery {
+    #[serde(default)]
+    purge: bool,
+}
+
+async fn api_delete_user(
+    path: web::Path<String>,
+    query: web::Query<DeleteUserQuery>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin = context
+        .get_ref()
+        .authorize(req.clone(), Some(model::Role::Admin))
+        .await?;
 
     context
         .app
         .services
         .user_service
-        .create(input)
-        .await
-        .map_err(|e| e.to_http_error())?;
+        .delete(actor(&req, Some(&admin)), path.into_inner(), query.purge)
+        .await?;
+
+    Ok(Response::NoContent().finish())
+}
+
+async fn api_list_user_sessions(
+    path: web::Path<String>,
+    query: web::Query<crate::domain::service::SessionListInput>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let res = context
+        .app
+        .services
+        .session_service
+        .list(path.into_inner(), query.into_inner())
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_revoke_user_session(
+    path: web::Path<(String, String)>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let admin = context
+        .get_ref()
+        .authorize(req.clone(), Some(model::Role::Admin))
+        .await?;
+
+    let (user_id, session_id) = path.into_inner();
+    context
+        .app
+        .services
+        .session_service
+        .revoke(actor(&req, Some(&admin)), user_id, session_id)
+        .await?;
+
+    Ok(Response::NoContent().finish())
+}
+
+async fn api_list_audit(
+    query: web::Query<crate::domain::service::AuditListInput>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let res = context
+        .app
+        .services
+        .audit_service
+        .list(query.into_inner())
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+// One JSON entry per line, newest first; streamed, since it may be the whole log
+async fn api_export_audit(
+    query: web::Query<crate::domain::service::AuditListInput>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    use futures::{StreamExt, TryStreamExt};
+
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let batches = context
+        .app
+        .services
+        .audit_service
+        .export(query.into_inner())?;
+    let lines = batches.map(|batch| {
+        let mut buf = Vec::new();
+        for entry in batch? {
+            serde_json::to_writer(&mut buf, &entry).map_err(ServiceError::ParseError)?;
+            buf.push(b'\n');
+        }
+        Ok::<_, ServiceError>(web::Bytes::from(buf))
+    });
+
+    Ok(Response::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(lines.boxed_local().compat()))
+}
+
+async fn api_list_webhooks(
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let res = context
+        .app
+        .services
+        .webhook_service
+        .list_endpoints()
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_create_webhook(
+    payload: web::Payload,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let input = parse_body::<crate::domain::service::WebhookEndpointInput>(payload).await?;
+
+    let endpoint = context
+        .app
+        .services
+        .webhook_service
+        .create_endpoint(input)
+        .await?;
+
+    Ok(Response::Created()
+        .header(
+            actix_web::http::header::LOCATION,
+            format!("/admin/webhooks/{}", endpoint.id),
+        )
+        .json(endpoint))
+}
+
+async fn api_delete_webhook(
+    path: web::Path<String>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    context
+        .app
+        .services
+        .webhook_service
+        .delete_endpoint(path.into_inner())
+        .await?;
+
+    Ok(Response::NoContent().finish())
+}
+
+async fn api_list_webhook_deliveries(
+    path: web::Path<String>,
+    query: web::Query<crate::domain::service::WebhookDeliveryListInput>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let res = context
+        .app
+        .services
+        .webhook_service
+        .list_deliveries(path.into_inner(), query.into_inner())
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_retry_webhook_delivery(
+    path: web::Path<(String, String)>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context
+        .get_ref()
+        .authorize(req, Some(model::Role::Admin))
+        .await?;
+
+    let (webhook_id, delivery_id) = path.into_inner();
+    let res = context
+        .app
+        .services
+        .webhook_service
+        .retry_delivery(webhook_id, delivery_id)
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_get_me(
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user = context.get_ref().authorize(req, None).await?;
+
+    // the token may be older than the latest profile change
+    let res = context.app.services.user_service.get(user.id).await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_list_my_sessions(
+    query: web::Query<crate::domain::service::SessionListInput>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user = context.get_ref().authorize(req, None).await?;
+
+    let res = context
+        .app
+        .services
+        .session_service
+        .list(user.id, query.into_inner())
+        .await?;
+
+    Ok(Response::Ok().json(res))
+}
+
+async fn api_revoke_my_session(
+    path: web::Path<String>,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user = context.get_ref().authorize(req.clone(), None).await?;
+
+    context
+        .app
+        .services
+        .session_service
+        .revoke(actor(&req, Some(&user)), user.id.clone(), path.into_inner())
+        .await?;
 
-    Ok(Response::Created().finish())
+    Ok(Response::NoContent().finish())
 }
 
 async fn api_auth_login(
     payload: web::Payload,
     context: web::Data<WebContext>,
-) -> Result<HttpResponse, error::Error> {
-    let body = Box::new(
-        futures::compat::Compat01As03::new(payload.concat2())
-            .await
-            .map_err(error::ErrorBadRequest)?,
-    );
-    let input = serde_json::from_slice::<crate::domain::service::AuthenticateInput>(body.as_ref())
-        .map_err(error::ErrorBadRequest)?;
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let input = parse_body::<crate::domain::service::AuthenticateInput>(payload).await?;
 
     let res = context
         .app
         .services
         .login_service
-        .authenticate(input)
-        .await
-        .map_err(|e| e.to_http_error())?;
+        .authenticate(actor(&req, None), input)
+        .await?;
 
     Ok(Response::Ok().json(res))
 }
 
-async fn private_api_enable_user_with_password(
+async fn api_auth_signup(
     payload: web::Payload,
     context: web::Data<WebContext>,
-) -> Result<HttpResponse, error::Error> {
-    let body = Box::new(
-        futures::compat::Compat01As03::new(payload.concat2())
-            .await
-            .map_err(error::ErrorBadRequest)?,
-    );
-    let input = serde_json::from_slice::<crate::domain::service::EnableUserWithPasswordInput>(
-        body.as_ref(),
+) -> Result<HttpResponse, ServiceError> {
+    if !context.app.services.signup_service.enabled() {
+        return Err(ServiceError::NotFound(failure::err_msg(
+            "signup is disabled",
+        )));
+    }
+
+    let input = parse_body::<crate::domain::service::SignupInput>(payload).await?;
+
+    let res = context.app.services.signup_service.signup(input).await?;
+
+    Ok(Response::Created().json(res))
+}
+
+async fn api_auth_signup_resend(
+    payload: web::Payload,
+    context: web::Data<WebContext>,
+) -> Result<HttpResponse, ServiceError> {
+    if !context.app.services.signup_service.enabled() {
+        return Err(ServiceError::NotFound(failure::err_msg(
+            "signup is disabled",
+        )));
+    }
+
+    let input = parse_body::<crate::domain::service::ResendVerificationInput>(payload).await?;
+
+    context.app.services.signup_service.resend(input).await?;
+
+    Ok(Response::Accepted().finish())
+}
+
+async fn api_auth_verify(
+    payload: web::Payload,
+    context: web::Data<WebContext>,
+) -> Result<HttpResponse, ServiceError> {
+    if !context.app.services.signup_service.enabled() {
+        return Err(ServiceError::NotFound(failure::err_msg(
+            "signup is disabled",
+        )));
+    }
+
+    let input = parse_body::<crate::domain::service::VerifyEmailInput>(payload).await?;
+
+    context.app.services.signup_service.verify(input).await?;
+
+    Ok(Response::NoContent().finish())
+}
+
+// The link of the verification mail (see config::SignupConfig), opened in a browser
+async fn api_auth_verify_link(
+    query: web::Query<crate::domain::service::VerifyEmailInput>,
+    context: web::Data<WebContext>,
+) -> Result<HttpResponse, ServiceError> {
+    if !context.app.services.signup_service.enabled() {
+        return Err(ServiceError::NotFound(failure::err_msg(
+            "signup is disabled",
+        )));
+    }
+
+    context
+        .app
+        .services
+        .signup_service
+        .verify(query.into_inner())
+        .await?;
+
+    Ok(Response::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body("Your email address is verified, you can now log in.\n"))
+}
+
+// Served only by the private listener (see config::PrivateConfig)
+pub fn private_handlers(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        resource("/private/login/{user_id}")
+            .route(web::put().to_async(async_await::wrap4(private_api_enable_user_with_password))),
+    )
+    // they hold a connection for as long as the client wants, so not on the public listener
+    .service(
+        resource("/private/perf/non_blocking")
+            .route(web::get().to_async(async_await::wrap2(api_non_blocking))),
     )
-    .map_err(error::ErrorBadRequest)?;
+    .service(
+        resource("/private/perf/query").route(web::get().to_async(async_await::wrap2(api_query))),
+    );
+}
+
+async fn private_api_enable_user_with_password(
+    path: web::Path<String>,
+    payload: web::Payload,
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context.get_ref().authorize_internal(&req)?;
+
+    let input = parse_body::<crate::domain::service::EnableUserWithPasswordInput>(payload).await?;
 
     let res = context
         .app
         .services
         .login_service
-        .enable_user_with_password(input)
-        .await
-        .map_err(|e| e.to_http_error())?;
+        .enable_user_with_password(actor(&req, None), path.into_inner(), input)
+        .await?;
 
     Ok(Response::Ok().json(res))
 }
 
-async fn api_non_blocking(
+#[derive(serde::Serialize)]
+struct HealthResponse {
+    // absent without DATABASE_REPLICA_URL
+    replica: Option<infra::ReplicaStatus>,
+    // absent without USER_CACHE_TTL_SECS
+    user_cache: Option<serviceclient::cache::UserCacheStats>,
+}
+
+// Fails while the primary is unreachable; a lagging replica only shows up in the body,
+// since reads fail over to the primary
+async fn api_health(
     _payload: web::Payload,
     context: web::Data<WebContext>,
-) -> Result<HttpResponse, error::Error> {
+) -> Result<HttpResponse, ServiceError> {
+    let db = &context.app.infras.db;
+    db.sql_query("SELECT 1")
+        .await
+        .map_err(ServiceError::DBError)?;
+
+    Ok(Response::Ok().json(HealthResponse {
+        replica: db.replica_status(),
+        user_cache: context.app.infras.user_cache.as_ref().map(|c| c.stats()),
+    }))
+}
+
+// takes about 3s on the server
+#[cfg(feature = "mysql")]
+const SLEEP_QUERY: &str = "SELECT sleep(3)";
+#[cfg(feature = "postgres")]
+const SLEEP_QUERY: &str = "SELECT pg_sleep(3)";
+// SQLite has no sleep, this counts instead (the time depends on the machine)
+#[cfg(feature = "sqlite")]
+const SLEEP_QUERY: &str =
+    "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 10000000) \
+     SELECT count(*) FROM n";
+
+async fn api_non_blocking(
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context.get_ref().authorize_internal(&req)?;
+
+    context
+        .app
+        .infras
+        .db
+        .sql_query(SLEEP_QUERY)
+        .await
+        .map_err(ServiceError::DBError)?;
+
+    Ok(Response::Ok().finish())
+}
+
+// Round trip of the cheapest query, for measuring the overhead of the DB path (see bench/perf.sh)
+async fn api_query(
+    context: web::Data<WebContext>,
+    req: web::HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    context.get_ref().authorize_internal(&req)?;
+
     context
         .app
         .infras
         .db
-        .sql_query("SELECT sleep(3)")
+        .sql_query("SELECT 1")
         .await
-        .map_err(ServiceError::DBError)
-        .map_err(|e| e.to_http_error())?;
+        .map_err(ServiceError::DBError)?;
 
     Ok(Response::Ok().finish())
 }
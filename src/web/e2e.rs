@@ -0,0 +1,606 @@
+// Requests through web::handlers, on an AppContext built like main does
+// The cases run on the in-memory repositories, and on the test database (see the tests at the bottom)
+use super::{handlers, Tracing, WebContext};
+use crate::config;
+use crate::domain::interface::{IHashManager, IJWTHandler};
+use crate::domain::model;
+use crate::infra;
+use crate::initializer;
+use crate::serviceclient;
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+use actix_web::App;
+use serde_json::json;
+
+// An ES384 key in a new temp file, as JWT_PRIVATE_KEY_FILE; removed once it is read
+fn generate_key_file() -> String {
+    use ring::signature::{EcdsaKeyPair, ECDSA_P384_SHA384_FIXED_SIGNING};
+
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+        &ECDSA_P384_SHA384_FIXED_SIGNING,
+        &ring::rand::SystemRandom::new(),
+    )
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("rustapp-test-{}.der", ulid::Ulid::new()));
+    std::fs::write(&path, pkcs8.as_ref()).unwrap();
+
+    path.to_string_lossy().into_owned()
+}
+
+fn test_config(repository: config::RepositoryKind) -> config::Config {
+    config::Config {
+        bind: "127.0.0.1:0".to_owned(),
+        private: None,
+        repository: repository,
+        user_cache: None,
+        database_url: infra::UNREACHABLE_DATABASE_URL.to_owned(),
+        pool: serviceclient::test_pool_config(),
+        replica: None,
+        private_key_file: generate_key_file(),
+        kv_store_url: None,
+        mail: config::MailConfig {
+            transport: config::MailTransport::File(
+                std::env::temp_dir().to_string_lossy().into_owned(),
+            ),
+            from: "noreply@example.com".to_owned(),
+        },
+        signup: config::SignupConfig {
+            enabled: false,
+            verification_url: "http://localhost:8080/auth/verify".to_owned(),
+            token_ttl_hours: 24,
+        },
+        events: config::EventsConfig {
+            sink: None,
+            poll_interval: std::time::Duration::from_secs(1),
+            max_attempts: 10,
+            // the receivers of the tests, see infra::start_http_stand_in
+            webhook_allowed_hosts: vec!["127.0.0.1".to_owned()],
+        },
+    }
+}
+
+struct Reply {
+    status: StatusCode,
+    location: Option<String>,
+    request_id: Option<String>,
+    // Null when there is no body, or it is not JSON
+    body: serde_json::Value,
+    text: String,
+}
+
+struct Harness {
+    context: WebContext,
+}
+
+impl Harness {
+    // the database is never reached
+    fn memory() -> Harness {
+        Harness::memory_with(test_config(config::RepositoryKind::Memory))
+    }
+
+    fn cached() -> Harness {
+        let mut config = test_config(config::RepositoryKind::Memory);
+        config.user_cache = Some(config::CacheConfig {
+            ttl: std::time::Duration::from_secs(60),
+            max_entries: 100,
+        });
+
+        Harness::memory_with(config)
+    }
+
+    fn memory_with(config: config::Config) -> Harness {
+        let pool = infra::DBConnPool::new_lazy(config.database_url.clone(), &config.pool);
+        let executor = infra::DBExecutor::start(pool, None, &config.pool);
+
+        Harness::new(&config, executor)
+    }
+
+    fn sql() -> Harness {
+        let config = test_config(config::RepositoryKind::Sql);
+
+        Harness::new(&config, serviceclient::test_executor())
+    }
+
+    fn new(config: &config::Config, executor: infra::DBExecutor) -> Harness {
+        let context = WebContext {
+            app: initializer::new(config, &initializer::Shared::new(config, executor).unwrap()),
+            internal_secret: None,
+        };
+        // read by initializer::new, not needed afterwards
+        std::fs::remove_file(&config.private_key_file).unwrap();
+
+        Harness { context: context }
+    }
+
+    fn call(&self, req: TestRequest) -> Reply {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Tracing)
+                .data(self.context.clone())
+                .configure(handlers),
+        );
+        let res = test::call_service(&mut app, req.to_request());
+
+        let status = res.status();
+        let get_header = |name: header::HeaderName| {
+            res.headers()
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_owned())
+        };
+        let location = get_header(header::LOCATION);
+        let request_id = get_header(header::HeaderName::from_static("x-request-id"));
+        let body = test::read_body(res);
+
+        Reply {
+            status: status,
+            location: location,
+            request_id: request_id,
+            body: serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+            text: String::from_utf8(body.to_vec()).unwrap(),
+        }
+    }
+
+    // a user with the given role, who can log in with "password"
+    fn seed_user(&self, role: model::Role) -> model::User {
+        use futures::{FutureExt, TryFutureExt};
+
+        let app = self.context.app.clone();
+        let user = model::User {
+            id: ulid::Ulid::new().to_string(),
+            name: format!("e2e-{}", ulid::Ulid::new()),
+            display_name: "e2e".to_owned(),
+            role: role,
+            email: None,
+        };
+        let login = model::Login {
+            user_id: user.id.clone(),
+            password_hash: app
+                .infras
+                .hash_manager
+                .hash("password".to_owned())
+                .to_string(),
+            status: model::LoginUserStatus::Enabled,
+            version: 0,
+        };
+
+        let saved = user.clone();
+        let fut = async move {
+            let sc = &app.serviceclients;
+            sc.user_repository.save(saved).await.unwrap();
+            sc.login_repository.save(login).await.unwrap();
+        };
+        test::block_on(Box::pin(fut.map(Ok::<_, ()>)).compat()).unwrap();
+
+        user
+    }
+
+    fn login(&self, user_name: &str, password: &str) -> Reply {
+        self.call(post(
+            "/auth/login",
+            json!({ "user_name": user_name, "password": password }),
+        ))
+    }
+
+    fn token_for(&self, role: model::Role) -> String {
+        let user = self.seed_user(role);
+        let reply = self.login(&user.name, "password");
+        assert_eq!(StatusCode::OK, reply.status);
+
+        reply.body.as_str().unwrap().to_owned()
+    }
+}
+
+fn post(uri: &str, body: serde_json::Value) -> TestRequest {
+    TestRequest::post()
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .set_payload(body.to_string())
+}
+
+fn bearer(req: TestRequest, token: &str) -> TestRequest {
+    req.header(header::AUTHORIZATION, format!("Bearer {}", token))
+}
+
+fn assert_problem(reply: &Reply, status: StatusCode, code: &str) {
+    assert_eq!(status, reply.status);
+    assert_eq!(code, reply.body["code"]);
+}
+
+fn login_should_issue_a_token_for_me(h: &Harness) {
+    let user = h.seed_user(model::Role::User);
+
+    let reply = h.login(&user.name, "password");
+    assert_eq!(StatusCode::OK, reply.status);
+    let token = reply.body.as_str().unwrap();
+
+    let me = h.call(bearer(TestRequest::get().uri("/me"), token));
+    assert_eq!(StatusCode::OK, me.status);
+    assert_eq!(user.id, me.body["id"]);
+    assert_eq!("user", me.body["role"]);
+
+    // the same answer whether the user exists or not
+    let wrong = h.login(&user.name, "wrong");
+    assert_problem(&wrong, StatusCode::BAD_REQUEST, "invalid_request");
+    let missing = h.login("nobody", "password");
+    assert_problem(&missing, StatusCode::BAD_REQUEST, "invalid_request");
+}
+
+fn admin_routes_should_check_the_role(h: &Harness) {
+    let list = || TestRequest::get().uri("/admin/users");
+    let create = || post("/admin/users", json!({ "name": "x", "display_name": "x" }));
+
+    let user = h.token_for(model::Role::User);
+    let reply = h.call(bearer(list(), &user));
+    assert_problem(&reply, StatusCode::UNAUTHORIZED, "unauthorized");
+
+    let power_user = h.token_for(model::Role::PowerUser);
+    let reply = h.call(bearer(list(), &power_user));
+    assert_eq!(StatusCode::OK, reply.status);
+    assert!(reply.body["items"].is_array());
+    let reply = h.call(bearer(create(), &power_user));
+    assert_problem(&reply, StatusCode::UNAUTHORIZED, "unauthorized");
+
+    let typo = TestRequest::get().uri("/admin/users?role=adminn");
+    let reply = h.call(bearer(typo, &power_user));
+    assert_problem(&reply, StatusCode::BAD_REQUEST, "invalid_request");
+}
+
+fn admin_should_create_users(h: &Harness) {
+    let admin = h.token_for(model::Role::Admin);
+    let name = format!("created-{}", ulid::Ulid::new());
+    let input = json!({ "name": name, "display_name": "Created", "password": "secret" });
+
+    let created = h.call(bearer(post("/admin/users", input.clone()), &admin));
+    assert_eq!(StatusCode::CREATED, created.status);
+    let id = created.body["id"].as_str().unwrap();
+    let location = format!("/admin/users/{}", id);
+    assert_eq!(Some(&location), created.location.as_ref());
+
+    let found = h.call(bearer(TestRequest::get().uri(&location), &admin));
+    assert_eq!(StatusCode::OK, found.status);
+    assert_eq!(name, found.body["name"]);
+    assert_eq!("unknown", found.body["role"]);
+
+    // the login was created with the user
+    assert_eq!(StatusCode::OK, h.login(&name, "secret").status);
+
+    let taken = h.call(bearer(post("/admin/users", input), &admin));
+    assert_problem(&taken, StatusCode::CONFLICT, "user_name_taken");
+}
+
+fn malformed_bodies_should_be_rejected(h: &Harness) {
+    let admin = h.token_for(model::Role::Admin);
+
+    let not_json = TestRequest::post()
+        .uri("/auth/login")
+        .set_payload("user_name=admin");
+    assert_problem(&h.call(not_json), StatusCode::BAD_REQUEST, "invalid_body");
+
+    let missing_field = post("/auth/login", json!({ "user_name": "admin" }));
+    assert_problem(
+        &h.call(missing_field),
+        StatusCode::BAD_REQUEST,
+        "invalid_body",
+    );
+
+    let wrong_type = post("/admin/users", json!({ "name": 1, "display_name": "x" }));
+    assert_problem(
+        &h.call(bearer(wrong_type, &admin)),
+        StatusCode::BAD_REQUEST,
+        "invalid_body",
+    );
+
+    // the query extractor answers like the handlers
+    let not_a_number = TestRequest::get().uri("/admin/users?limit=ten");
+    assert_problem(
+        &h.call(bearer(not_a_number, &admin)),
+        StatusCode::BAD_REQUEST,
+        "invalid_request",
+    );
+}
+
+fn request_ids_should_not_come_from_the_client(h: &Harness) {
+    let trace_id = "0af7651916cd43dd8448eb211c80319c";
+    let traced = || {
+        TestRequest::get().uri("/me").header(
+            "traceparent",
+            format!("00-{}-b7ad6b7169203331-01", trace_id),
+        )
+    };
+
+    let first = h.call(traced());
+    assert_problem(&first, StatusCode::UNAUTHORIZED, "unauthorized");
+    let request_id = first.request_id.clone().unwrap();
+    assert_eq!(request_id, first.body["request_id"]);
+    assert_ne!(trace_id, request_id);
+    assert_ne!(first.request_id, h.call(traced()).request_id);
+}
+
+fn bad_tokens_should_be_rejected(h: &Harness) {
+    let me = || TestRequest::get().uri("/me");
+
+    assert_problem(&h.call(me()), StatusCode::UNAUTHORIZED, "unauthorized");
+
+    let basic = me().header(header::AUTHORIZATION, "Basic YWRtaW46YWRtaW4=");
+    assert_problem(&h.call(basic), StatusCode::UNAUTHORIZED, "unauthorized");
+
+    let garbage = bearer(me(), "not.a.token");
+    assert_problem(&h.call(garbage), StatusCode::BAD_REQUEST, "invalid_request");
+
+    // a well-formed token, signed by another key
+    let key_file = generate_key_file();
+    let other_key = infra::JWTHandler::new(&key_file);
+    std::fs::remove_file(&key_file).unwrap();
+    let forged = other_key
+        .sign(model::TokenClaims {
+            sid: ulid::Ulid::new().to_string(),
+            user: model::User {
+                id: ulid::Ulid::new().to_string(),
+                name: "admin".to_owned(),
+                display_name: "admin".to_owned(),
+                role: model::Role::Admin,
+                email: None,
+            },
+        })
+        .unwrap();
+    let reply = h.call(bearer(TestRequest::get().uri("/admin/users"), &forged));
+    assert_problem(&reply, StatusCode::BAD_REQUEST, "invalid_request");
+}
+
+fn changes_should_be_audited(h: &Harness) {
+    let admin = h.seed_user(model::Role::Admin);
+    let token = h
+        .login(&admin.name, "password")
+        .body
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let input = json!({ "name": format!("audited-{}", ulid::Ulid::new()), "display_name": "x" });
+    let created = h.call(bearer(
+        post("/admin/users", input)
+            .header(header::USER_AGENT, "e2e")
+            .peer_addr("10.0.0.1:4000".parse().unwrap()),
+        &token,
+    ));
+    let id = created.body["id"].as_str().unwrap();
+    let update = TestRequest::patch()
+        .uri(&format!("/admin/users/{}", id))
+        .header(header::CONTENT_TYPE, "application/json")
+        .set_payload(json!({ "role": "admin" }).to_string());
+    assert_eq!(StatusCode::OK, h.call(bearer(update, &token)).status);
+
+    let list = |query: &str| {
+        let uri = format!("/admin/audit?target_id={}{}", id, query);
+        h.call(bearer(TestRequest::get().uri(&uri), &token))
+    };
+    let reply = list("");
+    assert_eq!(StatusCode::OK, reply.status);
+    let items = reply.body["items"].as_array().unwrap();
+    assert_eq!(2, items.len());
+    assert_eq!("user_updated", items[0]["action"]);
+    assert_eq!("role=admin", items[0]["detail"]);
+    assert_eq!("user_created", items[1]["action"]);
+    assert_eq!("success", items[1]["outcome"]);
+    assert_eq!(admin.id, items[1]["actor_id"]);
+    assert_eq!("10.0.0.1", items[1]["ip"]);
+    assert_eq!("e2e", items[1]["user_agent"]);
+
+    let first = list("&limit=1");
+    let cursor = first.body["next_cursor"].as_str().unwrap();
+    let second = list(&format!("&limit=1&cursor={}", cursor));
+    assert_eq!("user_created", second.body["items"][0]["action"]);
+    assert!(second.body["next_cursor"].is_null());
+    assert_problem(
+        &list("&action=exploded"),
+        StatusCode::BAD_REQUEST,
+        "invalid_request",
+    );
+
+    // failed logins are recorded against the user
+    h.login(&admin.name, "wrong");
+    let uri = format!("/admin/audit/export?target_id={}&outcome=failure", admin.id);
+    let export = h.call(bearer(TestRequest::get().uri(&uri), &token));
+    assert_eq!(StatusCode::OK, export.status);
+    let lines = export.text.lines().collect::<Vec<_>>();
+    assert_eq!(1, lines.len());
+    let entry = serde_json::from_str::<serde_json::Value>(lines[0]).unwrap();
+    assert_eq!("login_failed", entry["action"]);
+
+    // only admins may read the log
+    let power_user = h.token_for(model::Role::PowerUser);
+    let reply = h.call(bearer(TestRequest::get().uri("/admin/audit"), &power_user));
+    assert_problem(&reply, StatusCode::UNAUTHORIZED, "unauthorized");
+}
+
+fn sessions_should_be_revocable(h: &Harness) {
+    let user = h.seed_user(model::Role::User);
+    let login_from = |agent: &str| {
+        let req = post(
+            "/auth/login",
+            json!({ "user_name": user.name, "password": "password" }),
+        );
+        let reply = h.call(req.header(header::USER_AGENT, agent));
+        reply.body.as_str().unwrap().to_owned()
+    };
+    h.login(&user.name, "wrong");
+    let laptop = login_from("laptop");
+    let phone = login_from("phone");
+
+    let me = |token: &str| h.call(bearer(TestRequest::get().uri("/me"), token)).status;
+    let sessions = h.call(bearer(TestRequest::get().uri("/me/sessions"), &laptop));
+    assert_eq!(StatusCode::OK, sessions.status);
+    let items = sessions.body["items"].as_array().unwrap();
+    assert_eq!(3, items.len());
+    let id_of = |agent: &str| {
+        let session = items.iter().find(|s| s["user_agent"] == agent).unwrap();
+        session["id"].as_str().unwrap().to_owned()
+    };
+    let failed = items.iter().find(|s| s["succeeded"] == false).unwrap();
+    assert!(failed["user_agent"].is_null());
+
+    // one session is revoked, not the others
+    let uri = format!("/me/sessions/{}", id_of("phone"));
+    let revoked = h.call(bearer(TestRequest::delete().uri(&uri), &laptop));
+    assert_eq!(StatusCode::NO_CONTENT, revoked.status);
+    assert_eq!(StatusCode::UNAUTHORIZED, me(&phone));
+    assert_eq!(StatusCode::OK, me(&laptop));
+
+    // the sessions of others are not found
+    let other = h.token_for(model::Role::User);
+    let uri = format!("/me/sessions/{}", id_of("laptop"));
+    let reply = h.call(bearer(TestRequest::delete().uri(&uri), &other));
+    assert_problem(&reply, StatusCode::NOT_FOUND, "not_found");
+
+    let admin = h.token_for(model::Role::Admin);
+    let uri = format!("/admin/users/{}/sessions?limit=2", user.id);
+    let listed = h.call(bearer(TestRequest::get().uri(&uri), &admin));
+    assert_eq!(2, listed.body["items"].as_array().unwrap().len());
+    assert!(listed.body["next_cursor"].is_string());
+    let uri = format!("/admin/users/{}/sessions/{}", user.id, id_of("laptop"));
+    let revoked = h.call(bearer(TestRequest::delete().uri(&uri), &admin));
+    assert_eq!(StatusCode::NO_CONTENT, revoked.status);
+    assert_eq!(StatusCode::UNAUTHORIZED, me(&laptop));
+
+    // only admins may revoke the sessions of others
+    let reply = h.call(bearer(TestRequest::delete().uri(&uri), &other));
+    assert_problem(&reply, StatusCode::UNAUTHORIZED, "unauthorized");
+}
+
+fn webhooks_should_receive_user_changes(h: &Harness) {
+    use futures::{FutureExt, TryFutureExt};
+
+    let admin = h.token_for(model::Role::Admin);
+    let (url, requests) = infra::start_http_stand_in(vec![]);
+
+    let input = json!({ "url": url, "secret": "s3cret", "event_types": ["user_created"] });
+    let created = h.call(bearer(post("/admin/webhooks", input), &admin));
+    assert_eq!(StatusCode::CREATED, created.status);
+    assert!(created.body.get("secret").is_none());
+    let webhook_id = created.body["id"].as_str().unwrap().to_owned();
+    let unknown = json!({ "url": url, "secret": "s3cret", "event_types": ["user_renamed"] });
+    let reply = h.call(bearer(post("/admin/webhooks", unknown), &admin));
+    assert_problem(&reply, StatusCode::BAD_REQUEST, "invalid_request");
+    // only the stand-in's host is allowed to be internal
+    let metadata = json!({
+        "url": "http://169.254.169.254/latest/meta-data/",
+        "secret": "s3cret",
+        "event_types": ["user_created"],
+    });
+    let reply = h.call(bearer(post("/admin/webhooks", metadata), &admin));
+    assert_problem(&reply, StatusCode::BAD_REQUEST, "invalid_request");
+
+    let name = format!("hooked-{}", ulid::Ulid::new());
+    let input = json!({ "name": name, "display_name": "Hooked" });
+    let user = h.call(bearer(post("/admin/users", input), &admin));
+    let user_id = user.body["id"].as_str().unwrap().to_owned();
+
+    // what the background loop of main does, until nothing is due
+    let app = h.context.app.clone();
+    let fut = async move {
+        let dispatcher = crate::domain::service::EventDispatcher::new(
+            app.serviceclients.outbox_repository.clone(),
+            std::sync::Arc::new(app.services.webhook_service.clone()),
+            10,
+        );
+        let now = chrono::Utc::now().naive_utc();
+        while dispatcher.dispatch_due(now).await.unwrap() > 0 {}
+        // the deliveries are due from when the dispatch created them
+        let now = chrono::Utc::now().naive_utc();
+        while app.services.webhook_service.deliver_due(now).await.unwrap() > 0 {}
+    };
+    test::block_on(Box::pin(fut.map(Ok::<_, ()>)).compat()).unwrap();
+
+    // the users created by the other cases are sent too
+    let request = loop {
+        let request = requests
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        let body = serde_json::from_slice::<serde_json::Value>(&request.body).unwrap();
+        if body["data"]["user_id"] == user_id.as_str() {
+            assert_eq!(name, body["data"]["name"]);
+            break request;
+        }
+    };
+    assert_eq!("user_created", request.headers["x-event-type"]);
+    assert_eq!(
+        infra::webhook_signature(b"s3cret", &request.body),
+        request.headers["x-signature-256"]
+    );
+
+    let uri = format!("/admin/webhooks/{}/deliveries", webhook_id);
+    let log = h.call(bearer(TestRequest::get().uri(&uri), &admin));
+    let delivery = log.body["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| d["id"] == request.headers["x-delivery-id"].as_str())
+        .unwrap()
+        .clone();
+    assert_eq!("delivered", delivery["status"]);
+    assert_eq!(200, delivery["last_status_code"]);
+
+    let uri = format!("{}/{}/retry", uri, delivery["id"].as_str().unwrap());
+    let retried = h.call(bearer(post(&uri, json!({})), &admin));
+    assert_eq!(StatusCode::OK, retried.status);
+    assert_eq!("pending", retried.body["status"]);
+
+    // only admins manage webhooks
+    let user_token = h.token_for(model::Role::User);
+    let reply = h.call(bearer(
+        TestRequest::get().uri("/admin/webhooks"),
+        &user_token,
+    ));
+    assert_problem(&reply, StatusCode::UNAUTHORIZED, "unauthorized");
+
+    let uri = format!("/admin/webhooks/{}", webhook_id);
+    let deleted = h.call(bearer(TestRequest::delete().uri(&uri), &admin));
+    assert_eq!(StatusCode::NO_CONTENT, deleted.status);
+    let uri = format!("/admin/webhooks/{}/deliveries", webhook_id);
+    let reply = h.call(bearer(TestRequest::get().uri(&uri), &admin));
+    assert_problem(&reply, StatusCode::NOT_FOUND, "not_found");
+}
+
+// One test per case on each app, so that a failing case does not hide the others
+macro_rules! cases {
+    ($($case:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[test]
+                fn $case() {
+                    super::$case(&super::Harness::memory());
+                }
+            )*
+        }
+
+        mod cached {
+            $(
+                #[test]
+                fn $case() {
+                    super::$case(&super::Harness::cached());
+                }
+            )*
+        }
+
+        mod sql {
+            $(
+                #[test]
+                #[cfg_attr(not(feature = "sqlite"), ignore)]
+                fn $case() {
+                    super::$case(&super::Harness::sql());
+                }
+            )*
+        }
+    };
+}
+
+cases!(
+    login_should_issue_a_token_for_me,
+    admin_routes_should_check_the_role,
+    admin_should_create_users,
+    malformed_bodies_should_be_rejected,
+    request_ids_should_not_come_from_the_client,
+    bad_tokens_should_be_rejected,
+    changes_should_be_audited,
+    sessions_should_be_revocable,
+    webhooks_should_receive_user_changes,
+);
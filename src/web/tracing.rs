@@ -0,0 +1,138 @@
+use crate::infra::{with_span, Span, SpanContext};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures01::future::{ok, FutureResult};
+use futures01::{Async, Future, Poll};
+
+// Opens a root span for each HTTP request
+// (or continues the trace given in the W3C `traceparent` header)
+pub struct Tracing;
+
+impl<S, B> Transform<S> for Tracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracingMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TracingMiddleware { service: service })
+    }
+}
+
+pub struct TracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for TracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = TracedResponse<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let parent = req
+            .headers()
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_traceparent);
+        let mut span = Span::with_parent(format!("HTTP {} {}", req.method(), req.path()), parent);
+        span.set_attribute("http.method", req.method());
+        span.set_attribute("http.target", req.uri());
+        let request_id = span.context().request_id();
+        span.set_attribute("http.request_id", request_id);
+
+        let service = &mut self.service;
+        let inner = span.in_scope(|| service.call(req));
+
+        TracedResponse {
+            inner: inner,
+            span: Some(span),
+        }
+    }
+}
+
+pub struct TracedResponse<F> {
+    inner: F,
+    span: Option<Span>,
+}
+
+impl<F, B> Future for TracedResponse<F>
+where
+    F: Future<Item = ServiceResponse<B>, Error = Error>,
+{
+    type Item = ServiceResponse<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let context = self.span.as_ref().map(|s| s.context());
+        let inner = &mut self.inner;
+        let mut result = with_span(context, || inner.poll());
+
+        match &mut result {
+            Ok(Async::NotReady) => (),
+            Ok(Async::Ready(res)) => {
+                if let Some(mut span) = self.span.take() {
+                    span.set_attribute("http.status_code", res.status().as_u16());
+
+                    // same as `request_id` in error responses
+                    if let Ok(value) = HeaderValue::from_str(&span.context().request_id()) {
+                        res.headers_mut()
+                            .insert(HeaderName::from_static("x-request-id"), value);
+                    }
+                }
+            }
+            Err(err) => {
+                if let Some(mut span) = self.span.take() {
+                    span.set_attribute("error", err);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+// version-trace_id-parent_id-flags
+fn parse_traceparent(header: &str) -> Option<SpanContext> {
+    let parts = header.split('-').collect::<Vec<&str>>();
+    if parts.len() != 4 || parts[1].len() != 32 || parts[2].len() != 16 {
+        return None;
+    }
+
+    Some(SpanContext::remote(
+        u128::from_str_radix(parts[1], 16).ok()?,
+        u64::from_str_radix(parts[2], 16).ok()?,
+    ))
+}
+
+#[test]
+fn it_should_parse_traceparent() {
+    let header = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+    let parent = parse_traceparent(header).unwrap();
+    assert_eq!(0x0af7651916cd43dd8448eb211c80319c, parent.trace_id);
+    assert_eq!(0xb7ad6b7169203331, parent.span_id);
+    // the request id is never taken from the client
+    assert_ne!(
+        parent.request_id,
+        parse_traceparent(header).unwrap().request_id
+    );
+    assert_eq!(parse_traceparent("invalid"), None);
+}